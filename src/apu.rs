@@ -1,4 +1,24 @@
-use cpu;
+// The channels are written register by register after the nesdev wiki, with every return, cast and shift spelled out.
+#![allow(
+    clippy::assign_op_pattern,
+    clippy::manual_is_multiple_of,
+    clippy::needless_range_loop,
+    clippy::needless_return,
+    clippy::new_without_default,
+    clippy::redundant_field_names,
+    clippy::unnecessary_cast,
+    clippy::upper_case_acronyms,
+)]
+
+use blip;
+use blip::BlipBuffer;
+use filter::{FilterChain, FilterProfile};
+use state::{StateError, StateReader, StateWriter};
+
+const CPU_FREQUENCY: f64 = 1789773.0;
+pub const DEFAULT_SAMPLE_RATE: f64 = 44100.0;
+// CPU cycles between making the synthesized samples available, about 0.5ms.
+const AUDIO_FRAME_CYCLES: u32 = 1024;
 
 const TRIANGLE_SEQUENCE_TABLE: [u16; 32] = [
     15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
@@ -40,17 +60,126 @@ fn create_mixer_tnd_table() -> [f32; 203] {
     return table;
 }
 
+// The APU's sound channels, for the mixing controls.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] = [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc];
+
+    fn index(&self) -> usize {
+        match *self {
+            Channel::Pulse1 => 0,
+            Channel::Pulse2 => 1,
+            Channel::Triangle => 2,
+            Channel::Noise => 3,
+            Channel::Dmc => 4,
+        }
+    }
+}
+
+// How a channel is mixed into the output.
+#[derive(Clone, Copy)]
+struct ChannelSettings {
+    muted: bool,
+    soloed: bool,
+    gain: f32,
+    // -1 is all the way left, 1 all the way right. Only used for stereo output.
+    pan: f32,
+}
+
+// One side of the output, or the only one for mono.
+struct Speaker {
+    // The mixed output at the host's sample rate. Every change in the output level is added
+    // to it as it happens, at the CPU cycle it happens on.
+    blip: BlipBuffer,
+    // The output level in the BlipBuffer's fixed point units.
+    level: i32,
+    // The console's analog output stage, run on samples as they're read.
+    filters: FilterChain,
+    // What each channel's level is multiplied by before mixing, in the order of Channel::ALL.
+    weights: [f32; 5],
+    samples: Vec<f32>,
+}
+
+impl Speaker {
+    fn new() -> Speaker {
+        return Speaker{
+            blip: BlipBuffer::new(CPU_FREQUENCY, DEFAULT_SAMPLE_RATE),
+            level: 0,
+            filters: FilterChain::new(FilterProfile::Nes, DEFAULT_SAMPLE_RATE),
+            weights: [1.0; 5],
+            samples: Vec::new(),
+        };
+    }
+
+    // Drop any samples that haven't been read and carry on at `level`, with the filters
+    // settled on it, so the output doesn't jump.
+    fn restart(&mut self, profile: FilterProfile, sample_rate: f64, level: f32) {
+        self.blip.set_rates(CPU_FREQUENCY, sample_rate);
+        self.level = (level * blip::LEVEL_SCALE).round() as i32;
+        self.blip.restart_at(self.level as i64);
+        self.filters = FilterChain::new(profile, sample_rate);
+        self.filters.settle(self.blip.level());
+    }
+
+    // Change the filters, settled on the level they were last given.
+    fn set_filter_profile(&mut self, profile: FilterProfile, sample_rate: f64) {
+        self.filters = FilterChain::new(profile, sample_rate);
+        self.filters.settle(self.blip.level());
+    }
+
+    fn set_level(&mut self, cycle: u32, level: f32) {
+        let level = (level * blip::LEVEL_SCALE).round() as i32;
+        if level != self.level {
+            self.blip.add_delta(cycle, level as i64 - self.level as i64);
+            self.level = level;
+        }
+    }
+
+    // Replace `samples` with the filtered samples that are available.
+    fn read_samples(&mut self) {
+        self.samples.clear();
+        self.blip.read_samples(&mut self.samples);
+        for sample in self.samples.iter_mut() {
+            *sample = self.filters.process(*sample);
+        }
+    }
+}
+
 pub struct APU {
     pulse1: Pulse,
     pulse2: Pulse,
     triangle: Triangle,
     noise: Noise,
     dmc: DMC,
+    // CPU cycles since the frame counter's sequence last restarted.
     frame_counter: u64,
     frame_counter_mode: u8,
     inhibit_irq: bool,
+    frame_interrupt_flag: bool,
+    // A $4017 write restarts the sequence in the mode it wrote a few cycles later.
+    frame_counter_reset_delay: u8,
+    new_frame_counter_mode: u8,
+    // The CPU cycle of the last step.
+    cycle: u64,
     mixer_pulse_table: [f32; 31],
     mixer_tnd_table: [f32; 203],
+    // The right speaker is only used for stereo output.
+    left: Speaker,
+    right: Speaker,
+    stereo: bool,
+    sample_rate: f64,
+    blip_cycle: u32,
+    filter_profile: FilterProfile,
+    // Indexed in the order of Channel::ALL.
+    channel_settings: [ChannelSettings; 5],
 }
 
 impl APU {
@@ -64,8 +193,133 @@ impl APU {
             frame_counter: 0,
             frame_counter_mode: 0,
             inhibit_irq: false,
+            frame_interrupt_flag: false,
+            frame_counter_reset_delay: 0,
+            new_frame_counter_mode: 0,
+            cycle: 0,
             mixer_pulse_table: create_mixer_pulse_table(),
             mixer_tnd_table: create_mixer_tnd_table(),
+            left: Speaker::new(),
+            right: Speaker::new(),
+            stereo: false,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            blip_cycle: 0,
+            filter_profile: FilterProfile::Nes,
+            channel_settings: [ChannelSettings{muted: false, soloed: false, gain: 1.0, pan: 0.0}; 5],
+        }
+    }
+
+    // The rate samples are read at. Samples that haven't been read yet are dropped.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.restart_output();
+    }
+
+    // The filtering applied to samples, NES by default.
+    pub fn set_filter_profile(&mut self, profile: FilterProfile) {
+        self.filter_profile = profile;
+        self.left.set_filter_profile(profile, self.sample_rate);
+        self.right.set_filter_profile(profile, self.sample_rate);
+    }
+
+    // Make `ratio` times as many samples as the sample rate asks for, without dropping any.
+    // A frontend uses this to nudge the rate so its queue of samples neither runs dry nor grows.
+    pub fn set_rate_adjustment(&mut self, ratio: f64) {
+        self.left.blip.adjust_rates(CPU_FREQUENCY, self.sample_rate * ratio);
+        self.right.blip.adjust_rates(CPU_FREQUENCY, self.sample_rate * ratio);
+    }
+
+    // With stereo output the channels are panned, otherwise both sides of a frame are the
+    // same. Samples that haven't been read yet are dropped.
+    pub fn set_stereo(&mut self, stereo: bool) {
+        self.stereo = stereo;
+        self.update_weights();
+        self.restart_output();
+    }
+
+    pub fn stereo(&self) -> bool {
+        return self.stereo;
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.channel_settings[channel.index()].muted = muted;
+        self.update_weights();
+    }
+
+    pub fn channel_muted(&self, channel: Channel) -> bool {
+        return self.channel_settings[channel.index()].muted;
+    }
+
+    // While any channel is soloed only the soloed channels are heard, muted or not.
+    pub fn set_channel_soloed(&mut self, channel: Channel, soloed: bool) {
+        self.channel_settings[channel.index()].soloed = soloed;
+        self.update_weights();
+    }
+
+    pub fn channel_soloed(&self, channel: Channel) -> bool {
+        return self.channel_settings[channel.index()].soloed;
+    }
+
+    // What the channel's level is multiplied by before it's mixed, 1 by default.
+    pub fn set_channel_gain(&mut self, channel: Channel, gain: f32) {
+        self.channel_settings[channel.index()].gain = gain.max(0.0);
+        self.update_weights();
+    }
+
+    pub fn channel_gain(&self, channel: Channel) -> f32 {
+        return self.channel_settings[channel.index()].gain;
+    }
+
+    // From -1 for all the way left to 1 for all the way right, 0 by default.
+    pub fn set_channel_pan(&mut self, channel: Channel, pan: f32) {
+        self.channel_settings[channel.index()].pan = pan.clamp(-1.0, 1.0);
+        self.update_weights();
+    }
+
+    pub fn channel_pan(&self, channel: Channel) -> f32 {
+        return self.channel_settings[channel.index()].pan;
+    }
+
+    // Both speakers carry on from the current mix, the right one isn't kept up to date
+    // while the output is mono.
+    fn restart_output(&mut self) {
+        let levels = self.channel_levels();
+        let left_level = self.mix(&levels, &self.left.weights);
+        let right_level = self.mix(&levels, &self.right.weights);
+        self.left.restart(self.filter_profile, self.sample_rate, left_level);
+        self.right.restart(self.filter_profile, self.sample_rate, right_level);
+        self.blip_cycle = 0;
+    }
+
+    fn update_weights(&mut self) {
+        let any_soloed = self.channel_settings.iter().any(|settings| settings.soloed);
+        for i in 0..5 {
+            let settings = self.channel_settings[i];
+            let audible = if any_soloed { settings.soloed } else { !settings.muted };
+            let gain = if audible { settings.gain } else { 0.0 };
+            if self.stereo {
+                // Panning towards one side turns the other side down.
+                self.left.weights[i] = gain * (1.0 - settings.pan).min(1.0);
+                self.right.weights[i] = gain * (1.0 + settings.pan).min(1.0);
+            } else {
+                self.left.weights[i] = gain;
+                self.right.weights[i] = gain;
+            }
+        }
+    }
+
+    // Move the frames synthesized so far onto the end of `frames`, as (left, right) samples.
+    pub fn read_frames(&mut self, frames: &mut Vec<(f32, f32)>) {
+        self.left.read_samples();
+        if !self.stereo {
+            for sample in self.left.samples.iter() {
+                frames.push((*sample, *sample));
+            }
+            return;
+        }
+        self.right.read_samples();
+        for (left, right) in self.left.samples.iter().zip(self.right.samples.iter()) {
+            frames.push((*left, *right));
         }
     }
 
@@ -132,18 +386,24 @@ impl APU {
                 self.dmc.write_sample_length(value);
             },
             0x4015 => {
-                self.pulse1.enabled = value & 1 == 1;
-                self.pulse2.enabled = (value >> 1) & 1 == 1;
-                self.triangle.enabled = (value >> 2) & 1 == 1;
-                self.noise.enabled = (value >> 3) & 1 == 1;
-                self.dmc.enabled = (value >> 4) & 1 == 1;
-                if self.dmc.enabled && self.dmc.sample_length_counter == 0 {
-                    self.dmc.reset();
-                }
+                self.pulse1.set_enabled(value & 1 == 1);
+                self.pulse2.set_enabled((value >> 1) & 1 == 1);
+                self.triangle.set_enabled((value >> 2) & 1 == 1);
+                self.noise.set_enabled((value >> 3) & 1 == 1);
+                self.dmc.set_enabled((value >> 4) & 1 == 1);
             },
             0x4017 => {
-                self.frame_counter_mode = (value >> 7) & 1;
-                self.inhibit_irq = value & 0x40 == 1;
+                self.new_frame_counter_mode = (value >> 7) & 1;
+                self.inhibit_irq = value & 0x40 == 0x40;
+                if self.inhibit_irq {
+                    self.frame_interrupt_flag = false;
+                }
+                // The sequence restarts 3 CPU cycles after a write during an APU cycle, 4 after one between them.
+                if self.cycle % 2 == 0 {
+                    self.frame_counter_reset_delay = 3;
+                } else {
+                    self.frame_counter_reset_delay = 4;
+                }
             }
             _ => {
                 panic!("Address {:#X} not implemented", address);
@@ -151,47 +411,122 @@ impl APU {
         }
     }
 
-    pub fn step<T: cpu::Memory>(&mut self, cpu_cycle: u64, cpu: &mut cpu::CPU<T>) {
-        self.step_timer(cpu_cycle, cpu);
-        if cpu_cycle % 2 == 0 {
-            self.step_frame_counter();
+    // $4015 read: which channels have length left, and the interrupt flags. Reading clears
+    // the frame interrupt flag.
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        if self.pulse1.length_counter > 0 {
+            status |= 1 << 0;
+        }
+        if self.pulse2.length_counter > 0 {
+            status |= 1 << 1;
+        }
+        if self.triangle.length_counter > 0 {
+            status |= 1 << 2;
+        }
+        if self.noise.length_counter > 0 {
+            status |= 1 << 3;
+        }
+        if self.dmc.sample_length_counter > 0 {
+            status |= 1 << 4;
+        }
+        if self.frame_interrupt_flag {
+            status |= 1 << 6;
+        }
+        if self.dmc.interrupt_flag {
+            status |= 1 << 7;
+        }
+        self.frame_interrupt_flag = false;
+        return status;
+    }
+
+    // Whether the frame counter and DMC are asserting the CPU's IRQ line.
+    pub fn frame_irq(&self) -> bool {
+        return self.frame_interrupt_flag;
+    }
+
+    pub fn dmc_irq(&self) -> bool {
+        return self.dmc.interrupt_flag;
+    }
+
+    // Run one CPU cycle. The DMC reads its samples with read_memory, returns true when it
+    // fetched one. The CPU is halted while that happens.
+    pub fn step(&mut self, cpu_cycle: u64, read_memory: &mut dyn FnMut(u16) -> u8) -> bool {
+        self.cycle = cpu_cycle;
+        let fetched = self.step_timer(cpu_cycle, read_memory);
+        self.step_frame_counter();
+
+        let levels = self.channel_levels();
+        let left_level = self.mix(&levels, &self.left.weights);
+        self.left.set_level(self.blip_cycle, left_level);
+        if self.stereo {
+            let right_level = self.mix(&levels, &self.right.weights);
+            self.right.set_level(self.blip_cycle, right_level);
+        }
+        self.blip_cycle += 1;
+        if self.blip_cycle == AUDIO_FRAME_CYCLES {
+            self.left.blip.end_frame(self.blip_cycle);
+            if self.stereo {
+                self.right.blip.end_frame(self.blip_cycle);
+            }
+            self.blip_cycle = 0;
         }
+        return fetched;
     }
 
-    fn step_timer<T: cpu::Memory>(&mut self, cpu_cycle: u64, cpu: &mut cpu::CPU<T>) {
+    fn step_timer(&mut self, cpu_cycle: u64, read_memory: &mut dyn FnMut(u16) -> u8) -> bool {
+        let mut fetched = false;
         if cpu_cycle % 2 == 0 {
             self.pulse1.step_timer();
             self.pulse2.step_timer();
             self.noise.step_timer();
-            self.dmc.step_timer(cpu);
+            fetched = self.dmc.step_timer(read_memory);
         }
         self.triangle.step_timer();
+        return fetched;
     }
 
+    // The frame counter's steps, in CPU cycles since the sequence restarted.
+    // https://wiki.nesdev.com/w/index.php/APU_Frame_Counter
     fn step_frame_counter(&mut self) {
+        if self.frame_counter_reset_delay > 0 {
+            self.frame_counter_reset_delay -= 1;
+            if self.frame_counter_reset_delay == 0 {
+                self.frame_counter = 0;
+                self.frame_counter_mode = self.new_frame_counter_mode;
+                // Switching to 5 step mode clocks the units right away.
+                if self.frame_counter_mode == 1 {
+                    self.step_quarter_frame();
+                    self.step_half_frame();
+                }
+                return;
+            }
+        }
+
         self.frame_counter += 1;
         if self.frame_counter_mode == 0 {
             // 4 step mode
             match self.frame_counter {
-                3729 => {
-                    self.step_envelope();
-                    self.triangle.step_linear_counter();
-                },
                 7457 => {
-                    self.step_envelope();
-                    self.triangle.step_linear_counter();
-                    self.step_length_counter();
-                    self.step_sweep();
+                    self.step_quarter_frame();
+                },
+                14913 => {
+                    self.step_quarter_frame();
+                    self.step_half_frame();
                 },
-                11186 => {
-                    self.step_envelope();
-                    self.triangle.step_linear_counter();
+                22371 => {
+                    self.step_quarter_frame();
                 },
-                14915 => {
-                    self.step_envelope();
-                    self.triangle.step_linear_counter();
-                    self.step_length_counter();
-                    self.step_sweep();
+                29828 => {
+                    self.set_frame_interrupt();
+                },
+                29829 => {
+                    self.step_quarter_frame();
+                    self.step_half_frame();
+                    self.set_frame_interrupt();
+                },
+                29830 => {
+                    self.set_frame_interrupt();
                     self.frame_counter = 0;
                 },
                 _ => {}
@@ -199,26 +534,22 @@ impl APU {
         } else if self.frame_counter_mode == 1{
             // 5 step mode
             match self.frame_counter {
-                3729 => {
-                    self.step_envelope();
-                    self.triangle.step_linear_counter();
-                },
                 7457 => {
-                    self.step_envelope();
-                    self.triangle.step_linear_counter();
-                    self.step_length_counter();
-                    self.step_sweep();
+                    self.step_quarter_frame();
+                },
+                14913 => {
+                    self.step_quarter_frame();
+                    self.step_half_frame();
+                },
+                22371 => {
+                    self.step_quarter_frame();
                 },
-                11186 => {
-                    self.step_envelope();
-                    self.triangle.step_linear_counter();
+                29829 => {},
+                37281 => {
+                    self.step_quarter_frame();
+                    self.step_half_frame();
                 },
-                14915 => {},
-                18641 => {
-                    self.step_envelope();
-                    self.triangle.step_linear_counter();
-                    self.step_length_counter();
-                    self.step_sweep();
+                37282 => {
                     self.frame_counter = 0;
                 },
                 _ => {}
@@ -227,6 +558,24 @@ impl APU {
 
     }
 
+    // The envelopes and the triangle's linear counter.
+    fn step_quarter_frame(&mut self) {
+        self.step_envelope();
+        self.triangle.step_linear_counter();
+    }
+
+    // The length counters and the sweep units.
+    fn step_half_frame(&mut self) {
+        self.step_length_counter();
+        self.step_sweep();
+    }
+
+    fn set_frame_interrupt(&mut self) {
+        if !self.inhibit_irq {
+            self.frame_interrupt_flag = true;
+        }
+    }
+
     fn step_envelope(&mut self) {
         self.pulse1.step_envelope();
         self.pulse2.step_envelope();
@@ -245,15 +594,64 @@ impl APU {
         self.pulse2.step_sweep();
     }
 
-    pub fn output(&mut self) -> f32 {
-        let pulse1_out = self.pulse1.output();
-        let pulse2_out = self.pulse2.output();
-        let pulse_out = self.mixer_pulse_table[(pulse1_out + pulse2_out) as usize];
-        let triangle_out = self.triangle.output();
-        let noise_out = self.noise.output();
-        let dmc_out = self.dmc.get_output();
-        let tnd_out = self.mixer_tnd_table[((3*triangle_out) + (2*noise_out) + dmc_out) as usize];
-        return pulse_out + tnd_out;
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+        state.write_u64(self.frame_counter);
+        state.write_u8(self.frame_counter_mode);
+        state.write_bool(self.inhibit_irq);
+        state.write_bool(self.frame_interrupt_flag);
+        state.write_u8(self.frame_counter_reset_delay);
+        state.write_u8(self.new_frame_counter_mode);
+        state.write_u64(self.cycle);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+        self.frame_counter = state.read_u64()?;
+        self.frame_counter_mode = state.read_u8()?;
+        self.inhibit_irq = state.read_bool()?;
+        self.frame_interrupt_flag = state.read_bool()?;
+        self.frame_counter_reset_delay = state.read_u8()?;
+        self.new_frame_counter_mode = state.read_u8()?;
+        self.cycle = state.read_u64()?;
+        return Ok(());
+    }
+
+    // The level of each channel, in the order of Channel::ALL.
+    fn channel_levels(&mut self) -> [f32; 5] {
+        return [
+            self.pulse1.output() as f32,
+            self.pulse2.output() as f32,
+            self.triangle.output() as f32,
+            self.noise.output() as f32,
+            self.dmc.get_output() as f32,
+        ];
+    }
+
+    // The nonlinear mix of the channel levels, each multiplied by its weight first.
+    fn mix(&self, levels: &[f32; 5], weights: &[f32; 5]) -> f32 {
+        let pulse = levels[0] * weights[0] + levels[1] * weights[1];
+        let tnd = 3.0 * levels[2] * weights[2] + 2.0 * levels[3] * weights[3] + levels[4] * weights[4];
+        // Unweighted levels are whole numbers and can be looked up.
+        if *weights == [1.0; 5] {
+            return self.mixer_pulse_table[pulse as usize] + self.mixer_tnd_table[tnd as usize];
+        }
+        let mut output = 0.0;
+        if pulse > 0.0 {
+            output += 95.52 / (8128.0 / pulse + 100.0);
+        }
+        if tnd > 0.0 {
+            output += 163.67 / (24329.0 / tnd + 100.0);
+        }
+        return output;
     }
 }
 
@@ -328,9 +726,17 @@ impl Pulse {
         }
     }
 
+    // $4015 write. A disabled channel's length counter is held at 0.
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
     fn step_envelope(&mut self) {
         if !self.envelope_start_flag {
-            if self.envelope_counter <= 0 {
+            if self.envelope_counter == 0 {
                 self.envelope_counter = self.envelope_period;
                 if self.envelope_decay_level_counter > 0 {
                     self.envelope_decay_level_counter -= 1;
@@ -352,11 +758,8 @@ impl Pulse {
             let change_amount = self.timer_period >> self.sweep_shift_count;
             if self.sweep_negate {
                 self.timer_period -= change_amount;
-                match self.channel_type {
-                    PulseChannelType::One => {
-                        self.timer_period -= 1;
-                    }
-                    _ => {},
+                if let PulseChannelType::One = self.channel_type {
+                    self.timer_period -= 1;
                 }
             } else {
                  self.timer_period += change_amount;
@@ -397,11 +800,60 @@ impl Pulse {
     // $4003 / $4007
     fn write_length_counter_and_timer_period_high(&mut self, value: u8) {
         self.timer_period = (self.timer_period & 0x00FF) | (((value & 0x7) as u16) << 8);
-        self.length_counter = LENGTH_COUNTER_TABLE[((value & 0xF8) >> 3) as usize];
+        if self.enabled {
+            self.length_counter = LENGTH_COUNTER_TABLE[((value & 0xF8) >> 3) as usize];
+        }
         self.sequence_index = 0;
         self.envelope_start_flag = true;
     }
 
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.use_constant_volume);
+        state.write_u8(self.duty_cycle);
+        state.write_u8(self.constant_volume);
+        state.write_bool(self.enabled);
+        state.write_u8(self.envelope_counter);
+        state.write_u8(self.envelope_decay_level_counter);
+        state.write_bool(self.envelope_loop);
+        state.write_u8(self.envelope_period);
+        state.write_bool(self.envelope_start_flag);
+        state.write_bool(self.length_counter_halt);
+        state.write_u8(self.length_counter);
+        state.write_u8(self.sweep_counter);
+        state.write_bool(self.sweep_enabled);
+        state.write_u8(self.sweep_period);
+        state.write_bool(self.sweep_negate);
+        state.write_u8(self.sweep_shift_count);
+        state.write_bool(self.sweep_reload_flag);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer);
+        state.write_u8(self.sequence_index);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.use_constant_volume = state.read_bool()?;
+        self.duty_cycle = state.read_u8()?;
+        self.constant_volume = state.read_u8()?;
+        self.enabled = state.read_bool()?;
+        self.envelope_counter = state.read_u8()?;
+        self.envelope_decay_level_counter = state.read_u8()?;
+        self.envelope_loop = state.read_bool()?;
+        self.envelope_period = state.read_u8()?;
+        self.envelope_start_flag = state.read_bool()?;
+        self.length_counter_halt = state.read_bool()?;
+        self.length_counter = state.read_u8()?;
+        self.sweep_counter = state.read_u8()?;
+        self.sweep_enabled = state.read_bool()?;
+        self.sweep_period = state.read_u8()?;
+        self.sweep_negate = state.read_bool()?;
+        self.sweep_shift_count = state.read_u8()?;
+        self.sweep_reload_flag = state.read_bool()?;
+        self.timer_period = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        self.sequence_index = state.read_u8()?;
+        return Ok(());
+    }
+
     fn output(&mut self) -> u32 {
         if self.timer < 8 {
             return 0;
@@ -469,6 +921,14 @@ impl Triangle {
         }
     }
 
+    // $4015 write. A disabled channel's length counter is held at 0.
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
     fn step_linear_counter(&mut self) {
         if self.linear_counter_reload_flag {
             self.linear_counter = self.linear_counter_period;
@@ -495,10 +955,39 @@ impl Triangle {
     // $400B
     fn write_length_counter_and_timer_period_high(&mut self, value: u8) {
         self.timer_period = (self.timer_period & 0x00FF) | (((value & 0x7) as u16) << 8);
-        self.length_counter = LENGTH_COUNTER_TABLE[((value & 0xF8) >> 3) as usize];
+        if self.enabled {
+            self.length_counter = LENGTH_COUNTER_TABLE[((value & 0xF8) >> 3) as usize];
+        }
         self.linear_counter_reload_flag = true;
     }
 
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.control_flag);
+        state.write_bool(self.length_counter_halt);
+        state.write_u8(self.length_counter);
+        state.write_u8(self.linear_counter);
+        state.write_u8(self.linear_counter_period);
+        state.write_bool(self.linear_counter_reload_flag);
+        state.write_u8(self.sequence_index);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.timer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.control_flag = state.read_bool()?;
+        self.length_counter_halt = state.read_bool()?;
+        self.length_counter = state.read_u8()?;
+        self.linear_counter = state.read_u8()?;
+        self.linear_counter_period = state.read_u8()?;
+        self.linear_counter_reload_flag = state.read_bool()?;
+        self.sequence_index = state.read_u8()?;
+        self.timer_period = state.read_u16()?;
+        self.timer = state.read_u16()?;
+        return Ok(());
+    }
+
     fn output(&mut self) -> u16 {
         if !self.enabled {
             return 0;
@@ -570,9 +1059,17 @@ impl Noise {
         }
     }
 
+    // $4015 write. A disabled channel's length counter is held at 0.
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
     fn step_envelope(&mut self) {
         if !self.envelope_start_flag {
-            if self.envelope_counter <= 0 {
+            if self.envelope_counter == 0 {
                 self.envelope_counter = self.envelope_period;
                 if self.envelope_decay_level_counter > 0 {
                     self.envelope_decay_level_counter -= 1;
@@ -606,10 +1103,47 @@ impl Noise {
 
     // $400F
     fn write_length_counter(&mut self, value: u8) {
-        self.length_counter = LENGTH_COUNTER_TABLE[((value & 0xF8) >> 3) as usize];
+        if self.enabled {
+            self.length_counter = LENGTH_COUNTER_TABLE[((value & 0xF8) >> 3) as usize];
+        }
         self.envelope_start_flag = true;
     }
 
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.mode_flag);
+        state.write_bool(self.length_counter_halt);
+        state.write_u8(self.length_counter);
+        state.write_u8(self.envelope_counter);
+        state.write_u8(self.envelope_decay_level_counter);
+        state.write_bool(self.envelope_loop);
+        state.write_u8(self.envelope_period);
+        state.write_bool(self.envelope_start_flag);
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.shift_register);
+        state.write_bool(self.use_constant_volume);
+        state.write_u8(self.constant_volume);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.read_bool()?;
+        self.mode_flag = state.read_bool()?;
+        self.length_counter_halt = state.read_bool()?;
+        self.length_counter = state.read_u8()?;
+        self.envelope_counter = state.read_u8()?;
+        self.envelope_decay_level_counter = state.read_u8()?;
+        self.envelope_loop = state.read_bool()?;
+        self.envelope_period = state.read_u8()?;
+        self.envelope_start_flag = state.read_bool()?;
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        self.shift_register = state.read_u16()?;
+        self.use_constant_volume = state.read_bool()?;
+        self.constant_volume = state.read_u8()?;
+        return Ok(());
+    }
+
     fn output(&mut self) -> u16 {
         if !self.enabled {
             return 0;
@@ -628,15 +1162,16 @@ impl Noise {
 }
 
 struct DMC {
-    enabled: bool,
     timer: u16,
     timer_period: u16,
     sample_address: u16,
     sample_length: u16,
+    // The memory reader's position in the sample and the bytes it has left to read.
     sample_address_counter: u16,
     sample_length_counter: u16,
     output: u8,
     irq_enabled: bool,
+    interrupt_flag: bool,
     loop_enabled: bool,
     shift_register: u8,
     bits_remaining_counter: u8,
@@ -647,83 +1182,110 @@ struct DMC {
 impl DMC {
     fn new() -> DMC {
         DMC{
-            enabled: false,
             timer: 0,
-            timer_period: 0,
-            sample_address: 0,
-            sample_length: 0,
+            timer_period: DMC_PERIOD_TABLE[0],
+            sample_address: 0xC000,
+            sample_length: 1,
             sample_address_counter: 0,
             sample_length_counter: 0,
             output: 0,
             irq_enabled: false,
+            interrupt_flag: false,
             loop_enabled: false,
             shift_register: 0,
-            bits_remaining_counter: 0,
-            silence_flag: false,
-            sample_buffer: Some(0),
+            bits_remaining_counter: 8,
+            silence_flag: true,
+            sample_buffer: None,
         }
     }
 
+    // Start the sample over from its first byte.
     fn reset(&mut self) {
         self.sample_address_counter = self.sample_address;
         self.sample_length_counter = self.sample_length;
     }
 
-    fn step_timer<T: cpu::Memory>(&mut self, cpu: &mut cpu::CPU<T>) {
-        // TODO finish this and make sure it works.
-        if !self.enabled {
-            return
+    // $4015 write. Disabling stops the memory reader, the bits already read still play out.
+    // Enabling starts the sample over unless it is still playing. Either clears the interrupt flag.
+    fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.sample_length_counter = 0;
+        } else if self.sample_length_counter == 0 {
+            self.reset();
         }
-        if self.sample_buffer == None && self.sample_length_counter > 0 {
-            cpu.stall += 4;
-            self.sample_buffer = Some(cpu.read(self.sample_address_counter));
-            if self.sample_address_counter == 0xFFFF {
-                self.sample_address_counter = 0x8000;
-            } else {
-                self.sample_address_counter = 0x0000;
-            }
-            self.sample_length_counter -= 1;
-            if self.sample_address_counter == 0 && self.loop_enabled {
+        self.interrupt_flag = false;
+    }
+
+    // Called every APU cycle. Returns true when the memory reader fetched a byte.
+    fn step_timer(&mut self, read_memory: &mut dyn FnMut(u16) -> u8) -> bool {
+        let fetched = self.step_reader(read_memory);
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            self.step_output();
+        } else {
+            self.timer -= 1;
+        }
+        return fetched;
+    }
+
+    // The memory reader fills the sample buffer as soon as it is empty.
+    fn step_reader(&mut self, read_memory: &mut dyn FnMut(u16) -> u8) -> bool {
+        if self.sample_buffer.is_some() || self.sample_length_counter == 0 {
+            return false;
+        }
+        self.sample_buffer = Some(read_memory(self.sample_address_counter));
+        // The address wraps around to $8000, not $0000.
+        if self.sample_address_counter == 0xFFFF {
+            self.sample_address_counter = 0x8000;
+        } else {
+            self.sample_address_counter += 1;
+        }
+        self.sample_length_counter -= 1;
+        if self.sample_length_counter == 0 {
+            if self.loop_enabled {
                 self.reset();
+            } else if self.irq_enabled {
+                self.interrupt_flag = true;
             }
-            // TODO handle otherwise, if the bytes remaining counter becomes zero and the IRQ enabled flag is set, the interrupt flag is set.
         }
-        if self.timer == 0 {
-            self.timer = self.timer_period;
-            if !self.silence_flag {
-                if self.shift_register&1 == 1 {
-                    if self.output <= 125 {
-                        self.output += 2;
-                    }
-                } else {
-                    if self.output >= 2 {
-                        self.output -= 2;
-                    }
+        return true;
+    }
+
+    // Play the next bit of the shift register, moving on to the sample buffer after 8 bits.
+    fn step_output(&mut self) {
+        if !self.silence_flag {
+            if self.shift_register&1 == 1 {
+                if self.output <= 125 {
+                    self.output += 2;
+                }
+            } else {
+                if self.output >= 2 {
+                    self.output -= 2;
                 }
             }
-            self.shift_register >>= 1;
-            self.bits_remaining_counter -= 1;
-            if self.bits_remaining_counter <= 0 {
-                self.bits_remaining_counter = 8;
-                match self.sample_buffer{
-                    Some(v) => {
-                        self.silence_flag = false;
-                        self.shift_register = v;
-                        self.sample_buffer = None;
-                    },
-                    None => {
-                        self.silence_flag = true;
-                    }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining_counter -= 1;
+        if self.bits_remaining_counter == 0 {
+            self.bits_remaining_counter = 8;
+            match self.sample_buffer.take() {
+                Some(v) => {
+                    self.silence_flag = false;
+                    self.shift_register = v;
+                },
+                None => {
+                    self.silence_flag = true;
                 }
             }
-        } else {
-            self.timer -= 1; 
         }
     }
 
     // $4010
     fn write_controls(&mut self, value: u8) {
         self.irq_enabled = (value >> 7) & 1 == 1;
+        if !self.irq_enabled {
+            self.interrupt_flag = false;
+        }
         self.loop_enabled = (value >> 6) & 1 == 1;
         self.timer_period = DMC_PERIOD_TABLE[(value & 0x0F) as usize];
     }
@@ -743,6 +1305,48 @@ impl DMC {
         self.sample_length = (l as u16 * 16) + 1;
     }
 
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.timer);
+        state.write_u16(self.timer_period);
+        state.write_u16(self.sample_address);
+        state.write_u16(self.sample_length);
+        state.write_u16(self.sample_address_counter);
+        state.write_u16(self.sample_length_counter);
+        state.write_u8(self.output);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.interrupt_flag);
+        state.write_bool(self.loop_enabled);
+        state.write_u8(self.shift_register);
+        state.write_u8(self.bits_remaining_counter);
+        state.write_bool(self.silence_flag);
+        state.write_bool(self.sample_buffer.is_some());
+        state.write_u8(self.sample_buffer.unwrap_or(0));
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.timer = state.read_u16()?;
+        self.timer_period = state.read_u16()?;
+        self.sample_address = state.read_u16()?;
+        self.sample_length = state.read_u16()?;
+        self.sample_address_counter = state.read_u16()?;
+        self.sample_length_counter = state.read_u16()?;
+        self.output = state.read_u8()?;
+        self.irq_enabled = state.read_bool()?;
+        self.interrupt_flag = state.read_bool()?;
+        self.loop_enabled = state.read_bool()?;
+        self.shift_register = state.read_u8()?;
+        self.bits_remaining_counter = state.read_u8()?;
+        self.silence_flag = state.read_bool()?;
+        let has_sample_buffer = state.read_bool()?;
+        let sample_buffer = state.read_u8()?;
+        if has_sample_buffer {
+            self.sample_buffer = Some(sample_buffer);
+        } else {
+            self.sample_buffer = None;
+        }
+        return Ok(());
+    }
+
     fn get_output(&mut self) -> u16 {
         return self.output as u16;
     }
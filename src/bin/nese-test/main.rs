@@ -0,0 +1,143 @@
+// Runs test ROMs that report their result through cartridge RAM, the way blargg's test
+// suites (cpu_instrs, instr_timing, ppu_vbl_nmi, apu_test, mmc3_test, ...) do:
+//
+// $6000      status: $80 while running, $81 when the ROM wants the reset button pressed,
+//            otherwise the final result code (0 means passed)
+// $6001-6003 the signature DE B0 61, the status is only valid once this is present
+// $6004-     NUL terminated text output
+//
+// The exit code is the ROM's result code, or 255 if a ROM couldn't be loaded or didn't finish in time.
+#![allow(clippy::needless_return)]
+
+extern crate nese;
+extern crate clap;
+
+use clap::{Arg, App};
+
+const STATUS_ADDRESS: u16 = 0x6000;
+const SIGNATURE_ADDRESS: u16 = 0x6001;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEXT_ADDRESS: u16 = 0x6004;
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET_REQUESTED: u8 = 0x81;
+const FRAMES_PER_SECOND: u32 = 60;
+// The ROMs ask for the reset to happen at least 100ms after they request it.
+const RESET_DELAY_FRAMES: u32 = 6;
+const DEFAULT_TIMEOUT_SECONDS: &str = "120";
+const EXIT_CODE_ERROR: i32 = 255;
+
+enum Outcome {
+    Finished(u8),
+    TimedOut,
+}
+
+fn has_signature(console: &mut nese::Nes) -> bool {
+    for (i, byte) in SIGNATURE.iter().enumerate() {
+        if console.read_cartridge(SIGNATURE_ADDRESS + i as u16) != *byte {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn read_text(console: &mut nese::Nes) -> String {
+    let mut text = Vec::new();
+    let mut addr = TEXT_ADDRESS;
+    while addr < 0x8000 {
+        let c = console.read_cartridge(addr);
+        if c == 0 {
+            break;
+        }
+        text.push(c);
+        addr += 1;
+    }
+    return String::from_utf8_lossy(&text).into_owned();
+}
+
+// Run the ROM until it reports a final result, pressing reset whenever it asks for it.
+fn run(console: &mut nese::Nes, max_frames: u32) -> Outcome {
+    let mut frames = 0;
+    let mut reset_frame = None;
+    while frames < max_frames {
+        let (_, frame_change) = console.step();
+        if !frame_change {
+            continue;
+        }
+        frames += 1;
+
+        if !has_signature(console) {
+            continue;
+        }
+        let status = console.read_cartridge(STATUS_ADDRESS);
+        if status == STATUS_RUNNING {
+            continue;
+        } else if status == STATUS_RESET_REQUESTED {
+            match reset_frame {
+                None => reset_frame = Some(frames + RESET_DELAY_FRAMES),
+                Some(frame) if frames >= frame => {
+                    console.reset();
+                    reset_frame = None;
+                },
+                Some(_) => {},
+            }
+        } else if status < STATUS_RUNNING {
+            return Outcome::Finished(status);
+        }
+    }
+    return Outcome::TimedOut;
+}
+
+fn main() {
+    let matches = App::new("nese-test")
+                          .author("Matt McCoy <mattnenterprise@yahoo.com>")
+                          .about("Runs test ROMs that report their result at $6000 and exits with the result code")
+                          .arg(Arg::with_name("filenames")
+                                        .help("the test ROMs to run")
+                                        .index(1)
+                                        .multiple(true)
+                                        .required(true)
+                          )
+                          .arg(Arg::with_name("timeout")
+                                        .long("timeout")
+                                        .takes_value(true)
+                                        .help("give up on a ROM after this many seconds of emulated time (default: 120)")
+                          ).get_matches();
+
+    let timeout = matches.value_of("timeout").unwrap_or(DEFAULT_TIMEOUT_SECONDS);
+    let timeout_seconds: u32 = match timeout.parse() {
+        Ok(seconds) => seconds,
+        Err(_) => {
+            println!("Invalid timeout {}", timeout);
+            std::process::exit(EXIT_CODE_ERROR);
+        }
+    };
+
+    // With several ROMs the exit code is that of the last one that didn't pass.
+    let mut exit_code = 0;
+    for file_name in matches.values_of("filenames").unwrap() {
+        let mut console = match nese::Nes::new(file_name.to_string()) {
+            Ok(console) => console,
+            Err(e) => {
+                println!("{}: failed to load: {}", file_name, e);
+                exit_code = EXIT_CODE_ERROR;
+                continue;
+            }
+        };
+        match run(&mut console, timeout_seconds * FRAMES_PER_SECOND) {
+            Outcome::Finished(code) => {
+                println!("{}: {}", file_name, if code == 0 { "passed" } else { "failed" });
+                println!("{}", read_text(&mut console).trim_end());
+                if code != 0 {
+                    println!("result code {}", code);
+                    exit_code = code as i32;
+                }
+            },
+            Outcome::TimedOut => {
+                println!("{}: did not finish within {} seconds", file_name, timeout_seconds);
+                println!("{}", read_text(&mut console).trim_end());
+                exit_code = EXIT_CODE_ERROR;
+            },
+        }
+    }
+    std::process::exit(exit_code);
+}
@@ -0,0 +1,96 @@
+// Audio output through PortAudio. The emulator's sample rate is nudged up and down so the
+// queue of frames waiting to be played stays near a target length, that way the queue
+// neither runs dry and crackles nor grows and adds latency.
+
+// Written in the same explicit style as the emulator core.
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use portaudio;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+const FRAMES_PER_BUFFER: u32 = 256;
+// How much audio to keep queued, in seconds. Enough to ride out a late frame.
+const TARGET_LATENCY: f64 = 0.05;
+// The most the sample rate is scaled by, a 0.5% change in pitch can't be heard.
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+pub struct Audio {
+    stream: portaudio::Stream<portaudio::NonBlocking, portaudio::Output<f32>>,
+    // The number of frames the callback has taken from the queue.
+    frames_played: Arc<AtomicUsize>,
+    target_queue_length: usize,
+    // Dropped last, the stream has to be closed before PortAudio is terminated.
+    _pa: portaudio::PortAudio,
+}
+
+impl Audio {
+    // Frames of (left, right) samples sent to the returned sender are played at `sample_rate`.
+    // With 1 channel only the left sample is played.
+    pub fn open(sample_rate: f64, channels: i32) -> Result<(Audio, mpsc::SyncSender<(f32, f32)>), portaudio::Error> {
+        // Room for a second of frames.
+        let (sender, receiver) = mpsc::sync_channel::<(f32, f32)>(sample_rate as usize);
+        let pa = portaudio::PortAudio::new()?;
+        let mut settings = pa.default_output_stream_settings(channels, sample_rate, FRAMES_PER_BUFFER)?;
+        // we won't output out of range samples so don't bother clipping them.
+        settings.flags = portaudio::stream_flags::CLIP_OFF;
+
+        let frames_played = Arc::new(AtomicUsize::new(0));
+        let callback_frames_played = Arc::clone(&frames_played);
+        let mut last_frame = (0.0, 0.0);
+        let callback = move |portaudio::OutputStreamCallbackArgs { buffer, .. }| {
+            let mut played = 0;
+            for output in buffer.chunks_mut(channels as usize) {
+                // Repeat the last frame when the queue runs dry, dropping to 0 would click.
+                if let Ok(frame) = receiver.try_recv() {
+                    last_frame = frame;
+                    played += 1;
+                }
+                output[0] = last_frame.0;
+                if output.len() > 1 {
+                    output[1] = last_frame.1;
+                }
+            }
+            callback_frames_played.fetch_add(played, Ordering::SeqCst);
+            portaudio::Continue
+        };
+        let stream = pa.open_non_blocking_stream(settings, callback)?;
+
+        let audio = Audio{
+            stream: stream,
+            frames_played: frames_played,
+            target_queue_length: (sample_rate * TARGET_LATENCY) as usize,
+            _pa: pa,
+        };
+        return Ok((audio, sender));
+    }
+
+    pub fn start(&mut self) -> Result<(), portaudio::Error> {
+        return self.stream.start();
+    }
+
+    pub fn close(&mut self) -> Result<(), portaudio::Error> {
+        self.stream.stop()?;
+        return self.stream.close();
+    }
+
+    pub fn target_queue_length(&self) -> usize {
+        return self.target_queue_length;
+    }
+
+    // The frames that have been sent but not played yet.
+    pub fn queue_length(&self, frames_sent: u64) -> usize {
+        return (frames_sent as usize).saturating_sub(self.frames_played.load(Ordering::SeqCst));
+    }
+
+    // The ratio to scale the emulator's sample rate by. Under 1 makes fewer samples while the
+    // queue is longer than the target, over 1 makes more while it is shorter.
+    pub fn rate_adjustment(&self, frames_sent: u64) -> f64 {
+        let target = self.target_queue_length as f64;
+        let queued = self.queue_length(frames_sent) as f64;
+        let error = ((target - queued) / target).clamp(-1.0, 1.0);
+        return 1.0 + MAX_RATE_ADJUSTMENT * error;
+    }
+}
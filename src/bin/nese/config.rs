@@ -0,0 +1,282 @@
+// Written in the same explicit style as the emulator core.
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use nese::Buttons;
+use nese::apu::Channel;
+use sdl2::controller::Button;
+use sdl2::keyboard::Keycode;
+use toml;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const DEFAULT_CONFIG_FILE: &str = "nese.toml";
+
+// An emulator action that isn't a controller button.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hotkey {
+    Quit,
+    SaveState,
+    LoadState,
+    // Toggle whether an APU channel is muted or soloed.
+    Mute(Channel),
+    Solo(Channel),
+}
+
+// What a keyboard key is bound to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyAction {
+    // A button on the controller plugged into port 1 (index 0) or port 2 (index 1).
+    Controller(usize, Buttons),
+    Hotkey(Hotkey),
+}
+
+// The resolved input bindings the event loop looks keys and gamepad buttons up in.
+pub struct Bindings {
+    pub keys: HashMap<Keycode, KeyAction>,
+    // Gamepad button bindings for the pad assigned to each controller port.
+    pub gamepad_buttons: [HashMap<Button, Buttons>; 2],
+}
+
+// How one APU channel is mixed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelMix {
+    pub gain: f32,
+    // -1 is all the way left, 1 all the way right.
+    pub pan: f32,
+}
+
+// Everything the config file sets up.
+pub struct Config {
+    pub bindings: Bindings,
+    // In the order of nese::apu::Channel::ALL.
+    pub mixer: [ChannelMix; 5],
+}
+
+// The layout of the config file. Names are SDL key names (e.g. "Return", "Left Shift")
+// and SDL game controller button names (e.g. "a", "dpup"). An empty name leaves the
+// button unbound. Anything left out of the file keeps its default binding. A key or gamepad
+// button can only be bound to one action, so taking a key used by a default binding means
+// unbinding that default.
+//
+// [player1.keyboard]
+// a = "A"
+// start = "Return"
+//
+// [player2.gamepad]
+// a = "b"
+//
+// [hotkeys]
+// quit = "Escape"
+//
+// [mixer.triangle]
+// gain = 1.5
+// pan = -0.5
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    player1: PlayerConfig,
+    player2: PlayerConfig,
+    hotkeys: HotkeyConfig,
+    mixer: MixerConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct PlayerConfig {
+    keyboard: ButtonConfig,
+    gamepad: ButtonConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ButtonConfig {
+    a: Option<String>,
+    b: Option<String>,
+    select: Option<String>,
+    start: Option<String>,
+    up: Option<String>,
+    down: Option<String>,
+    left: Option<String>,
+    right: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct HotkeyConfig {
+    quit: Option<String>,
+    save_state: Option<String>,
+    load_state: Option<String>,
+    mute_pulse1: Option<String>,
+    mute_pulse2: Option<String>,
+    mute_triangle: Option<String>,
+    mute_noise: Option<String>,
+    mute_dmc: Option<String>,
+    solo_pulse1: Option<String>,
+    solo_pulse2: Option<String>,
+    solo_triangle: Option<String>,
+    solo_noise: Option<String>,
+    solo_dmc: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct MixerConfig {
+    pulse1: ChannelConfig,
+    pulse2: ChannelConfig,
+    triangle: ChannelConfig,
+    noise: ChannelConfig,
+    dmc: ChannelConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ChannelConfig {
+    gain: Option<f32>,
+    pan: Option<f32>,
+}
+
+// Default names in the order A, B, Select, Start, Up, Down, Left, Right.
+const DEFAULT_KEYBOARD_BINDINGS: [[&str; 8]; 2] = [
+    ["A", "Z", "S", "Return", "Up", "Down", "Left", "Right"],
+    ["O", "U", "Y", "P", "I", "K", "J", "L"],
+];
+const DEFAULT_GAMEPAD_BINDINGS: [&str; 8] = ["a", "x", "back", "start", "dpup", "dpdown", "dpleft", "dpright"];
+const DEFAULT_QUIT_KEY: &str = "Escape";
+const DEFAULT_SAVE_STATE_KEY: &str = "F5";
+const DEFAULT_LOAD_STATE_KEY: &str = "F9";
+// Default names in the order of Channel::ALL.
+const DEFAULT_MUTE_KEYS: [&str; 5] = ["1", "2", "3", "4", "5"];
+const DEFAULT_SOLO_KEYS: [&str; 5] = ["Q", "W", "E", "R", "T"];
+
+impl ButtonConfig {
+    // Pair every button with its configured name, falling back to the defaults.
+    fn bindings<'a>(&'a self, defaults: &[&'a str; 8]) -> [(&'a str, Buttons); 8] {
+        let names = [&self.a, &self.b, &self.select, &self.start, &self.up, &self.down, &self.left, &self.right];
+        let buttons = [Buttons::A, Buttons::B, Buttons::Select, Buttons::Start, Buttons::Up, Buttons::Down, Buttons::Left, Buttons::Right];
+        let mut bindings = [("", Buttons::A); 8];
+        for i in 0..8 {
+            let name = match *names[i] {
+                Some(ref name) => name.as_str(),
+                None => defaults[i],
+            };
+            bindings[i] = (name, buttons[i]);
+        }
+        return bindings;
+    }
+}
+
+fn bind_key(keys: &mut HashMap<Keycode, KeyAction>, name: &str, action: KeyAction) -> Result<(), String> {
+    if name.is_empty() {
+        return Ok(());
+    }
+    match Keycode::from_name(name) {
+        Some(keycode) => {
+            if keys.contains_key(&keycode) {
+                return Err(format!("key \"{}\" is bound to more than one action", name));
+            }
+            keys.insert(keycode, action);
+            return Ok(());
+        },
+        None => return Err(format!("unknown key name \"{}\"", name)),
+    }
+}
+
+fn bind_gamepad_button(buttons: &mut HashMap<Button, Buttons>, name: &str, nes_button: Buttons) -> Result<(), String> {
+    if name.is_empty() {
+        return Ok(());
+    }
+    match Button::from_string(name) {
+        Some(button) => {
+            if buttons.contains_key(&button) {
+                return Err(format!("game controller button \"{}\" is bound to more than one button", name));
+            }
+            buttons.insert(button, nes_button);
+            return Ok(());
+        },
+        None => return Err(format!("unknown game controller button name \"{}\"", name)),
+    }
+}
+
+impl ChannelConfig {
+    fn mix(&self, name: &str) -> Result<ChannelMix, String> {
+        let gain = self.gain.unwrap_or(1.0);
+        if gain < 0.0 {
+            return Err(format!("the {} gain can't be negative", name));
+        }
+        let pan = self.pan.unwrap_or(0.0);
+        if !(-1.0..=1.0).contains(&pan) {
+            return Err(format!("the {} pan must be between -1 and 1", name));
+        }
+        return Ok(ChannelMix{gain: gain, pan: pan});
+    }
+}
+
+impl MixerConfig {
+    fn mixer(&self) -> Result<[ChannelMix; 5], String> {
+        return Ok([
+            self.pulse1.mix("pulse1")?,
+            self.pulse2.mix("pulse2")?,
+            self.triangle.mix("triangle")?,
+            self.noise.mix("noise")?,
+            self.dmc.mix("dmc")?,
+        ]);
+    }
+}
+
+impl ConfigFile {
+    fn into_config(self) -> Result<Config, String> {
+        let mut bindings = Bindings{
+            keys: HashMap::new(),
+            gamepad_buttons: [HashMap::new(), HashMap::new()],
+        };
+        for (port, player) in [&self.player1, &self.player2].iter().enumerate() {
+            for &(name, button) in player.keyboard.bindings(&DEFAULT_KEYBOARD_BINDINGS[port]).iter() {
+                bind_key(&mut bindings.keys, name, KeyAction::Controller(port, button))?;
+            }
+            for &(name, button) in player.gamepad.bindings(&DEFAULT_GAMEPAD_BINDINGS).iter() {
+                bind_gamepad_button(&mut bindings.gamepad_buttons[port], name, button)?;
+            }
+        }
+        let mut hotkeys = vec![
+            (&self.hotkeys.quit, DEFAULT_QUIT_KEY, Hotkey::Quit),
+            (&self.hotkeys.save_state, DEFAULT_SAVE_STATE_KEY, Hotkey::SaveState),
+            (&self.hotkeys.load_state, DEFAULT_LOAD_STATE_KEY, Hotkey::LoadState),
+        ];
+        let mute_keys = [&self.hotkeys.mute_pulse1, &self.hotkeys.mute_pulse2, &self.hotkeys.mute_triangle, &self.hotkeys.mute_noise, &self.hotkeys.mute_dmc];
+        let solo_keys = [&self.hotkeys.solo_pulse1, &self.hotkeys.solo_pulse2, &self.hotkeys.solo_triangle, &self.hotkeys.solo_noise, &self.hotkeys.solo_dmc];
+        for i in 0..5 {
+            hotkeys.push((mute_keys[i], DEFAULT_MUTE_KEYS[i], Hotkey::Mute(Channel::ALL[i])));
+            hotkeys.push((solo_keys[i], DEFAULT_SOLO_KEYS[i], Hotkey::Solo(Channel::ALL[i])));
+        }
+        for &(name, default, hotkey) in hotkeys.iter() {
+            let name = match *name {
+                Some(ref name) => name.as_str(),
+                None => default,
+            };
+            bind_key(&mut bindings.keys, name, KeyAction::Hotkey(hotkey))?;
+        }
+        return Ok(Config{
+            bindings: bindings,
+            mixer: self.mixer.mixer()?,
+        });
+    }
+}
+
+// Load the input bindings and mixer settings from a config file, using the defaults if the
+// file doesn't exist.
+pub fn load_config(file_name: &Path) -> Result<Config, String> {
+    let config = match fs::read_to_string(file_name) {
+        Ok(contents) => {
+            match toml::from_str::<ConfigFile>(&contents) {
+                Ok(config) => config,
+                Err(e) => return Err(format!("{}: {}", file_name.display(), e)),
+            }
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => ConfigFile::default(),
+        Err(e) => return Err(format!("{}: {}", file_name.display(), e)),
+    };
+    return config.into_config().map_err(|e| format!("{}: {}", file_name.display(), e));
+}
@@ -0,0 +1,121 @@
+// Written in the same explicit style as the emulator core.
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, Button, GameController};
+
+// How far the analog stick has to be pushed before it counts as a D-pad press.
+const STICK_DEAD_ZONE: i16 = 16384;
+
+struct Gamepad {
+    controller: GameController,
+    // The direction the left stick is pushed in on each axis, -1, 0 or 1.
+    stick_x: i8,
+    stick_y: i8,
+}
+
+// Tracks the game controllers that are plugged into the two NES controller ports.
+// Pads are given the first free port when they are connected and free it again when
+// they are disconnected, so a pad plugged in later can take over the port.
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    ports: [Option<Gamepad>; 2],
+}
+
+impl Gamepads {
+    pub fn new(subsystem: GameControllerSubsystem) -> Gamepads {
+        return Gamepads{
+            subsystem: subsystem,
+            ports: [None, None],
+        };
+    }
+
+    // Open a newly connected pad and assign it to a free port. SDL also reports every pad
+    // that is already connected at startup this way.
+    pub fn add(&mut self, joystick_index: u32) {
+        if !self.subsystem.is_game_controller(joystick_index) {
+            return;
+        }
+        let controller = match self.subsystem.open(joystick_index) {
+            Ok(controller) => controller,
+            Err(e) => {
+                println!("Failed to open game controller {}: {}", joystick_index, e);
+                return;
+            }
+        };
+        if self.port(controller.instance_id()).is_some() {
+            return;
+        }
+        for port in 0..2 {
+            if self.ports[port].is_none() {
+                println!("{} connected to controller port {}", controller.name(), port + 1);
+                self.ports[port] = Some(Gamepad{
+                    controller: controller,
+                    stick_x: 0,
+                    stick_y: 0,
+                });
+                return;
+            }
+        }
+        println!("{} connected, but both controller ports are in use", controller.name());
+    }
+
+    // Close a disconnected pad, returning the port it was assigned to.
+    pub fn remove(&mut self, instance_id: i32) -> Option<usize> {
+        let port = self.port(instance_id)?;
+        if let Some(gamepad) = self.ports[port].take() {
+            println!("{} disconnected from controller port {}", gamepad.controller.name(), port + 1);
+        }
+        return Some(port);
+    }
+
+    // The port a pad is assigned to, if any.
+    pub fn port(&self, instance_id: i32) -> Option<usize> {
+        for port in 0..2 {
+            if let Some(ref gamepad) = self.ports[port] {
+                if gamepad.controller.instance_id() == instance_id {
+                    return Some(port);
+                }
+            }
+        }
+        return None;
+    }
+
+    // Turn left stick movement into D-pad presses and releases, so the stick follows
+    // whatever the D-pad is bound to. Returns the port and the D-pad buttons that changed.
+    pub fn axis_motion(&mut self, instance_id: i32, axis: Axis, value: i16) -> Option<(usize, Vec<(Button, bool)>)> {
+        let port = self.port(instance_id)?;
+        let gamepad = match self.ports[port] {
+            Some(ref mut gamepad) => gamepad,
+            None => return None,
+        };
+        let direction = if value <= -STICK_DEAD_ZONE {
+            -1
+        } else if value >= STICK_DEAD_ZONE {
+            1
+        } else {
+            0
+        };
+        let (current, negative, positive) = match axis {
+            Axis::LeftX => (&mut gamepad.stick_x, Button::DPadLeft, Button::DPadRight),
+            Axis::LeftY => (&mut gamepad.stick_y, Button::DPadUp, Button::DPadDown),
+            _ => return None,
+        };
+        let mut changes = Vec::new();
+        if *current == direction {
+            return Some((port, changes));
+        }
+        if *current == -1 {
+            changes.push((negative, false));
+        } else if *current == 1 {
+            changes.push((positive, false));
+        }
+        if direction == -1 {
+            changes.push((negative, true));
+        } else if direction == 1 {
+            changes.push((positive, true));
+        }
+        *current = direction;
+        return Some((port, changes));
+    }
+}
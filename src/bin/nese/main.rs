@@ -0,0 +1,525 @@
+// The frontend uses explicit returns like the emulator core.
+#![allow(clippy::needless_return)]
+
+extern crate nese;
+extern crate sdl2;
+extern crate portaudio;
+extern crate time;
+extern crate clap;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+
+mod audio;
+mod config;
+mod gamepad;
+mod repl;
+
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::event::Event;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use clap::{Arg, App, AppSettings, SubCommand};
+use config::{Hotkey, KeyAction};
+use gamepad::Gamepads;
+use nese::Buttons;
+use nese::apu::Channel;
+use nese::debugger::Debugger;
+use nese::filter::FilterProfile;
+use nese::gdb::{GdbServer, GdbSession};
+use nese::trace::Tracer;
+
+// How often battery backed RAM is flushed to the .sav file, in seconds.
+const BATTERY_RAM_FLUSH_INTERVAL: u64 = 5;
+
+fn main() {
+    let matches = App::new("nese")
+                          .author("Matt McCoy <mattnenterprise@yahoo.com>")
+                          .setting(AppSettings::SubcommandsNegateReqs)
+                          .arg(Arg::with_name("filename")
+                                        .help("the game file to use")
+                                        .index(1)
+                                        .required(true)
+                          )
+                          .arg(Arg::with_name("config")
+                                        .long("config")
+                                        .takes_value(true)
+                                        .help("the input bindings and mixer config file to use (default: nese.toml)")
+                          )
+                          .arg(Arg::with_name("sample-rate")
+                                        .long("sample-rate")
+                                        .takes_value(true)
+                                        .help("the audio output sample rate in Hz (default: 44100)")
+                          )
+                          .arg(Arg::with_name("filter")
+                                        .long("filter")
+                                        .takes_value(true)
+                                        .possible_values(&["raw", "nes", "famicom"])
+                                        .help("the console whose audio output filters are emulated, or raw for none (default: nes)")
+                          )
+                          .arg(Arg::with_name("sync")
+                                        .long("sync")
+                                        .takes_value(true)
+                                        .possible_values(&["audio", "vsync"])
+                                        .help("pace frames by the audio queue or by the display's vsync (default: audio)")
+                          )
+                          .arg(Arg::with_name("trace")
+                                        .long("trace")
+                                        .takes_value(true)
+                                        .help("write every executed instruction to this file")
+                          )
+                          .arg(Arg::with_name("trace-range")
+                                        .long("trace-range")
+                                        .takes_value(true)
+                                        .help("only trace instructions with a PC in this range, e.g. 8000-BFFF")
+                          )
+                          .arg(Arg::with_name("trace-ring")
+                                        .long("trace-ring")
+                                        .takes_value(true)
+                                        .help("keep the last N instructions and print them if the emulator crashes")
+                          )
+                          .arg(Arg::with_name("gdb")
+                                        .long("gdb")
+                                        .takes_value(true)
+                                        .help("let a GDB remote protocol client attach on this localhost port while the game runs")
+                          )
+                          .subcommand(SubCommand::with_name("disasm")
+                                        .about("Disassembles a game's PRG ROM")
+                                        .arg(Arg::with_name("filename")
+                                                      .help("the game file to disassemble")
+                                                      .index(1)
+                                                      .required(true)
+                                        )
+                                        .arg(Arg::with_name("range")
+                                                      .long("range")
+                                                      .takes_value(true)
+                                                      .help("the address range to disassemble, e.g. C000-C0FF (default: 8000-FFFF)")
+                                        )
+                                        .arg(Arg::with_name("bank")
+                                                      .long("bank")
+                                                      .takes_value(true)
+                                                      .help("disassemble this 16KB PRG ROM bank instead of what is mapped in at power on")
+                                        )
+                                        .arg(Arg::with_name("base")
+                                                      .long("base")
+                                                      .takes_value(true)
+                                                      .help("the address the --bank is disassembled at (default: 8000)")
+                                        )
+                          )
+                          .subcommand(SubCommand::with_name("debug")
+                                        .about("Runs a game headless in an interactive debugger")
+                                        .arg(Arg::with_name("filename")
+                                                      .help("the game file to debug")
+                                                      .index(1)
+                                                      .required(true)
+                                        )
+                                        .arg(Arg::with_name("gdb")
+                                                      .long("gdb")
+                                                      .takes_value(true)
+                                                      .help("serve the GDB remote protocol on this localhost port instead of the command line")
+                                        )
+                          ).get_matches();
+
+    if let Some(disasm_matches) = matches.subcommand_matches("disasm") {
+        match disassemble(disasm_matches) {
+            Ok(listing) => print!("{}", listing),
+            Err(e) => {
+                println!("Failed to disassemble {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(debug_matches) = matches.subcommand_matches("debug") {
+        let game_file = debug_matches.value_of("filename").unwrap();
+        let console = match nese::Nes::new(game_file.to_string()) {
+            Ok(console) => console,
+            Err(e) => {
+                println!("Failed to load {}: {}", game_file, e);
+                std::process::exit(1);
+            }
+        };
+        match debug_matches.value_of("gdb") {
+            Some(port) => {
+                if let Err(e) = serve_gdb(console, port) {
+                    println!("GDB server failed: {}", e);
+                    std::process::exit(1);
+                }
+            },
+            None => repl::run(console),
+        }
+        return;
+    }
+
+    let game_file = matches.value_of("filename").unwrap();
+    let sample_rate = match matches.value_of("sample-rate") {
+        Some(rate) => match rate.parse::<f64>() {
+            Ok(rate) if rate > 0.0 => rate,
+            _ => {
+                println!("Invalid sample rate {}", rate);
+                std::process::exit(1);
+            }
+        },
+        None => nese::apu::DEFAULT_SAMPLE_RATE,
+    };
+    let filter_profile = match matches.value_of("filter") {
+        Some("raw") => FilterProfile::Raw,
+        Some("famicom") => FilterProfile::Famicom,
+        _ => FilterProfile::Nes,
+    };
+    let sync_to_vsync = matches.value_of("sync") == Some("vsync");
+    let config_file = matches.value_of("config").unwrap_or(config::DEFAULT_CONFIG_FILE);
+    let state_file = Path::new(game_file).with_extension("state");
+
+    let config = match config::load_config(Path::new(config_file)) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Failed to load config {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+
+    let window = video_subsystem.window("nese", 256, 240)
+        .position_centered()
+        .opengl()
+        .build()
+        .unwrap();
+
+    let mut canvas_builder = window.into_canvas();
+    if sync_to_vsync {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let mut canvas = canvas_builder.build().unwrap();
+    let texture_creator = canvas.texture_creator();
+
+    let mut texture = texture_creator.create_texture_streaming(
+        PixelFormatEnum::RGB24, 256, 240).unwrap();
+
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut gamepads = Gamepads::new(sdl_context.game_controller().unwrap());
+
+    // Only output stereo if a channel is panned.
+    let stereo = config.mixer.iter().any(|mix| mix.pan != 0.0);
+    let (mut audio, audio_frame_sender) = match audio::Audio::open(sample_rate, if stereo { 2 } else { 1 }) {
+        Ok(audio) => audio,
+        Err(e) => {
+            println!("Failed to open audio output: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut console = match nese::Nes::new(game_file.to_string()) {
+        Ok(console) => console,
+        Err(e) => {
+            println!("Failed to load {}: {}", game_file, e);
+            std::process::exit(1);
+        }
+    };
+    console.set_sample_rate(sample_rate);
+    console.set_filter_profile(filter_profile);
+    {
+        let mut apu = console.apu();
+        apu.set_stereo(stereo);
+        for (channel, mix) in Channel::ALL.iter().zip(config.mixer.iter()) {
+            apu.set_channel_gain(*channel, mix.gain);
+            apu.set_channel_pan(*channel, mix.pan);
+        }
+    }
+    console.set_audio_sender(Some(audio_frame_sender));
+    match create_tracer(&matches) {
+        Ok(tracer) => console.set_tracer(tracer),
+        Err(e) => {
+            println!("Failed to start tracing: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let gdb_server = match matches.value_of("gdb") {
+        Some(port) => match bind_gdb(port) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                println!("Failed to start the GDB server: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let mut gdb_session = None;
+    let mut debugger = Debugger::new(console);
+
+    let mut last_battery_ram_flush_time = time::precise_time_ns();
+
+    audio.start().unwrap();
+    loop {
+        if let Some(ref server) = gdb_server {
+            if gdb_session.is_none() {
+                match server.try_accept() {
+                    Ok(session) => gdb_session = session,
+                    Err(e) => println!("Failed to accept a GDB connection: {}", e),
+                }
+            }
+        }
+        // The game runs freely until a GDB client attaches, then only while the client has it continued.
+        let mut gdb_stopped = false;
+        if let Some(mut session) = gdb_session.take() {
+            match poll_gdb(&mut session, &mut debugger) {
+                Ok(true) => {
+                    gdb_stopped = !session.running();
+                    gdb_session = Some(session);
+                },
+                Ok(false) => {},
+                Err(e) => println!("GDB connection failed: {}", e),
+            }
+        } else {
+            // Run until the PPU finishes a frame.
+            loop {
+                let (_, frame_change) = debugger.console().step();
+                if frame_change {
+                    break;
+                }
+            }
+        }
+        let console = debugger.console();
+        canvas.clear();
+        let v8_pixels = console.get_frame_buffer();
+        texture.update(None, v8_pixels.as_slice(), 768).unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+        // Blocks until the next vblank when syncing to vsync.
+        canvas.present();
+
+        for event in event_pump.poll_iter() {
+            let mut actions = Vec::new();
+            match event {
+                Event::Quit {..} => actions.push((KeyAction::Hotkey(Hotkey::Quit), true)),
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                    if let Some(action) = config.bindings.keys.get(&keycode) {
+                        actions.push((*action, true));
+                    }
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(action) = config.bindings.keys.get(&keycode) {
+                        actions.push((*action, false));
+                    }
+                },
+                Event::ControllerDeviceAdded { which, .. } => gamepads.add(which),
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    // Let go of everything the pad was holding down.
+                    if let Some(port) = gamepads.remove(which) {
+                        for button in config.bindings.gamepad_buttons[port].values() {
+                            actions.push((KeyAction::Controller(port, *button), false));
+                        }
+                    }
+                },
+                Event::ControllerButtonDown { which, button, .. } => {
+                    if let Some(port) = gamepads.port(which) {
+                        if let Some(b) = config.bindings.gamepad_buttons[port].get(&button) {
+                            actions.push((KeyAction::Controller(port, *b), true));
+                        }
+                    }
+                },
+                Event::ControllerButtonUp { which, button, .. } => {
+                    if let Some(port) = gamepads.port(which) {
+                        if let Some(b) = config.bindings.gamepad_buttons[port].get(&button) {
+                            actions.push((KeyAction::Controller(port, *b), false));
+                        }
+                    }
+                },
+                Event::ControllerAxisMotion { which, axis, value, .. } => {
+                    if let Some((port, changes)) = gamepads.axis_motion(which, axis, value) {
+                        for (button, pressed) in changes {
+                            if let Some(b) = config.bindings.gamepad_buttons[port].get(&button) {
+                                actions.push((KeyAction::Controller(port, *b), pressed));
+                            }
+                        }
+                    }
+                },
+                _ => {},
+            }
+            for action in actions {
+                match action {
+                    (KeyAction::Controller(port, button), pressed) => {
+                        set_button_state(console, port, button, pressed);
+                    },
+                    (KeyAction::Hotkey(Hotkey::Quit), true) => {
+                        if let Err(e) = audio.close() {
+                            println!("Failed to close audio output: {}", e);
+                        }
+                        if let Err(e) = console.save_battery_ram() {
+                            println!("Failed to write save file: {}", e);
+                        }
+                        return
+                    },
+                    (KeyAction::Hotkey(Hotkey::SaveState), true) => {
+                        if let Err(e) = fs::write(&state_file, console.save_state()) {
+                            println!("Failed to write save state {}: {}", state_file.display(), e);
+                        }
+                    },
+                    (KeyAction::Hotkey(Hotkey::Mute(channel)), true) => {
+                        let mut apu = console.apu();
+                        let muted = !apu.channel_muted(channel);
+                        apu.set_channel_muted(channel, muted);
+                        println!("{:?} {}", channel, if muted { "muted" } else { "unmuted" });
+                    },
+                    (KeyAction::Hotkey(Hotkey::Solo(channel)), true) => {
+                        let mut apu = console.apu();
+                        let soloed = !apu.channel_soloed(channel);
+                        apu.set_channel_soloed(channel, soloed);
+                        println!("{:?} {}", channel, if soloed { "soloed" } else { "unsoloed" });
+                    },
+                    (KeyAction::Hotkey(Hotkey::LoadState), true) => {
+                        match fs::read(&state_file) {
+                            Ok(data) => {
+                                if let Err(e) = console.load_state(&data) {
+                                    println!("Failed to load save state {}: {}", state_file.display(), e);
+                                }
+                            },
+                            Err(e) => println!("Failed to read save state {}: {}", state_file.display(), e),
+                        }
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        if (time::precise_time_ns() - last_battery_ram_flush_time) > BATTERY_RAM_FLUSH_INTERVAL * 1000000000 {
+            if let Err(e) = console.save_battery_ram() {
+                println!("Failed to write save file: {}", e);
+            }
+            last_battery_ram_flush_time = time::precise_time_ns();
+        }
+
+        // Steer the audio queue towards its target length.
+        console.set_audio_rate_adjustment(audio.rate_adjustment(console.audio_frames_sent()));
+        // When syncing to audio, sleep until the queue drains back to the target. Vsync paces
+        // frames already, the queue is only waited on if the display runs much faster than 60Hz.
+        let max_queue_length = if sync_to_vsync {
+            audio.target_queue_length() * 3
+        } else {
+            audio.target_queue_length()
+        };
+        while audio.queue_length(console.audio_frames_sent()) > max_queue_length {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // No audio is made while a GDB client has execution stopped, so nothing else paces the loop.
+        if gdb_stopped {
+            thread::sleep(Duration::from_millis(16));
+        }
+    }
+}
+
+fn set_button_state(console: &mut nese::Nes, port: usize, button: Buttons, state: bool) {
+    if port == 0 {
+        console.set_controller1_button_state(button, state);
+    } else {
+        console.set_controller2_button_state(button, state);
+    }
+}
+
+// Build the instruction tracer from the --trace options, None if tracing wasn't asked for.
+fn create_tracer(matches: &clap::ArgMatches) -> Result<Option<Tracer>, String> {
+    let output: Option<Box<dyn std::io::Write>> = match matches.value_of("trace") {
+        Some(file_name) => {
+            let file = File::create(file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+            Some(Box::new(BufWriter::new(file)))
+        },
+        None => None,
+    };
+    let ring_buffer_size = match matches.value_of("trace-ring") {
+        Some(size) => size.parse::<usize>().map_err(|_| format!("invalid trace ring buffer size {}", size))?,
+        None => 0,
+    };
+    if output.is_none() && ring_buffer_size == 0 {
+        return Ok(None);
+    }
+
+    let mut tracer = Tracer::new(output, ring_buffer_size);
+    if let Some(range) = matches.value_of("trace-range") {
+        let (start, end) = parse_range(range)?;
+        tracer.set_pc_range(start, end);
+    }
+    return Ok(Some(tracer));
+}
+
+// Handle the client's packets and run a frame if it has execution continued. Returns false once
+// the client is gone.
+fn poll_gdb(session: &mut GdbSession, debugger: &mut Debugger) -> std::io::Result<bool> {
+    if !session.poll(debugger)? {
+        return Ok(false);
+    }
+    if session.running() {
+        session.run_frame(debugger)?;
+    }
+    return Ok(true);
+}
+
+fn bind_gdb(port: &str) -> Result<GdbServer, String> {
+    let port = port.parse::<u16>().map_err(|_| format!("invalid port {}", port))?;
+    let server = GdbServer::bind(port).map_err(|e| e.to_string())?;
+    let address = server.local_addr().map_err(|e| e.to_string())?;
+    println!("Waiting for a GDB connection on {}", address);
+    return Ok(server);
+}
+
+fn serve_gdb(console: nese::Nes, port: &str) -> Result<(), String> {
+    let server = bind_gdb(port)?;
+    let mut debugger = Debugger::new(console);
+    return server.serve(&mut debugger).map_err(|e| e.to_string());
+}
+
+// Parse an inclusive range of hex addresses, e.g. 8000-BFFF
+fn parse_range(range: &str) -> Result<(u16, u16), String> {
+    let bounds: Vec<&str> = range.split('-').collect();
+    if bounds.len() != 2 {
+        return Err(format!("invalid range {}", range));
+    }
+    let start = u16::from_str_radix(bounds[0], 16).map_err(|_| format!("invalid range {}", range))?;
+    let end = u16::from_str_radix(bounds[1], 16).map_err(|_| format!("invalid range {}", range))?;
+    return Ok((start, end));
+}
+
+// Disassemble through the CPU bus as the game is mapped in at power on, or a raw PRG ROM bank with --bank.
+fn disassemble(matches: &clap::ArgMatches) -> Result<String, String> {
+    let game_file = matches.value_of("filename").unwrap();
+    let (start, end) = parse_range(matches.value_of("range").unwrap_or("8000-FFFF"))?;
+
+    match matches.value_of("bank") {
+        Some(bank) => {
+            let bank = bank.parse::<usize>().map_err(|_| format!("invalid bank {}", bank))?;
+            let base = match matches.value_of("base") {
+                Some(base) => u16::from_str_radix(base, 16).map_err(|_| format!("invalid base address {}", base))?,
+                None => 0x8000,
+            };
+            let ines_data = nese::ines::load_ines_file(game_file).map_err(|e| format!("{}: {}", game_file, e))?;
+            let bank_size = nese::ines::PRG_ROM_UNIT_SIZE as usize;
+            let bank_count = ines_data.prg.len() / bank_size;
+            if bank >= bank_count {
+                return Err(format!("{}: bank {} is out of range, the game has {} PRG ROM banks", game_file, bank, bank_count));
+            }
+            let prg = &ines_data.prg[bank * bank_size..(bank + 1) * bank_size];
+
+            let mut labels = nese::disasm::Labels::hardware();
+            // Only a bank mapped in at the top of memory holds the vectors.
+            if base as usize + bank_size == 0x10000 {
+                labels.add_vector_targets(&mut |addr| prg[(addr - base) as usize]);
+            }
+            let instructions: Vec<nese::disasm::Instruction> = nese::disasm::disassemble_bank(prg, base).into_iter()
+                .filter(|instruction| instruction.address >= start && instruction.address <= end)
+                .collect();
+            return Ok(nese::disasm::format_listing(&instructions, &labels));
+        },
+        None => {
+            let mut console = nese::Nes::new(game_file.to_string()).map_err(|e| format!("{}: {}", game_file, e))?;
+            let instructions = console.disassemble(start, end);
+            let labels = console.labels();
+            return Ok(nese::disasm::format_listing(&instructions, &labels));
+        },
+    }
+}
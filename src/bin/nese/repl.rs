@@ -0,0 +1,270 @@
+// A command line frontend for nese::debugger, started with `nese debug <game>`.
+
+// Written in the same explicit style as the emulator core.
+#![allow(clippy::needless_return)]
+
+use nese::cpu::{AccessKind, MemoryAccess};
+use nese::debugger::{Breakpoint, Debugger, StopReason};
+use nese::disasm::{self, Labels};
+
+use std::io;
+use std::io::prelude::*;
+
+const HELP: &str = "\
+break <addr>                 stop before the instruction at addr runs
+break nmi | irq              stop when the interrupt is taken
+break op <opcode>            stop before any instruction with this opcode
+break pos <scanline> <dot>   stop when the PPU reaches this position
+watch cpu|ppu r|w|rw <addr>  stop after an instruction reads or writes addr
+delete <n>                   delete breakpoint n
+list                         list breakpoints
+step [n]          (s)        run n instructions, stepping into subroutines
+next              (n)        run an instruction, stepping over subroutines
+finish                       run until the current subroutine returns
+frame [n]                    run until the end of the nth frame
+continue          (c)        run until a breakpoint is hit
+regs              (r)        print the registers
+mem <addr> [len]  (x)        print CPU memory
+disasm [addr] [n] (d)        disassemble n instructions from addr, or the PC
+quit              (q)
+Addresses and opcodes are hex, an empty line repeats the last command.";
+
+const DEFAULT_MEMORY_LENGTH: u16 = 64;
+const DEFAULT_DISASSEMBLY_LENGTH: usize = 10;
+
+pub fn run(console: nese::Nes) {
+    let mut debugger = Debugger::new(console);
+    let labels = debugger.console().labels();
+    let stdin = io::stdin();
+    let mut last_command = String::new();
+
+    println!("Type help for a list of commands.");
+    print_location(&mut debugger, &labels);
+    loop {
+        print!("(nese) ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {},
+        }
+        let mut command = line.trim().to_string();
+        if command.is_empty() {
+            command = last_command.clone();
+        }
+        last_command = command.clone();
+
+        let words: Vec<&str> = command.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        if words[0] == "quit" || words[0] == "q" {
+            return;
+        }
+        if let Err(e) = run_command(&mut debugger, &labels, &words) {
+            println!("{}", e);
+        }
+    }
+}
+
+fn run_command(debugger: &mut Debugger, labels: &Labels, words: &[&str]) -> Result<(), String> {
+    match words[0] {
+        "help" | "h" => println!("{}", HELP),
+        "break" | "b" => {
+            let breakpoint = match words.get(1) {
+                Some(&"nmi") => Breakpoint::Nmi,
+                Some(&"irq") => Breakpoint::Irq,
+                Some(&"op") => {
+                    let opcode = parse_hex(argument(words, 2)?)?;
+                    if opcode > 0xFF {
+                        return Err(format!("invalid opcode {:X}", opcode));
+                    }
+                    Breakpoint::Opcode(opcode as u8)
+                },
+                Some(&"pos") => Breakpoint::Position(parse_number(argument(words, 2)?)? as u16, parse_number(argument(words, 3)?)? as u16),
+                Some(address) => Breakpoint::Execute(parse_hex(address)?),
+                None => return Err("break needs an address".to_string()),
+            };
+            let index = debugger.add_breakpoint(breakpoint);
+            println!("{}: {}", index, describe_breakpoint(&breakpoint));
+        },
+        "watch" | "w" => {
+            let address = parse_hex(argument(words, 3)?)?;
+            let (read, write) = match argument(words, 2)? {
+                "r" => (true, false),
+                "w" => (false, true),
+                "rw" => (true, true),
+                kind => return Err(format!("unknown access {}, expected r, w or rw", kind)),
+            };
+            let mut breakpoints = Vec::new();
+            match argument(words, 1)? {
+                "cpu" => {
+                    if read {
+                        breakpoints.push(Breakpoint::CpuRead(address));
+                    }
+                    if write {
+                        breakpoints.push(Breakpoint::CpuWrite(address));
+                    }
+                },
+                "ppu" => {
+                    if read {
+                        breakpoints.push(Breakpoint::PpuRead(address));
+                    }
+                    if write {
+                        breakpoints.push(Breakpoint::PpuWrite(address));
+                    }
+                },
+                bus => return Err(format!("unknown bus {}, expected cpu or ppu", bus)),
+            }
+            for breakpoint in breakpoints {
+                let index = debugger.add_breakpoint(breakpoint);
+                println!("{}: {}", index, describe_breakpoint(&breakpoint));
+            }
+        },
+        "delete" => {
+            let index = parse_number(argument(words, 1)?)? as usize;
+            if debugger.remove_breakpoint(index).is_none() {
+                return Err(format!("no breakpoint {}", index));
+            }
+        },
+        "list" | "l" => {
+            for (index, breakpoint) in debugger.breakpoints().iter().enumerate() {
+                println!("{}: {}", index, describe_breakpoint(breakpoint));
+            }
+        },
+        "step" | "s" => {
+            let count = optional_number(words, 1, 1)?;
+            for i in 0..count {
+                let reason = debugger.step_into();
+                if reason != StopReason::Done || i + 1 == count {
+                    report_stop(debugger, labels, reason);
+                    break;
+                }
+            }
+        },
+        "next" | "n" => {
+            let reason = debugger.step_over();
+            report_stop(debugger, labels, reason);
+        },
+        "finish" => {
+            let reason = debugger.step_out();
+            report_stop(debugger, labels, reason);
+        },
+        "frame" => {
+            let count = optional_number(words, 1, 1)?;
+            for i in 0..count {
+                let reason = debugger.run_to_frame();
+                if reason != StopReason::Done || i + 1 == count {
+                    report_stop(debugger, labels, reason);
+                    break;
+                }
+            }
+        },
+        "continue" | "c" => {
+            let reason = debugger.resume();
+            report_stop(debugger, labels, reason);
+        },
+        "regs" | "r" => print_location(debugger, labels),
+        "mem" | "x" => {
+            let start = parse_hex(argument(words, 1)?)?;
+            let length = optional_number(words, 2, DEFAULT_MEMORY_LENGTH as u32)? as u16;
+            print_memory(debugger, start, length);
+        },
+        "disasm" | "d" => {
+            let start = match words.get(1) {
+                Some(address) => parse_hex(address)?,
+                None => debugger.console().cpu().get_registers().pc,
+            };
+            let count = optional_number(words, 2, DEFAULT_DISASSEMBLY_LENGTH as u32)? as usize;
+            // Each instruction is at most 3 bytes.
+            let end = start.saturating_add((count * 3).min(0xFFFF) as u16);
+            let instructions = debugger.console().disassemble(start, end);
+            let count = count.min(instructions.len());
+            print!("{}", disasm::format_listing(&instructions[..count], labels));
+        },
+        command => return Err(format!("unknown command {}, type help for a list of commands", command)),
+    }
+    return Ok(());
+}
+
+fn report_stop(debugger: &mut Debugger, labels: &Labels, reason: StopReason) {
+    match reason {
+        StopReason::Done => {},
+        StopReason::Breakpoint(index) => {
+            println!("Breakpoint {}: {}", index, describe_breakpoint(&debugger.breakpoints()[index]));
+        },
+        StopReason::Watchpoint(index, access) => {
+            println!("Watchpoint {}: {}", index, describe_access(&access));
+        },
+    }
+    print_location(debugger, labels);
+}
+
+// The registers, the PPU position and the next instruction.
+fn print_location(debugger: &mut Debugger, labels: &Labels) {
+    let registers = debugger.console().cpu().get_registers();
+    let (scanline, dot) = debugger.console().ppu_position();
+    println!("A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3}",
+        registers.a, registers.x, registers.y, registers.p, registers.sp, scanline, dot);
+    let instructions = debugger.console().disassemble(registers.pc, registers.pc);
+    print!("{}", disasm::format_listing(&instructions, labels));
+}
+
+fn print_memory(debugger: &mut Debugger, start: u16, length: u16) {
+    let mut address = start as u32;
+    let end = (start as u32 + length as u32).min(0x10000);
+    while address < end {
+        let mut line = format!("{:04X}:", address);
+        let line_end = (address + 16).min(end);
+        while address < line_end {
+            line.push_str(&format!(" {:02X}", debugger.console().peek(address as u16)));
+            address += 1;
+        }
+        println!("{}", line);
+    }
+}
+
+fn describe_breakpoint(breakpoint: &Breakpoint) -> String {
+    match *breakpoint {
+        Breakpoint::Execute(address) => format!("execute ${:04X}", address),
+        Breakpoint::CpuRead(address) => format!("CPU read ${:04X}", address),
+        Breakpoint::CpuWrite(address) => format!("CPU write ${:04X}", address),
+        Breakpoint::PpuRead(address) => format!("PPU read ${:04X}", address),
+        Breakpoint::PpuWrite(address) => format!("PPU write ${:04X}", address),
+        Breakpoint::Nmi => "NMI".to_string(),
+        Breakpoint::Irq => "IRQ".to_string(),
+        Breakpoint::Opcode(opcode) => format!("opcode ${:02X}", opcode),
+        Breakpoint::Position(scanline, dot) => format!("scanline {} dot {}", scanline, dot),
+    }
+}
+
+fn describe_access(access: &MemoryAccess) -> String {
+    match access.kind {
+        AccessKind::Read => format!("read ${:02X} from ${:04X}", access.value, access.address),
+        AccessKind::Write => format!("wrote ${:02X} to ${:04X}", access.value, access.address),
+    }
+}
+
+fn argument<'a>(words: &[&'a str], index: usize) -> Result<&'a str, String> {
+    match words.get(index) {
+        Some(word) => Ok(word),
+        None => Err(format!("{} needs more arguments, type help for its usage", words[0])),
+    }
+}
+
+fn optional_number(words: &[&str], index: usize, default: u32) -> Result<u32, String> {
+    match words.get(index) {
+        Some(word) => parse_number(word),
+        None => Ok(default),
+    }
+}
+
+// Hex with an optional $ prefix, e.g. C000 or $C000
+fn parse_hex(word: &str) -> Result<u16, String> {
+    let digits = word.trim_start_matches('$');
+    return u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address {}", word));
+}
+
+fn parse_number(word: &str) -> Result<u32, String> {
+    return word.parse::<u32>().map_err(|_| format!("invalid number {}", word));
+}
@@ -0,0 +1,146 @@
+// Band-limited step synthesis, in the style of blip_buf. A signal clocked at the emulated
+// rate is described by the changes in its level, each change is spread over a few output
+// samples with a windowed sinc so the steps don't alias when it is read back at the host's
+// sample rate.
+// http://www.slack.net/~ant/bl-synth/
+
+// The kernel and integration loops index their arrays the way the math is written.
+#![allow(clippy::needless_range_loop, clippy::needless_return)]
+
+use std::f64::consts::PI;
+
+// The number of fractional sample positions a step can start at.
+const PHASES: usize = 32;
+// Samples on each side of a step that it is spread over.
+const HALF_WIDTH: usize = 8;
+const WIDTH: usize = HALF_WIDTH * 2;
+// The kernel's cutoff as a fraction of the output sample rate, a little under Nyquist.
+const CUTOFF: f64 = 0.45;
+// Deltas and kernel taps are fixed point, so every step adds exactly its delta to the level
+// once it has been read and nothing drifts however long it runs. Levels have LEVEL_SCALE
+// units to 1.0 and the taps of each phase add up to exactly KERNEL_SCALE.
+pub const LEVEL_SCALE: f32 = (1 << 20) as f32;
+const KERNEL_SCALE: i64 = 1 << 15;
+
+pub struct BlipBuffer {
+    // Output samples per input clock.
+    factor: f64,
+    // Where the current frame starts, in output samples from the start of `deltas`.
+    offset: f64,
+    deltas: Vec<i64>,
+    // The sum of every delta read so far, which is the output level.
+    integrator: i64,
+    kernel: Vec<[i64; WIDTH]>,
+}
+
+impl BlipBuffer {
+    // `clock_rate` is the rate the input is clocked at, `sample_rate` the rate samples are read at.
+    pub fn new(clock_rate: f64, sample_rate: f64) -> BlipBuffer {
+        return BlipBuffer{
+            factor: sample_rate / clock_rate,
+            offset: 0.0,
+            deltas: Vec::new(),
+            integrator: 0,
+            kernel: create_kernel(),
+        };
+    }
+
+    // Changing the rates drops any samples that haven't been read.
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.factor = sample_rate / clock_rate;
+        self.clear();
+    }
+
+    // Change the rates without dropping samples, for small adjustments while running.
+    pub fn adjust_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.factor = sample_rate / clock_rate;
+    }
+
+    pub fn clear(&mut self) {
+        self.offset = 0.0;
+        self.deltas.clear();
+        self.integrator = 0;
+    }
+
+    // Drop any samples that haven't been read and carry on from `level`, in LEVEL_SCALE
+    // units, without a step to it.
+    pub fn restart_at(&mut self, level: i64) {
+        self.clear();
+        self.integrator = level * KERNEL_SCALE;
+    }
+
+    // The level of the last sample read.
+    pub fn level(&self) -> f32 {
+        return (self.integrator as f64 / (LEVEL_SCALE as f64 * KERNEL_SCALE as f64)) as f32;
+    }
+
+    // Change the level by `delta`, in LEVEL_SCALE units, `time` clocks after the start of the
+    // current frame.
+    pub fn add_delta(&mut self, time: u32, delta: i64) {
+        let position = self.offset + time as f64 * self.factor;
+        let sample = position as usize;
+        let phase = (((position - sample as f64) * PHASES as f64) as usize).min(PHASES - 1);
+        if self.deltas.len() < sample + WIDTH {
+            self.deltas.resize(sample + WIDTH, 0);
+        }
+        let taps = &self.kernel[phase];
+        for i in 0..WIDTH {
+            self.deltas[sample + i] += delta * taps[i];
+        }
+    }
+
+    // End the current frame `duration` clocks after its start. The samples before the end
+    // won't change any more and can be read.
+    pub fn end_frame(&mut self, duration: u32) {
+        self.offset += duration as f64 * self.factor;
+        let end = self.offset as usize;
+        if self.deltas.len() < end + WIDTH {
+            self.deltas.resize(end + WIDTH, 0);
+        }
+    }
+
+    pub fn samples_available(&self) -> usize {
+        return self.offset as usize;
+    }
+
+    // Move the samples that are available onto the end of `samples`.
+    pub fn read_samples(&mut self, samples: &mut Vec<f32>) {
+        let count = self.samples_available();
+        for i in 0..count {
+            self.integrator += self.deltas[i];
+            samples.push(self.level());
+        }
+        self.deltas.drain(..count);
+        self.offset -= count as f64;
+    }
+}
+
+// A Blackman windowed sinc for each phase, normalized so a step ends up at exactly the
+// height of its delta. This delays every step by about HALF_WIDTH samples.
+fn create_kernel() -> Vec<[i64; WIDTH]> {
+    let mut kernel = Vec::with_capacity(PHASES);
+    for phase in 0..PHASES {
+        let fraction = phase as f64 / PHASES as f64;
+        let mut taps = [0.0; WIDTH];
+        let mut sum = 0.0;
+        for i in 0..WIDTH {
+            // How far the sample this tap is added to is from the step.
+            let x = i as f64 - (HALF_WIDTH as f64 - 1.0) - fraction;
+            let window = 0.42 + 0.5 * (PI * x / HALF_WIDTH as f64).cos() + 0.08 * (2.0 * PI * x / HALF_WIDTH as f64).cos();
+            let y = 2.0 * CUTOFF * x;
+            let sinc = if y == 0.0 { 1.0 } else { (PI * y).sin() / (PI * y) };
+            let tap = 2.0 * CUTOFF * sinc * window;
+            taps[i] = tap;
+            sum += tap;
+        }
+        let mut normalized = [0; WIDTH];
+        for i in 0..WIDTH {
+            normalized[i] = (taps[i] / sum * KERNEL_SCALE as f64).round() as i64;
+        }
+        // Rounding the taps leaves them a little off KERNEL_SCALE, the center tap makes up the rest.
+        let rounded_sum: i64 = normalized.iter().sum();
+        normalized[HALF_WIDTH - 1] += KERNEL_SCALE - rounded_sum;
+        kernel.push(normalized);
+    }
+    return kernel;
+}
@@ -1,3 +1,9 @@
+// Written with explicit returns like the rest of the emulator core.
+#![allow(clippy::needless_return, clippy::new_without_default)]
+
+use state::{StateError, StateReader, StateWriter};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Buttons {
     A,
     B,
@@ -26,13 +32,13 @@ impl Controller {
 
     pub fn set_strobe(&mut self, v: bool) {
         self.strobe = v;
-        if v == true {
+        if v {
             self.read_index = 0;
         }
     }
 
     pub fn read_next_button_state(&mut self) -> u8 {
-        if self.strobe == true {
+        if self.strobe {
             self.read_index = 0;
             return self.button_states[0] as u8;
         }
@@ -44,6 +50,18 @@ impl Controller {
         return state;
     }
 
+    // Button states are live input from the frontend so they aren't part of a save state.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.strobe);
+        state.write_u8(self.read_index);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.strobe = state.read_bool()?;
+        self.read_index = state.read_u8()?;
+        return Ok(());
+    }
+
     pub fn set_button_state(&mut self, button: Buttons, state: bool) {
         match button {
             Buttons::A => {
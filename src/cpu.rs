@@ -1,15 +1,53 @@
+// Each instruction sets its flags and returns explicitly, following the 6502 references it was written from.
+#![allow(
+    clippy::assign_op_pattern,
+    clippy::identity_op,
+    clippy::manual_range_contains,
+    clippy::needless_bool_assign,
+    clippy::needless_return,
+    clippy::redundant_field_names,
+    clippy::unnecessary_cast,
+)]
+
 use mapper;
 use controller;
 use apu;
 use ppu;
+use state::{StateError, StateReader, StateWriter};
 
 use std::rc::Rc;
 use std::cell::RefCell;
 
+// Set in the status pushed by BRK and PHP, clear in the one pushed by NMI and IRQ.
+const BREAK_FLAG: u8 = 0x10;
+
 pub trait Memory {
     fn read(&mut self, addr: u16) -> u8;
-    fn write(&mut self, cycle: u64, addr: u16, data: u8);
-    fn get_added_stall(&mut self) -> u32;
+    fn write(&mut self, addr: u16, data: u8);
+    // The page a write to $4014 asked to copy to OAM. The CPU runs the DMA before its next instruction.
+    fn take_oam_dma(&mut self) -> Option<u8> {
+        return None;
+    }
+    // Advance the rest of the system by one CPU cycle, `cycle` is what the CPU does on the bus during it.
+    // Returns the extra cycles a DMC fetch halted the CPU for before its access.
+    fn tick(&mut self, cycle: BusCycle) -> u32;
+    // Whether the NMI and IRQ lines are asserted. The CPU samples them at the end of every cycle.
+    fn nmi_line(&mut self) -> bool;
+    fn irq_line(&mut self) -> bool;
+    // Read without any side effects, for debugging tools. Registers that change state when
+    // they are read return 0.
+    fn peek(&mut self, addr: u16) -> u8;
+    // Write without any side effects, for debugging tools. Only RAM can be written, returns
+    // false for any other address.
+    fn poke(&mut self, addr: u16, data: u8) -> bool;
+    // Called with every read and write on the bus, for debugging tools.
+    fn set_access_hook(&mut self, _access_hook: Option<MemoryHook>) {}
+
+    // Hooks to persist any state held by the memory in a save state.
+    fn save_state(&self, _state: &mut StateWriter) {}
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+        return Ok(());
+    }
 }
 
 pub struct CPU<T: Memory> {
@@ -23,8 +61,9 @@ pub struct CPU<T: Memory> {
     // Stack Pointer
     sp: u8,
     instruction_num: u32,
-    pub stall: u32,
     cycles: u64,
+    // The part of cycles the CPU spent halted by DMC fetches.
+    dmc_halted_cycles: u64,
     carry_flag: bool,
     zero_flag: bool,
     interrupt_disable_flag: bool,
@@ -33,7 +72,19 @@ pub struct CPU<T: Memory> {
     unused_bit5_flag: bool,
     overflow_flag: bool,
     negative_flag: bool,
-    trigger_irq: bool,
+    // The NMI line as it was last sampled. An NMI is taken when the line becomes asserted,
+    // need_nmi stays set until it is.
+    nmi_line: bool,
+    need_nmi: bool,
+    // The IRQ line is asserted and the I flag is clear.
+    run_irq: bool,
+    // need_nmi and run_irq one cycle earlier. Interrupts are polled before the last cycle of
+    // an instruction, so these decide whether one is taken after it.
+    prev_need_nmi: bool,
+    prev_run_irq: bool,
+    // The interrupt taken by the last step, if any.
+    interrupt: Option<Interrupt>,
+    trace_hook: Option<TraceHook>,
 }
 
 impl<T: Memory> CPU<T> {
@@ -46,8 +97,8 @@ impl<T: Memory> CPU<T> {
             pc: 0,
             sp: 0xFD,
             instruction_num: 0,
-            stall: 0,
             cycles: 0,
+            dmc_halted_cycles: 0,
             carry_flag: false,
             zero_flag: false,
             interrupt_disable_flag: true,
@@ -56,35 +107,116 @@ impl<T: Memory> CPU<T> {
             unused_bit5_flag: true,
             overflow_flag: false,
             negative_flag: false,
-            trigger_irq: false,
+            nmi_line: false,
+            need_nmi: false,
+            run_irq: false,
+            prev_need_nmi: false,
+            prev_run_irq: false,
+            interrupt: None,
+            trace_hook: None,
         };
-        cpu.pc = cpu.read16(0xFFFC);
-        // TODO this is just for testing with nestext.nes the real initial address to load the pc is above this.
-        //cpu.pc = 0xC000;
+        // The reset vector is read without advancing the rest of the system, power on starts at cycle 0.
+        let lo = cpu.mem.read(0xFFFC) as u16;
+        let hi = cpu.mem.read(0xFFFD) as u16;
+        cpu.pc = (hi << 8) | lo;
         cpu
     }
 
-    pub fn step(&mut self, nmi: bool) -> u64 {
-        self.stall += self.mem.get_added_stall();
-        if self.stall > 0 {
-            self.stall -=1;
-            return 1;
+    // The reset button. The registers keep their values, except the stack pointer which
+    // moves down by 3 as if an interrupt was pushed without writing anything.
+    pub fn reset(&mut self) {
+        self.sp = self.sp.wrapping_sub(3);
+        self.interrupt_disable_flag = true;
+        self.need_nmi = false;
+        self.run_irq = false;
+        self.prev_need_nmi = false;
+        self.prev_run_irq = false;
+        // 2 dummy reads and 3 stack reads, then the vector.
+        for _ in 0..5 {
+            self.idle_cycle();
         }
+        self.pc = self.read16(0xFFFC);
+    }
+
+    // Start running from a different address than the reset vector, e.g. 0xC000 for nestest's automation mode.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    // Called with every instruction right before it runs, after any interrupt has been taken.
+    pub fn set_trace_hook(&mut self, trace_hook: Option<TraceHook>) {
+        self.trace_hook = trace_hook;
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.pc = registers.pc;
+        self.a = registers.a;
+        self.x = registers.x;
+        self.y = registers.y;
+        self.set_flags(registers.p);
+        self.sp = registers.sp;
+    }
+
+    // Called with every read and write the CPU makes on its bus.
+    pub fn set_memory_hook(&mut self, memory_hook: Option<MemoryHook>) {
+        self.mem.set_access_hook(memory_hook);
+    }
+
+    // The memory the CPU is connected to.
+    pub fn memory(&mut self) -> &mut T {
+        return &mut self.mem;
+    }
+
+    // The number of instructions run since power on. Stall cycles and interrupts don't count.
+    pub fn instruction_count(&self) -> u32 {
+        return self.instruction_num;
+    }
+
+    // The interrupt the last step took. Taking an interrupt is a step of its own, so after it
+    // the PC is at the first instruction of the handler.
+    pub fn last_interrupt(&self) -> Option<Interrupt> {
+        return self.interrupt;
+    }
+
+    pub fn get_registers(&mut self) -> Registers {
+        return Registers{
+            pc: self.pc,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            p: self.get_flags(),
+            sp: self.sp,
+        };
+    }
 
+    // Run one instruction, take an interrupt or run an OAM DMA. The rest of the system is
+    // advanced a CPU cycle before every bus access, so reads and writes land on the right PPU dot.
+    pub fn step(&mut self) -> u64 {
+        let interrupted = self.interrupt.is_some();
+        self.interrupt = None;
         let cycles: u64 = self.cycles;
-        if nmi {
-            self.nmi();
-            self.cycles += 7;
-        } else if self.trigger_irq {
-            self.irq();
-            self.cycles += 7;
-            self.trigger_irq = false;
+        let dmc_halted_cycles = self.dmc_halted_cycles;
+        if let Some(page) = self.mem.take_oam_dma() {
+            self.oam_dma(page);
+            return self.cycles - cycles;
+        }
+
+        // The first instruction of an interrupt handler always runs before another interrupt is taken.
+        if !interrupted && (self.prev_need_nmi || self.prev_run_irq) {
+            self.interrupt = Some(self.take_interrupt());
+            return self.cycles - cycles;
+        }
+
+        if self.trace_hook.is_some() {
+            let trace = self.instruction_trace();
+            if let Some(ref mut trace_hook) = self.trace_hook {
+                trace_hook(&trace);
+            }
         }
 
-        let opcode = self.mem.read(self.pc);
-        let addressing_mode = self.instruction_addressing_mode(opcode);
-        let _flags = self.get_flags();
-        self.instruction_num += 1;
+        let opcode = self.bus_read(self.pc);
+        let addressing_mode = instruction_addressing_mode(opcode);
+        self.instruction_num = self.instruction_num.wrapping_add(1);
         let mut step_info = StepInfo{
             opcode: opcode,
             address: 0,
@@ -100,17 +232,27 @@ impl<T: Memory> CPU<T> {
             },
             AddressingMode::AbsoluteX => {
                 let read_address = self.pc + 1;
-                step_info.address = self.read16(read_address).wrapping_add(self.x as u16);
-                page_crossed = read_address&0xFF00 != step_info.address&0xFF00;
+                let base = self.read16(read_address);
+                step_info.address = base.wrapping_add(self.x as u16);
+                page_crossed = base&0xFF00 != step_info.address&0xFF00;
+                self.indexed_dummy_read(opcode, base, step_info.address, page_crossed);
             },
             AddressingMode::AbsoluteY => {
                 let read_address = self.pc + 1;
-                step_info.address = self.read16(read_address).wrapping_add(self.y as u16);
-                page_crossed = read_address&0xFF00 != step_info.address&0xFF00;
+                let base = self.read16(read_address);
+                step_info.address = base.wrapping_add(self.y as u16);
+                page_crossed = base&0xFF00 != step_info.address&0xFF00;
+                self.indexed_dummy_read(opcode, base, step_info.address, page_crossed);
+            },
+            AddressingMode::Accumulator => {
+                let pc = self.pc;
+                self.bus_read(pc + 1);
             },
-            AddressingMode::Accumulator => {},
             AddressingMode::IndexedIndirect => {
-                let address = self.mem.read(self.pc + 1).wrapping_add(self.x);
+                let pointer = self.bus_read(self.pc + 1);
+                // Read while X is added.
+                self.bus_read(pointer as u16);
+                let address = pointer.wrapping_add(self.x);
                 step_info.address = self.read16_zero_page(address);
             },
             AddressingMode::Indirect => {
@@ -119,17 +261,23 @@ impl<T: Memory> CPU<T> {
                 step_info.address = self.read16_low_byte_wrap(address);
             }
             AddressingMode::IndirectIndexed => {
-                let address = self.mem.read(self.pc + 1);
-                step_info.address = self.read16_zero_page(address).wrapping_add(self.y as u16);
-                page_crossed = (address as u16)&0xFF00 != step_info.address&0xFF00;
+                let address = self.bus_read(self.pc + 1);
+                let base = self.read16_zero_page(address);
+                step_info.address = base.wrapping_add(self.y as u16);
+                page_crossed = base&0xFF00 != step_info.address&0xFF00;
+                self.indexed_dummy_read(opcode, base, step_info.address, page_crossed);
             },
             AddressingMode::Immediate => {
                 step_info.address = self.pc + 1;
             },
-            AddressingMode::Implied => {},
+            AddressingMode::Implied => {
+                // Single byte instructions still read the byte after the opcode.
+                let pc = self.pc;
+                self.bus_read(pc + 1);
+            },
             AddressingMode::Relative => {
                 let offset_address = self.pc + 1;
-                let offset = self.mem.read(offset_address);
+                let offset = self.bus_read(offset_address);
 
                 if offset < 0x80 {
                     step_info.address = self.pc + 2 + offset as u16;
@@ -140,196 +288,125 @@ impl<T: Memory> CPU<T> {
             AddressingMode::Unknown => panic!("Unknown addressing mode for opcode {:#04X}", opcode),
             AddressingMode::ZeroPage => {
                 let read_address = self.pc + 1;
-                step_info.address = self.mem.read(read_address) as u16;
+                step_info.address = self.bus_read(read_address) as u16;
             },
             AddressingMode::ZeroPageX => {
                 let address = self.pc + 1;
-                step_info.address = self.mem.read(address).wrapping_add(self.x) as u16;
+                let base = self.bus_read(address);
+                // Read while X is added.
+                self.bus_read(base as u16);
+                step_info.address = base.wrapping_add(self.x) as u16;
             },
             AddressingMode::ZeroPageY => {
                 let address = self.pc + 1;
-                step_info.address = self.mem.read(address).wrapping_add(self.y) as u16;
+                let base = self.bus_read(address);
+                // Read while Y is added.
+                self.bus_read(base as u16);
+                step_info.address = base.wrapping_add(self.y) as u16;
             }
         };
 
-
-        let _instruction_size = self.instruction_size(opcode);
-        //println!("Instruction Num: {:06} , size: {:06} , OpCode: {:#06X} , Address: {:#06X} , PC: {:#06X} , A: {:#04X} , X: {:#04X} , Y: {:#04X} , P: {:#04X}, SP: {:#04X}", self.instruction_num, _instruction_size, opcode, step_info.address, self.pc, self.a, self.x, self.y, _flags, self.sp);
-
-        self.pc += self.instruction_size(opcode);
-        self.cycles += self.instruction_cycles(opcode) as u64;
+        let mut expected_cycles = self.instruction_cycles(opcode) as u64;
         if page_crossed {
-            self.cycles += self.page_crossed_cycles(opcode) as u64;
+            expected_cycles += self.page_crossed_cycles(opcode) as u64;
         }
-        
+
+        self.pc += instruction_size(opcode);
         self.run_instruction(step_info);
 
+        // Instructions that don't make an access on every cycle, like the unofficial NOPs and
+        // KIL, are padded out to their cycle count, not counting DMC halts.
+        while self.cycles - cycles - (self.dmc_halted_cycles - dmc_halted_cycles) < expected_cycles {
+            self.idle_cycle();
+        }
+
         return self.cycles - cycles;
     }
 
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.a);
+        state.write_u8(self.x);
+        state.write_u8(self.y);
+        state.write_u16(self.pc);
+        state.write_u8(self.sp);
+        state.write_u32(self.instruction_num);
+        state.write_u64(self.cycles);
+        state.write_bool(self.carry_flag);
+        state.write_bool(self.zero_flag);
+        state.write_bool(self.interrupt_disable_flag);
+        state.write_bool(self.decimal_mode_flag);
+        state.write_bool(self.unused_bit4_flag);
+        state.write_bool(self.unused_bit5_flag);
+        state.write_bool(self.overflow_flag);
+        state.write_bool(self.negative_flag);
+        state.write_bool(self.nmi_line);
+        state.write_bool(self.need_nmi);
+        state.write_bool(self.run_irq);
+        state.write_bool(self.prev_need_nmi);
+        state.write_bool(self.prev_run_irq);
+        self.mem.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.a = state.read_u8()?;
+        self.x = state.read_u8()?;
+        self.y = state.read_u8()?;
+        self.pc = state.read_u16()?;
+        self.sp = state.read_u8()?;
+        self.instruction_num = state.read_u32()?;
+        self.cycles = state.read_u64()?;
+        self.carry_flag = state.read_bool()?;
+        self.zero_flag = state.read_bool()?;
+        self.interrupt_disable_flag = state.read_bool()?;
+        self.decimal_mode_flag = state.read_bool()?;
+        self.unused_bit4_flag = state.read_bool()?;
+        self.unused_bit5_flag = state.read_bool()?;
+        self.overflow_flag = state.read_bool()?;
+        self.negative_flag = state.read_bool()?;
+        self.nmi_line = state.read_bool()?;
+        self.need_nmi = state.read_bool()?;
+        self.run_irq = state.read_bool()?;
+        self.prev_need_nmi = state.read_bool()?;
+        self.prev_run_irq = state.read_bool()?;
+        return self.mem.load_state(state);
+    }
+
+    fn instruction_trace(&mut self) -> InstructionTrace {
+        let registers = self.get_registers();
+        let mut bytes = [0; 3];
+        bytes[0] = self.mem.peek(registers.pc);
+        let size = instruction_size(bytes[0]);
+        for i in 1..size {
+            bytes[i as usize] = self.mem.peek(registers.pc.wrapping_add(i));
+        }
+        return InstructionTrace{
+            registers: registers,
+            bytes: bytes,
+            size: size,
+            cycles: self.cycles,
+        };
+    }
+
+    // A taken branch reads the next opcode while it adds the offset, and reads from the wrong
+    // page while it fixes the high byte if the branch crosses a page.
     fn add_branch_cycles(&mut self, step_info: StepInfo) {
-        self.cycles += 1;
+        // A taken branch that doesn't cross a page skips polling for an IRQ on its last cycle,
+        // an IRQ that shows up during the branch waits until after the next instruction.
+        if self.run_irq && !self.prev_run_irq {
+            self.run_irq = false;
+        }
+        let pc = self.pc;
+        self.bus_read(pc);
         if self.pc&0xFF00 != step_info.address&0xFF00 {
-            self.cycles += 1;
+            self.bus_read((self.pc & 0xFF00) | (step_info.address & 0x00FF));
         }
     }
 
-    fn instruction_addressing_mode(&mut self, opcode: u8) -> AddressingMode {
-        match opcode {
-            0x00 => AddressingMode::Immediate,
-            0x01 => AddressingMode::IndexedIndirect,
-            0x04 => AddressingMode::ZeroPage,
-            0x05 => AddressingMode::ZeroPage,
-            0x06 => AddressingMode::ZeroPage,
-            0x08 => AddressingMode::Implied,
-            0x09 => AddressingMode::Immediate,
-            0x0A => AddressingMode::Accumulator,
-            0x0D => AddressingMode::Absolute,
-            0x0E => AddressingMode::Absolute,
-            0x10 => AddressingMode::Relative,
-            0x11 => AddressingMode::IndirectIndexed,
-            0x15 => AddressingMode::ZeroPageX,
-            0x16 => AddressingMode::ZeroPageX,
-            0x18 => AddressingMode::Implied,
-            0x19 => AddressingMode::AbsoluteY,
-            0x1D => AddressingMode::AbsoluteX,
-            0x1E => AddressingMode::AbsoluteX,
-            0x20 => AddressingMode::Absolute,
-            0x21 => AddressingMode::IndexedIndirect,
-            0x24 => AddressingMode::ZeroPage,
-            0x25 => AddressingMode::ZeroPage,
-            0x26 => AddressingMode::ZeroPage,
-            0x28 => AddressingMode::Implied,
-            0x29 => AddressingMode::Immediate,
-            0x2A => AddressingMode::Accumulator,
-            0x2C => AddressingMode::Absolute,
-            0x2D => AddressingMode::Absolute,
-            0x2E => AddressingMode::Absolute,
-            0x30 => AddressingMode::Relative,
-            0x31 => AddressingMode::IndirectIndexed,
-            0x35 => AddressingMode::ZeroPageX,
-            0x36 => AddressingMode::ZeroPageX,
-            0x38 => AddressingMode::Implied,
-            0x39 => AddressingMode::AbsoluteY,
-            0x3D => AddressingMode::AbsoluteX,
-            0x3E => AddressingMode::AbsoluteX,
-            0x40 => AddressingMode::Implied,
-            0x41 => AddressingMode::IndexedIndirect,
-            0x44 => AddressingMode::ZeroPage,
-            0x45 => AddressingMode::ZeroPage,
-            0x46 => AddressingMode::ZeroPage,
-            0x48 => AddressingMode::Implied,
-            0x49 => AddressingMode::Immediate,
-            0x4A => AddressingMode::Accumulator,
-            0x4C => AddressingMode::Absolute,
-            0x4D => AddressingMode::Absolute,
-            0x4E => AddressingMode::Absolute,
-            0x50 => AddressingMode::Relative,
-            0x51 => AddressingMode::IndirectIndexed,
-            0x55 => AddressingMode::ZeroPageX,
-            0x56 => AddressingMode::ZeroPageX,
-            0x58 => AddressingMode::Implied,
-            0x59 => AddressingMode::AbsoluteY,
-            0x5D => AddressingMode::AbsoluteX,
-            0x5E => AddressingMode::AbsoluteX,
-            0x60 => AddressingMode::Implied,
-            0x61 => AddressingMode::IndexedIndirect,
-            0x65 => AddressingMode::ZeroPage,
-            0x66 => AddressingMode::ZeroPage,
-            0x68 => AddressingMode::Implied,
-            0x69 => AddressingMode::Immediate,
-            0x6A => AddressingMode::Accumulator,
-            0x6C => AddressingMode::Indirect,
-            0x6E => AddressingMode::Absolute,
-            0x6D => AddressingMode::Absolute,
-            0x70 => AddressingMode::Relative,
-            0x71 => AddressingMode::IndirectIndexed,
-            0x75 => AddressingMode::ZeroPageX,
-            0x76 => AddressingMode::ZeroPageX,
-            0x78 => AddressingMode::Implied,
-            0x79 => AddressingMode::AbsoluteY,
-            0x7D => AddressingMode::AbsoluteX,
-            0x7E => AddressingMode::AbsoluteX,
-            0x81 => AddressingMode::IndexedIndirect,
-            0x84 => AddressingMode::ZeroPage,
-            0x85 => AddressingMode::ZeroPage,
-            0x86 => AddressingMode::ZeroPage,
-            0x88 => AddressingMode::Implied,
-            0x8A => AddressingMode::Implied,
-            0x8C => AddressingMode::Absolute,
-            0x8D => AddressingMode::Absolute,
-            0x8E => AddressingMode::Absolute,
-            0x90 => AddressingMode::Relative,
-            0x91 => AddressingMode::IndirectIndexed,
-            0x94 => AddressingMode::ZeroPageX,
-            0x95 => AddressingMode::ZeroPageX,
-            0x96 => AddressingMode::ZeroPageY,
-            0x98 => AddressingMode::Implied,
-            0x99 => AddressingMode::AbsoluteY,
-            0x9A => AddressingMode::Implied,
-            0x9D => AddressingMode::AbsoluteX,
-            0xA0 => AddressingMode::Immediate,
-            0xA1 => AddressingMode::IndexedIndirect,
-            0xA2 => AddressingMode::Immediate,
-            0xA4 => AddressingMode::ZeroPage,
-            0xA5 => AddressingMode::ZeroPage,
-            0xA6 => AddressingMode::ZeroPage,
-            0xA8 => AddressingMode::Implied,
-            0xA9 => AddressingMode::Immediate,
-            0xAA => AddressingMode::Implied,
-            0xAC => AddressingMode::Absolute,
-            0xAD => AddressingMode::Absolute,
-            0xAE => AddressingMode::Absolute,
-            0xB0 => AddressingMode::Relative,
-            0xB1 => AddressingMode::IndirectIndexed,
-            0xB4 => AddressingMode::ZeroPageX,
-            0xB5 => AddressingMode::ZeroPageX,
-            0xB6 => AddressingMode::ZeroPageY,
-            0xB8 => AddressingMode::Implied,
-            0xB9 => AddressingMode::AbsoluteY,
-            0xBA => AddressingMode::Implied,
-            0xBC => AddressingMode::AbsoluteX,
-            0xBD => AddressingMode::AbsoluteX,
-            0xBE => AddressingMode::AbsoluteY,
-            0xC0 => AddressingMode::Immediate,
-            0xC1 => AddressingMode::IndexedIndirect,
-            0xC4 => AddressingMode::ZeroPage,
-            0xC5 => AddressingMode::ZeroPage,
-            0xC6 => AddressingMode::ZeroPage,
-            0xC8 => AddressingMode::Implied,
-            0xC9 => AddressingMode::Immediate,
-            0xCA => AddressingMode::Implied,
-            0xCC => AddressingMode::Absolute,
-            0xCD => AddressingMode::Absolute,
-            0xCE => AddressingMode::Absolute,
-            0xD0 => AddressingMode::Relative,
-            0xD1 => AddressingMode::IndirectIndexed,
-            0xD5 => AddressingMode::ZeroPageX,
-            0xD6 => AddressingMode::ZeroPageX,
-            0xD8 => AddressingMode::Implied,
-            0xD9 => AddressingMode::AbsoluteY,
-            0xDD => AddressingMode::AbsoluteX,
-            0xDE => AddressingMode::AbsoluteX,
-            0xE0 => AddressingMode::Immediate,
-            0xE1 => AddressingMode::IndexedIndirect,
-            0xE4 => AddressingMode::ZeroPage,
-            0xE5 => AddressingMode::ZeroPage,
-            0xE6 => AddressingMode::ZeroPage,
-            0xE8 => AddressingMode::Implied,
-            0xE9 => AddressingMode::Immediate,
-            0xEA => AddressingMode::Implied,
-            0xEC => AddressingMode::Absolute,
-            0xED => AddressingMode::Absolute,
-            0xEE => AddressingMode::Absolute,
-            0xF0 => AddressingMode::Relative,
-            0xF1 => AddressingMode::IndirectIndexed,
-            0xF5 => AddressingMode::ZeroPageX,
-            0xF6 => AddressingMode::ZeroPageX,
-            0xF8 => AddressingMode::Implied,
-            0xF9 => AddressingMode::AbsoluteY,
-            0xFD => AddressingMode::AbsoluteX,
-            0xFE => AddressingMode::AbsoluteX,
-            _ => AddressingMode::Unknown,
+    // Indexed reads first read from the address before the carry into the high byte is added.
+    // Reads only do this when a page is crossed, stores and read-modify-write instructions always do.
+    fn indexed_dummy_read(&mut self, opcode: u8, base: u16, address: u16, page_crossed: bool) {
+        if page_crossed || self.page_crossed_cycles(opcode) == 0 {
+            self.bus_read((base & 0xFF00) | (address & 0x00FF));
         }
     }
 
@@ -337,156 +414,260 @@ impl<T: Memory> CPU<T> {
         match opcode {
             0x00 => 7,
             0x01 => 6,
+            0x02 => 2,
+            0x03 => 8,
+            0x04 => 3,
             0x05 => 3,
             0x06 => 5,
+            0x07 => 5,
             0x08 => 3,
             0x09 => 2,
             0x0A => 2,
+            0x0B => 2,
+            0x0C => 4,
             0x0D => 4,
             0x0E => 6,
+            0x0F => 6,
             0x10 => 2,
             0x11 => 5,
+            0x12 => 2,
+            0x13 => 8,
+            0x14 => 4,
             0x15 => 4,
             0x16 => 6,
+            0x17 => 6,
             0x18 => 2,
             0x19 => 4,
+            0x1A => 2,
+            0x1B => 7,
+            0x1C => 4,
             0x1D => 4,
-            0x1E => 7, 
+            0x1E => 7,
+            0x1F => 7,
             0x20 => 6,
             0x21 => 6,
+            0x22 => 2,
+            0x23 => 8,
             0x24 => 3,
             0x25 => 3,
             0x26 => 5,
+            0x27 => 5,
             0x28 => 4,
             0x29 => 2,
             0x2A => 2,
+            0x2B => 2,
             0x2C => 4,
             0x2D => 4,
             0x2E => 6,
+            0x2F => 6,
             0x30 => 2,
             0x31 => 5,
+            0x32 => 2,
+            0x33 => 8,
+            0x34 => 4,
             0x35 => 4,
             0x36 => 6,
+            0x37 => 6,
             0x38 => 2,
             0x39 => 4,
+            0x3A => 2,
+            0x3B => 7,
+            0x3C => 4,
             0x3D => 4,
             0x3E => 7,
+            0x3F => 7,
             0x40 => 6,
             0x41 => 6,
+            0x42 => 2,
+            0x43 => 8,
+            0x44 => 3,
             0x45 => 3,
             0x46 => 5,
+            0x47 => 5,
             0x48 => 3,
             0x49 => 2,
             0x4A => 2,
+            0x4B => 2,
             0x4C => 3,
             0x4D => 4,
             0x4E => 6,
+            0x4F => 6,
             0x50 => 2,
             0x51 => 5,
+            0x52 => 2,
+            0x53 => 8,
+            0x54 => 4,
             0x55 => 4,
             0x56 => 6,
+            0x57 => 6,
             0x58 => 2,
             0x59 => 4,
+            0x5A => 2,
+            0x5B => 7,
+            0x5C => 4,
             0x5D => 4,
             0x5E => 7,
+            0x5F => 7,
             0x60 => 6,
             0x61 => 6,
+            0x62 => 2,
+            0x63 => 8,
+            0x64 => 3,
             0x65 => 3,
             0x66 => 5,
+            0x67 => 5,
             0x68 => 4,
             0x69 => 2,
             0x6A => 2,
+            0x6B => 2,
             0x6C => 5,
             0x6D => 4,
             0x6E => 6,
+            0x6F => 6,
             0x70 => 2,
             0x71 => 5,
+            0x72 => 2,
+            0x73 => 8,
+            0x74 => 4,
             0x75 => 4,
             0x76 => 6,
+            0x77 => 6,
             0x78 => 2,
             0x79 => 4,
+            0x7A => 2,
+            0x7B => 7,
+            0x7C => 4,
             0x7D => 4,
             0x7E => 7,
+            0x7F => 7,
+            0x80 => 2,
             0x81 => 6,
+            0x82 => 2,
+            0x83 => 6,
             0x84 => 3,
             0x85 => 3,
             0x86 => 3,
+            0x87 => 3,
             0x88 => 2,
+            0x89 => 2,
             0x8A => 2,
+            0x8B => 2,
             0x8C => 4,
             0x8D => 4,
             0x8E => 4,
+            0x8F => 4,
             0x90 => 2,
             0x91 => 6,
+            0x92 => 2,
+            0x93 => 6,
             0x94 => 4,
             0x95 => 4,
             0x96 => 4,
+            0x97 => 4,
             0x98 => 2,
             0x99 => 5,
             0x9A => 2,
+            0x9B => 5,
+            0x9C => 5,
             0x9D => 5,
+            0x9E => 5,
+            0x9F => 5,
             0xA0 => 2,
             0xA1 => 6,
             0xA2 => 2,
+            0xA3 => 6,
             0xA4 => 3,
             0xA5 => 3,
             0xA6 => 3,
+            0xA7 => 3,
             0xA8 => 2,
             0xA9 => 2,
             0xAA => 2,
+            0xAB => 2,
             0xAC => 4,
             0xAD => 4,
             0xAE => 4,
+            0xAF => 4,
             0xB0 => 2,
             0xB1 => 5,
+            0xB2 => 2,
+            0xB3 => 5,
             0xB4 => 4,
             0xB5 => 4,
             0xB6 => 4,
+            0xB7 => 4,
             0xB8 => 2,
             0xB9 => 4,
             0xBA => 2,
+            0xBB => 4,
             0xBC => 4,
             0xBD => 4,
             0xBE => 4,
+            0xBF => 4,
             0xC0 => 2,
             0xC1 => 6,
+            0xC2 => 2,
+            0xC3 => 8,
             0xC4 => 3,
             0xC5 => 3,
             0xC6 => 5,
+            0xC7 => 5,
             0xC8 => 2,
             0xC9 => 2,
             0xCA => 2,
-            0xCC => 4, 
+            0xCB => 2,
+            0xCC => 4,
             0xCD => 4,
             0xCE => 6,
+            0xCF => 6,
             0xD0 => 2,
             0xD1 => 5,
+            0xD2 => 2,
+            0xD3 => 8,
+            0xD4 => 4,
             0xD5 => 4,
             0xD6 => 6,
+            0xD7 => 6,
             0xD8 => 2,
             0xD9 => 4,
+            0xDA => 2,
+            0xDB => 7,
+            0xDC => 4,
             0xDD => 4,
             0xDE => 7,
+            0xDF => 7,
             0xE0 => 2,
             0xE1 => 6,
+            0xE2 => 2,
+            0xE3 => 8,
             0xE4 => 3,
             0xE5 => 3,
             0xE6 => 5,
+            0xE7 => 5,
             0xE8 => 2,
             0xE9 => 2,
             0xEA => 2,
+            0xEB => 2,
             0xEC => 4,
             0xED => 4,
             0xEE => 6,
+            0xEF => 6,
             0xF0 => 2,
             0xF1 => 5,
+            0xF2 => 2,
+            0xF3 => 8,
+            0xF4 => 4,
             0xF5 => 4,
             0xF6 => 6,
+            0xF7 => 6,
             0xF8 => 2,
             0xF9 => 4,
+            0xFA => 2,
+            0xFB => 7,
+            0xFC => 4,
             0xFD => 4,
             0xFE => 7,
-            _ => panic!("Unknown instruction cycles for opcode {:#04X}", opcode),
+            0xFF => 7,
         }
     }
 
@@ -495,234 +676,260 @@ impl<T: Memory> CPU<T> {
         match opcode {
             0x00 => 0,
             0x01 => 0,
+            0x02 => 0,
+            0x03 => 0,
+            0x04 => 0,
             0x05 => 0,
             0x06 => 0,
+            0x07 => 0,
             0x08 => 0,
             0x09 => 0,
             0x0A => 0,
+            0x0B => 0,
+            0x0C => 0,
             0x0D => 0,
             0x0E => 0,
+            0x0F => 0,
+            0x10 => 0,
             0x11 => 1,
+            0x12 => 0,
+            0x13 => 0,
+            0x14 => 0,
             0x15 => 0,
             0x16 => 0,
+            0x17 => 0,
             0x18 => 0,
             0x19 => 1,
+            0x1A => 0,
+            0x1B => 0,
+            0x1C => 1,
             0x1D => 1,
             0x1E => 0,
+            0x1F => 0,
+            0x20 => 0,
             0x21 => 0,
+            0x22 => 0,
+            0x23 => 0,
             0x24 => 0,
             0x25 => 0,
+            0x26 => 0,
+            0x27 => 0,
+            0x28 => 0,
             0x29 => 0,
+            0x2A => 0,
+            0x2B => 0,
             0x2C => 0,
             0x2D => 0,
+            0x2E => 0,
+            0x2F => 0,
+            0x30 => 0,
             0x31 => 1,
+            0x32 => 0,
+            0x33 => 0,
+            0x34 => 0,
             0x35 => 0,
+            0x36 => 0,
+            0x37 => 0,
+            0x38 => 0,
             0x39 => 1,
+            0x3A => 0,
+            0x3B => 0,
+            0x3C => 1,
             0x3D => 1,
             0x3E => 0,
+            0x3F => 0,
             0x40 => 0,
             0x41 => 0,
+            0x42 => 0,
+            0x43 => 0,
+            0x44 => 0,
             0x45 => 0,
+            0x46 => 0,
+            0x47 => 0,
+            0x48 => 0,
+            0x49 => 0,
+            0x4A => 0,
+            0x4B => 0,
+            0x4C => 0,
+            0x4D => 0,
+            0x4E => 0,
+            0x4F => 0,
+            0x50 => 0,
             0x51 => 1,
+            0x52 => 0,
+            0x53 => 0,
+            0x54 => 0,
+            0x55 => 0,
+            0x56 => 0,
+            0x57 => 0,
+            0x58 => 0,
             0x59 => 1,
+            0x5A => 0,
+            0x5B => 0,
+            0x5C => 1,
             0x5D => 1,
-            0x5E => 0, 
+            0x5E => 0,
+            0x5F => 0,
+            0x60 => 0,
             0x61 => 0,
+            0x62 => 0,
+            0x63 => 0,
+            0x64 => 0,
             0x65 => 0,
+            0x66 => 0,
+            0x67 => 0,
+            0x68 => 0,
             0x69 => 0,
+            0x6A => 0,
+            0x6B => 0,
+            0x6C => 0,
             0x6D => 0,
+            0x6E => 0,
+            0x6F => 0,
+            0x70 => 0,
             0x71 => 1,
+            0x72 => 0,
+            0x73 => 0,
+            0x74 => 0,
             0x75 => 0,
+            0x76 => 0,
+            0x77 => 0,
+            0x78 => 0,
             0x79 => 1,
+            0x7A => 0,
+            0x7B => 0,
+            0x7C => 1,
             0x7D => 1,
             0x7E => 0,
+            0x7F => 0,
+            0x80 => 0,
             0x81 => 0,
+            0x82 => 0,
+            0x83 => 0,
+            0x84 => 0,
+            0x85 => 0,
+            0x86 => 0,
+            0x87 => 0,
+            0x88 => 0,
+            0x89 => 0,
+            0x8A => 0,
+            0x8B => 0,
+            0x8C => 0,
+            0x8D => 0,
+            0x8E => 0,
+            0x8F => 0,
+            0x90 => 0,
             0x91 => 0,
+            0x92 => 0,
+            0x93 => 0,
+            0x94 => 0,
+            0x95 => 0,
+            0x96 => 0,
+            0x97 => 0,
+            0x98 => 0,
             0x99 => 0,
+            0x9A => 0,
+            0x9B => 0,
+            0x9C => 0,
             0x9D => 0,
+            0x9E => 0,
+            0x9F => 0,
             0xA0 => 0,
             0xA1 => 0,
             0xA2 => 0,
+            0xA3 => 0,
             0xA4 => 0,
+            0xA5 => 0,
+            0xA6 => 0,
+            0xA7 => 0,
+            0xA8 => 0,
+            0xA9 => 0,
+            0xAA => 0,
+            0xAB => 0,
+            0xAC => 0,
+            0xAD => 0,
+            0xAE => 0,
+            0xAF => 0,
+            0xB0 => 0,
             0xB1 => 1,
+            0xB2 => 0,
+            0xB3 => 1,
+            0xB4 => 0,
+            0xB5 => 0,
+            0xB6 => 0,
+            0xB7 => 0,
+            0xB8 => 0,
             0xB9 => 1,
+            0xBA => 0,
+            0xBB => 1,
             0xBC => 1,
             0xBD => 1,
             0xBE => 1,
+            0xBF => 1,
+            0xC0 => 0,
+            0xC1 => 0,
+            0xC2 => 0,
+            0xC3 => 0,
+            0xC4 => 0,
+            0xC5 => 0,
+            0xC6 => 0,
+            0xC7 => 0,
+            0xC8 => 0,
+            0xC9 => 0,
+            0xCA => 0,
+            0xCB => 0,
+            0xCC => 0,
+            0xCD => 0,
+            0xCE => 0,
+            0xCF => 0,
+            0xD0 => 0,
             0xD1 => 1,
+            0xD2 => 0,
+            0xD3 => 0,
+            0xD4 => 0,
+            0xD5 => 0,
+            0xD6 => 0,
+            0xD7 => 0,
+            0xD8 => 0,
             0xD9 => 1,
+            0xDA => 0,
+            0xDB => 0,
+            0xDC => 1,
             0xDD => 1,
             0xDE => 0,
+            0xDF => 0,
+            0xE0 => 0,
+            0xE1 => 0,
+            0xE2 => 0,
+            0xE3 => 0,
+            0xE4 => 0,
+            0xE5 => 0,
+            0xE6 => 0,
+            0xE7 => 0,
+            0xE8 => 0,
+            0xE9 => 0,
+            0xEA => 0,
+            0xEB => 0,
             0xEC => 0,
             0xED => 0,
             0xEE => 0,
+            0xEF => 0,
+            0xF0 => 0,
             0xF1 => 1,
+            0xF2 => 0,
+            0xF3 => 0,
+            0xF4 => 0,
             0xF5 => 0,
             0xF6 => 0,
+            0xF7 => 0,
             0xF8 => 0,
             0xF9 => 1,
+            0xFA => 0,
+            0xFB => 0,
+            0xFC => 1,
             0xFD => 1,
             0xFE => 0,
-            _ => panic!("Unknown page crossed cycles for opcode {:#04X}", opcode),
-        }
-    }
-
-    fn instruction_size(&mut self, opcode: u8) -> u16 {
-        match opcode {
-            0x00 => 2,
-            0x01 => 2,
-            0x04 => 2,
-            0x05 => 2,
-            0x06 => 2,
-            0x08 => 1,
-            0x09 => 2,
-            0x0A => 1,
-            0x0D => 3,
-            0x0E => 3,
-            0x10 => 2,
-            0x11 => 2,
-            0x15 => 2,
-            0x16 => 2,
-            0x18 => 1,
-            0x19 => 3,
-            0x1D => 3,
-            0x1E => 3,
-            0x20 => 3,
-            0x21 => 2,
-            0x24 => 2,
-            0x25 => 2,
-            0x26 => 2,
-            0x28 => 1,
-            0x29 => 2,
-            0x2A => 1,
-            0x2C => 3,
-            0x2D => 3,
-            0x2E => 3,
-            0x30 => 2,
-            0x31 => 2,
-            0x35 => 2,
-            0x36 => 2,
-            0x38 => 1,
-            0x39 => 3,
-            0x3D => 3,
-            0x3E => 3,
-            0x40 => 1,
-            0x41 => 2,
-            0x44 => 2,
-            0x45 => 2,
-            0x46 => 2,
-            0x48 => 1,
-            0x49 => 2,
-            0x4A => 1,
-            0x4C => 3,
-            0x4D => 3,
-            0x4E => 3,
-            0x50 => 2,
-            0x51 => 2,
-            0x55 => 2,
-            0x56 => 2,
-            0x58 => 1,
-            0x59 => 3,
-            0x5D => 3,
-            0x5E => 3,
-            0x60 => 1,
-            0x61 => 2,
-            0x65 => 2,
-            0x66 => 2,
-            0x68 => 1,
-            0x69 => 2,
-            0x6A => 1,
-            0x6C => 3,
-            0x6D => 3,
-            0x6E => 3,
-            0x70 => 2,
-            0x71 => 2,
-            0x75 => 2,
-            0x76 => 2,
-            0x78 => 1,
-            0x79 => 3,
-            0x7D => 3,
-            0x7E => 3,
-            0x81 => 2,
-            0x84 => 2,
-            0x85 => 2,
-            0x86 => 2,
-            0x88 => 1,
-            0x8A => 1,
-            0x8C => 3,
-            0x8D => 3,
-            0x8E => 3,
-            0x90 => 2,
-            0x91 => 2,
-            0x94 => 2,
-            0x95 => 2,
-            0x96 => 2,
-            0x98 => 1,
-            0x99 => 3,
-            0x9A => 1,
-            0x9D => 3,
-            0xA0 => 2,
-            0xA1 => 2,
-            0xA2 => 2,
-            0xA4 => 2,
-            0xA5 => 2,
-            0xA6 => 2,
-            0xA8 => 1,
-            0xA9 => 2,
-            0xAA => 1,
-            0xAC => 3,
-            0xAD => 3,
-            0xAE => 3,
-            0xB0 => 2,
-            0xB1 => 2,
-            0xB4 => 2,
-            0xB5 => 2,
-            0xB6 => 2,
-            0xB8 => 1,
-            0xB9 => 3,
-            0xBA => 1,
-            0xBC => 3,
-            0xBD => 3,
-            0xBE => 3,
-            0xC0 => 2,
-            0xC1 => 2,
-            0xC4 => 2,
-            0xC5 => 2,
-            0xC6 => 2,
-            0xC8 => 1,
-            0xC9 => 2,
-            0xCA => 1,
-            0xCC => 3,
-            0xCD => 3,
-            0xCE => 3,
-            0xD0 => 2,
-            0xD1 => 2,
-            0xD5 => 2,
-            0xD6 => 2,
-            0xD8 => 1,
-            0xD9 => 3,
-            0xDD => 3,
-            0xDE => 3,
-            0xE0 => 2,
-            0xE1 => 2,
-            0xE4 => 2,
-            0xE5 => 2,
-            0xE6 => 2,
-            0xE8 => 1,
-            0xE9 => 2,
-            0xEA => 1,
-            0xEC => 3,
-            0xED => 3,
-            0xEE => 3,
-            0xF0 => 2,
-            0xF1 => 2,
-            0xF5 => 2,
-            0xF6 => 2,
-            0xF8 => 1,
-            0xF9 => 3,
-            0xFD => 3,
-            0xFE => 3,
-            _ => panic!("Unknown instruction size for opcode {:#04X}", opcode),
+            0xFF => 0,
         }
     }
 
@@ -730,189 +937,297 @@ impl<T: Memory> CPU<T> {
         match step_info.opcode {
             0x00 => self.brk(step_info),
             0x01 => self.ora(step_info),
+            0x02 => self.kil(step_info),
+            0x03 => self.slo(step_info),
             0x04 => self.nop(step_info),
             0x05 => self.ora(step_info),
             0x06 => self.asl(step_info),
+            0x07 => self.slo(step_info),
             0x08 => self.php(step_info),
             0x09 => self.ora(step_info),
             0x0A => self.asl(step_info),
+            0x0B => self.anc(step_info),
+            0x0C => self.nop(step_info),
             0x0D => self.ora(step_info),
             0x0E => self.asl(step_info),
+            0x0F => self.slo(step_info),
             0x10 => self.bpl(step_info),
             0x11 => self.ora(step_info),
+            0x12 => self.kil(step_info),
+            0x13 => self.slo(step_info),
+            0x14 => self.nop(step_info),
             0x15 => self.ora(step_info),
             0x16 => self.asl(step_info),
+            0x17 => self.slo(step_info),
             0x18 => self.clc(step_info),
             0x19 => self.ora(step_info),
+            0x1A => self.nop(step_info),
+            0x1B => self.slo(step_info),
+            0x1C => self.nop(step_info),
             0x1D => self.ora(step_info),
             0x1E => self.asl(step_info),
+            0x1F => self.slo(step_info),
             0x20 => self.jsr(step_info),
             0x21 => self.and(step_info),
+            0x22 => self.kil(step_info),
+            0x23 => self.rla(step_info),
             0x24 => self.bit(step_info),
             0x25 => self.and(step_info),
             0x26 => self.rol(step_info),
+            0x27 => self.rla(step_info),
             0x28 => self.plp(step_info),
             0x29 => self.and(step_info),
             0x2A => self.rol(step_info),
+            0x2B => self.anc(step_info),
             0x2C => self.bit(step_info),
             0x2D => self.and(step_info),
             0x2E => self.rol(step_info),
+            0x2F => self.rla(step_info),
             0x30 => self.bmi(step_info),
             0x31 => self.and(step_info),
+            0x32 => self.kil(step_info),
+            0x33 => self.rla(step_info),
+            0x34 => self.nop(step_info),
             0x35 => self.and(step_info),
             0x36 => self.rol(step_info),
+            0x37 => self.rla(step_info),
             0x38 => self.sec(step_info),
             0x39 => self.and(step_info),
+            0x3A => self.nop(step_info),
+            0x3B => self.rla(step_info),
+            0x3C => self.nop(step_info),
             0x3D => self.and(step_info),
             0x3E => self.rol(step_info),
+            0x3F => self.rla(step_info),
             0x40 => self.rti(step_info),
             0x41 => self.eor(step_info),
+            0x42 => self.kil(step_info),
+            0x43 => self.sre(step_info),
             0x44 => self.nop(step_info),
             0x45 => self.eor(step_info),
             0x46 => self.lsr(step_info),
+            0x47 => self.sre(step_info),
             0x48 => self.pha(step_info),
             0x49 => self.eor(step_info),
             0x4A => self.lsr(step_info),
+            0x4B => self.alr(step_info),
             0x4C => self.jmp(step_info),
             0x4D => self.eor(step_info),
             0x4E => self.lsr(step_info),
+            0x4F => self.sre(step_info),
             0x50 => self.bvc(step_info),
             0x51 => self.eor(step_info),
+            0x52 => self.kil(step_info),
+            0x53 => self.sre(step_info),
+            0x54 => self.nop(step_info),
             0x55 => self.eor(step_info),
             0x56 => self.lsr(step_info),
+            0x57 => self.sre(step_info),
             0x58 => self.cli(step_info),
             0x59 => self.eor(step_info),
+            0x5A => self.nop(step_info),
+            0x5B => self.sre(step_info),
+            0x5C => self.nop(step_info),
             0x5D => self.eor(step_info),
             0x5E => self.lsr(step_info),
+            0x5F => self.sre(step_info),
             0x60 => self.rts(step_info),
             0x61 => self.adc(step_info),
+            0x62 => self.kil(step_info),
+            0x63 => self.rra(step_info),
+            0x64 => self.nop(step_info),
             0x65 => self.adc(step_info),
             0x66 => self.ror(step_info),
+            0x67 => self.rra(step_info),
             0x68 => self.pla(step_info),
             0x69 => self.adc(step_info),
             0x6A => self.ror(step_info),
+            0x6B => self.arr(step_info),
             0x6C => self.jmp(step_info),
             0x6D => self.adc(step_info),
             0x6E => self.ror(step_info),
+            0x6F => self.rra(step_info),
             0x70 => self.bvs(step_info),
             0x71 => self.adc(step_info),
+            0x72 => self.kil(step_info),
+            0x73 => self.rra(step_info),
+            0x74 => self.nop(step_info),
             0x75 => self.adc(step_info),
             0x76 => self.ror(step_info),
+            0x77 => self.rra(step_info),
             0x78 => self.sei(step_info),
             0x79 => self.adc(step_info),
+            0x7A => self.nop(step_info),
+            0x7B => self.rra(step_info),
+            0x7C => self.nop(step_info),
             0x7D => self.adc(step_info),
             0x7E => self.ror(step_info),
+            0x7F => self.rra(step_info),
+            0x80 => self.nop(step_info),
             0x81 => self.sta(step_info),
+            0x82 => self.nop(step_info),
+            0x83 => self.sax(step_info),
             0x84 => self.sty(step_info),
             0x85 => self.sta(step_info),
             0x86 => self.stx(step_info),
+            0x87 => self.sax(step_info),
             0x88 => self.dey(step_info),
+            0x89 => self.nop(step_info),
             0x8A => self.txa(step_info),
+            0x8B => self.xaa(step_info),
             0x8C => self.sty(step_info),
             0x8D => self.sta(step_info),
             0x8E => self.stx(step_info),
+            0x8F => self.sax(step_info),
             0x90 => self.bcc(step_info),
             0x91 => self.sta(step_info),
+            0x92 => self.kil(step_info),
+            0x93 => self.ahx(step_info),
             0x94 => self.sty(step_info),
             0x95 => self.sta(step_info),
             0x96 => self.stx(step_info),
+            0x97 => self.sax(step_info),
             0x98 => self.tya(step_info),
             0x99 => self.sta(step_info),
             0x9A => self.txs(step_info),
+            0x9B => self.tas(step_info),
+            0x9C => self.shy(step_info),
             0x9D => self.sta(step_info),
+            0x9E => self.shx(step_info),
+            0x9F => self.ahx(step_info),
             0xA0 => self.ldy(step_info),
             0xA1 => self.lda(step_info),
             0xA2 => self.ldx(step_info),
+            0xA3 => self.lax(step_info),
             0xA4 => self.ldy(step_info),
             0xA5 => self.lda(step_info),
             0xA6 => self.ldx(step_info),
+            0xA7 => self.lax(step_info),
             0xA8 => self.tay(step_info),
             0xA9 => self.lda(step_info),
-            0xAC => self.ldy(step_info),
             0xAA => self.tax(step_info),
+            0xAB => self.lax(step_info),
+            0xAC => self.ldy(step_info),
             0xAD => self.lda(step_info),
             0xAE => self.ldx(step_info),
+            0xAF => self.lax(step_info),
             0xB0 => self.bcs(step_info),
             0xB1 => self.lda(step_info),
+            0xB2 => self.kil(step_info),
+            0xB3 => self.lax(step_info),
             0xB4 => self.ldy(step_info),
             0xB5 => self.lda(step_info),
             0xB6 => self.ldx(step_info),
+            0xB7 => self.lax(step_info),
             0xB8 => self.clv(step_info),
             0xB9 => self.lda(step_info),
             0xBA => self.tsx(step_info),
+            0xBB => self.las(step_info),
             0xBC => self.ldy(step_info),
             0xBD => self.lda(step_info),
             0xBE => self.ldx(step_info),
+            0xBF => self.lax(step_info),
             0xC0 => self.cpy(step_info),
             0xC1 => self.cmp(step_info),
+            0xC2 => self.nop(step_info),
+            0xC3 => self.dcp(step_info),
             0xC4 => self.cpy(step_info),
             0xC5 => self.cmp(step_info),
             0xC6 => self.dec(step_info),
+            0xC7 => self.dcp(step_info),
             0xC8 => self.iny(step_info),
             0xC9 => self.cmp(step_info),
             0xCA => self.dex(step_info),
+            0xCB => self.axs(step_info),
             0xCC => self.cpy(step_info),
             0xCD => self.cmp(step_info),
             0xCE => self.dec(step_info),
+            0xCF => self.dcp(step_info),
             0xD0 => self.bne(step_info),
             0xD1 => self.cmp(step_info),
+            0xD2 => self.kil(step_info),
+            0xD3 => self.dcp(step_info),
+            0xD4 => self.nop(step_info),
             0xD5 => self.cmp(step_info),
             0xD6 => self.dec(step_info),
+            0xD7 => self.dcp(step_info),
             0xD8 => self.cld(step_info),
             0xD9 => self.cmp(step_info),
+            0xDA => self.nop(step_info),
+            0xDB => self.dcp(step_info),
+            0xDC => self.nop(step_info),
             0xDD => self.cmp(step_info),
             0xDE => self.dec(step_info),
+            0xDF => self.dcp(step_info),
             0xE0 => self.cpx(step_info),
             0xE1 => self.sbc(step_info),
+            0xE2 => self.nop(step_info),
+            0xE3 => self.isb(step_info),
             0xE4 => self.cpx(step_info),
             0xE5 => self.sbc(step_info),
             0xE6 => self.inc(step_info),
+            0xE7 => self.isb(step_info),
             0xE8 => self.inx(step_info),
             0xE9 => self.sbc(step_info),
             0xEA => self.nop(step_info),
+            0xEB => self.sbc(step_info),
             0xEC => self.cpx(step_info),
             0xED => self.sbc(step_info),
             0xEE => self.inc(step_info),
+            0xEF => self.isb(step_info),
             0xF0 => self.beq(step_info),
             0xF1 => self.sbc(step_info),
+            0xF2 => self.kil(step_info),
+            0xF3 => self.isb(step_info),
+            0xF4 => self.nop(step_info),
             0xF5 => self.sbc(step_info),
             0xF6 => self.inc(step_info),
+            0xF7 => self.isb(step_info),
             0xF8 => self.sed(step_info),
             0xF9 => self.sbc(step_info),
+            0xFA => self.nop(step_info),
+            0xFB => self.isb(step_info),
+            0xFC => self.nop(step_info),
             0xFD => self.sbc(step_info),
             0xFE => self.inc(step_info),
-            _ => self.unimplemented(step_info),
+            0xFF => self.isb(step_info),
         }
     }
 
-    fn nmi(&mut self) {
+    // The sequence NMI and IRQ share. Only BRK and PHP push the status with the break flag set.
+    fn take_interrupt(&mut self) -> Interrupt {
         let pc = self.pc;
+        self.bus_read(pc);
+        self.bus_read(pc);
         self.push16(pc);
-        self.php(StepInfo{
-            opcode: 0,
-            address: 0,
-            addressing_mode: AddressingMode::Unknown,
-        });
-        self.pc = self.read16(0xFFFA);
+        let (interrupt, vector) = self.interrupt_vector();
+        let flags = self.get_flags() & !BREAK_FLAG;
+        self.push(flags);
         self.interrupt_disable_flag = true;
+        self.pc = self.read16(vector);
+        return interrupt;
     }
 
-    pub fn set_irq(&mut self) {
-        if !self.interrupt_disable_flag {
-            self.trigger_irq = true;
+    // The vector is picked after the PC is pushed. An NMI detected by then hijacks an IRQ or BRK,
+    // which then jumps to the NMI handler.
+    fn interrupt_vector(&mut self) -> (Interrupt, u16) {
+        if self.need_nmi {
+            self.need_nmi = false;
+            return (Interrupt::Nmi, 0xFFFA);
         }
+        return (Interrupt::Irq, 0xFFFE);
     }
 
-    fn irq(&mut self) {
-        let pc = self.pc;
-        self.push16(pc);
-        self.php(StepInfo{
-            opcode: 0,
-            address: 0,
-            addressing_mode: AddressingMode::Unknown,
-        });
-        self.pc = self.read16(0xFFFE);
-        self.interrupt_disable_flag = true;
+    // Sample the interrupt lines, the CPU does this at the end of every cycle.
+    fn poll_interrupts(&mut self) {
+        self.prev_need_nmi = self.need_nmi;
+        self.prev_run_irq = self.run_irq;
+        let nmi_line = self.mem.nmi_line();
+        if nmi_line && !self.nmi_line {
+            self.need_nmi = true;
+        }
+        self.nmi_line = nmi_line;
+        self.run_irq = self.mem.irq_line() && !self.interrupt_disable_flag;
     }
 
     fn set_negative(&mut self, value: u8) {
@@ -933,7 +1248,12 @@ impl<T: Memory> CPU<T> {
 
     //  Add with Carry
     fn adc(&mut self, step_info: StepInfo) {
-        let data = self.mem.read(step_info.address);
+        let data = self.bus_read(step_info.address);
+        self.add_with_carry(data);
+    }
+
+    // Shared by ADC and RRA
+    fn add_with_carry(&mut self, data: u8) {
         let result: u32 = (self.a as u32) + (data as u32) + (self.carry_flag as u32);
 
         if result > 0xFF {
@@ -955,7 +1275,7 @@ impl<T: Memory> CPU<T> {
     }
 
     fn and(&mut self, step_info: StepInfo) {
-        let data = self.mem.read(step_info.address);
+        let data = self.bus_read(step_info.address);
         self.a = self.a & data;
         let a = self.a;
         self.set_negative(a);
@@ -977,14 +1297,14 @@ impl<T: Memory> CPU<T> {
                 self.set_negative(a);
             },
             _ => {
-                let mut data = self.mem.read(step_info.address);
+                let mut data = self.read_for_modify(step_info.address);
                 if data & 0x80 == 0x80 {
                     self.carry_flag = true;
                 } else {
                     self.carry_flag = false;
                 }
                 data = data << 1;
-                self.mem.write(self.cycles, step_info.address, data);
+                self.bus_write(step_info.address, data);
                 self.set_zero(data);
                 self.set_negative(data);
              }
@@ -1018,7 +1338,7 @@ impl<T: Memory> CPU<T> {
 
     // test BITs
     fn bit(&mut self, step_info: StepInfo) {
-        let value = self.mem.read(step_info.address);
+        let value = self.bus_read(step_info.address);
 
         if (value >> 6) & 1 == 1 {
             self.overflow_flag = true;
@@ -1058,15 +1378,17 @@ impl<T: Memory> CPU<T> {
 
     // Break
     fn brk(&mut self, step_info: StepInfo) {
+        // BRK skips the byte after it, it is read and ignored.
+        self.bus_read(step_info.address);
         let pc = self.pc;
         self.push16(pc);
-        self.php(step_info);
-        self.sei(StepInfo{
-            opcode: 0,
-            address: 0,
-            addressing_mode: AddressingMode::Immediate,
-        });
-        self.pc = self.read16(0xFFFE);
+        let (_, vector) = self.interrupt_vector();
+        let flags = self.get_flags() | BREAK_FLAG;
+        self.push(flags);
+        self.interrupt_disable_flag = true;
+        self.pc = self.read16(vector);
+        // An NMI detected during the vector fetch is taken after the handler's first instruction.
+        self.prev_need_nmi = false;
     }
 
     // Branch if Overflow Clear
@@ -1097,7 +1419,7 @@ impl<T: Memory> CPU<T> {
 
     // Compare X Register
     fn cpx(&mut self, step_info: StepInfo) {
-        let data = self.mem.read(step_info.address);
+        let data = self.bus_read(step_info.address);
         let result: i32 = (self.x as i32) - (data as i32);
 
 
@@ -1113,7 +1435,7 @@ impl<T: Memory> CPU<T> {
 
     // Compare Y Register
     fn cpy(&mut self, step_info: StepInfo) {
-        let data = self.mem.read(step_info.address);
+        let data = self.bus_read(step_info.address);
         let result: i32 = (self.y as i32) - (data as i32);
 
 
@@ -1134,14 +1456,20 @@ impl<T: Memory> CPU<T> {
 
     // CoMPare accumulator
     fn cmp(&mut self, step_info: StepInfo) {
-        let data = self.mem.read(step_info.address);
-        let result: i32 = (self.a as i32) - (data as i32);
+        let data = self.bus_read(step_info.address);
+        let a = self.a;
+        self.compare(a, data);
+    }
+
+    // Shared by CMP and DCP
+    fn compare(&mut self, register: u8, data: u8) {
+        let result: i32 = (register as i32) - (data as i32);
 
 
         self.set_negative(result as u8);
         self.set_zero(result as u8);
 
-        if self.a >= data {
+        if register >= data {
             self.carry_flag = true;
         } else {
             self.carry_flag = false;
@@ -1150,11 +1478,11 @@ impl<T: Memory> CPU<T> {
 
     // Decrement Memory
     fn dec(&mut self, step_info: StepInfo) {
-        let mut data = self.mem.read(step_info.address);
+        let mut data = self.read_for_modify(step_info.address);
         data = data.wrapping_sub(1);
         self.set_negative(data);
         self.set_zero(data);
-        self.mem.write(self.cycles, step_info.address, data);
+        self.bus_write(step_info.address, data);
     }
 
     // Decrement X
@@ -1179,7 +1507,7 @@ impl<T: Memory> CPU<T> {
 
     // Exclusive OR
     fn eor(&mut self, step_info: StepInfo) {
-        let data = self.mem.read(step_info.address);
+        let data = self.bus_read(step_info.address);
         self.a = self.a ^ data;
 
         let a = self.a;
@@ -1189,11 +1517,11 @@ impl<T: Memory> CPU<T> {
 
     // Increment Memory
     fn inc(&mut self, step_info: StepInfo) {
-        let mut data = self.mem.read(step_info.address);
+        let mut data = self.read_for_modify(step_info.address);
         data = data.wrapping_add(1);
         self.set_negative(data);
         self.set_zero(data);
-        self.mem.write(self.cycles, step_info.address, data);
+        self.bus_write(step_info.address, data);
     }
 
     // Increment X Register
@@ -1218,7 +1546,12 @@ impl<T: Memory> CPU<T> {
 
      // Subtract with Carry
     fn sbc(&mut self, step_info: StepInfo) {
-        let data = self.mem.read(step_info.address);
+        let data = self.bus_read(step_info.address);
+        self.subtract_with_carry(data);
+    }
+
+    // Shared by SBC and ISB
+    fn subtract_with_carry(&mut self, data: u8) {
         let result: i32 = (self.a as i32) - (data as i32) - (1 - (self.carry_flag as i32));
         let resultu8: u8 =  (self.a as u8).wrapping_sub(data as u8).wrapping_sub(1 - (self.carry_flag as u8));
 
@@ -1297,7 +1630,7 @@ impl<T: Memory> CPU<T> {
 
     // Load Accumulator With Memory
     fn lda(&mut self, step_info: StepInfo) {
-        let value = self.mem.read(step_info.address);
+        let value = self.bus_read(step_info.address);
         self.set_negative(value);
         self.set_zero(value);
         self.a = value;
@@ -1305,7 +1638,7 @@ impl<T: Memory> CPU<T> {
 
     // Load X Index With Memory
     fn ldx(&mut self, step_info: StepInfo) {
-        let value = self.mem.read(step_info.address);
+        let value = self.bus_read(step_info.address);
         self.x = value;
 
         self.set_negative(value);
@@ -1314,7 +1647,7 @@ impl<T: Memory> CPU<T> {
 
     // Load Y Index With Memory
     fn ldy(&mut self, step_info: StepInfo) {
-        let value = self.mem.read(step_info.address);
+        let value = self.bus_read(step_info.address);
         self.y = value;
 
         self.set_negative(value);
@@ -1336,14 +1669,14 @@ impl<T: Memory> CPU<T> {
                 self.set_negative(a);
             },
             _ => {
-                let mut data = self.mem.read(step_info.address);
+                let mut data = self.read_for_modify(step_info.address);
                 if data & 1 == 1 {
                     self.carry_flag = true;
                 } else {
                     self.carry_flag = false;
                 }
                 data = data >> 1;
-                self.mem.write(self.cycles, step_info.address, data);
+                self.bus_write(step_info.address, data);
                 self.set_zero(data);
                 self.set_negative(data);
              }
@@ -1351,13 +1684,19 @@ impl<T: Memory> CPU<T> {
     }
 
     // Nop does nothing
-    fn nop(&mut self, _: StepInfo) {
-
+    fn nop(&mut self, step_info: StepInfo) {
+        // The unofficial NOPs with an operand still read it.
+        match step_info.addressing_mode {
+            AddressingMode::Implied => {},
+            _ => {
+                self.bus_read(step_info.address);
+            },
+        }
     }
 
     // Logical Inclusive OR
     fn ora(&mut self, step_info: StepInfo) {
-        let data = self.mem.read(step_info.address);
+        let data = self.bus_read(step_info.address);
         self.a = self.a | data;
 
         let a = self.a;
@@ -1373,12 +1712,13 @@ impl<T: Memory> CPU<T> {
 
     // Push Processor Status
     fn php(&mut self, _: StepInfo) {
-        let flags = self.get_flags();
+        let flags = self.get_flags() | BREAK_FLAG;
         self.push(flags);
     }
 
     // Pull Accumulator
     fn pla(&mut self, _: StepInfo) {
+        self.stack_dummy_read();
         self.a = self.pop();
         let a = self.a;
         self.set_negative(a);
@@ -1387,6 +1727,7 @@ impl<T: Memory> CPU<T> {
 
     // Pull Processor Status
     fn plp(&mut self, _: StepInfo) {
+        self.stack_dummy_read();
         let flags = self.pop();
         self.set_flags(flags);
     }
@@ -1416,6 +1757,7 @@ impl<T: Memory> CPU<T> {
     // Jump to SubRoutine
     fn jsr(&mut self, step_info: StepInfo) {
         let push_address = self.pc - 1;
+        self.stack_dummy_read();
         self.push16(push_address);
 
         self.pc = step_info.address;
@@ -1443,14 +1785,14 @@ impl<T: Memory> CPU<T> {
             },
             _ => {
                 let old_carry = self.carry_flag as u8;
-                let mut data = self.mem.read(step_info.address);
+                let mut data = self.read_for_modify(step_info.address);
                 if data & 0x80 == 0x80 {
                     self.carry_flag = true;
                 } else {
                     self.carry_flag = false;
                 }
                 data = (data << 1) | (old_carry);
-                self.mem.write(self.cycles, step_info.address, data);
+                self.bus_write(step_info.address, data);
                 self.set_negative(data);
                 self.set_zero(data);
             },
@@ -1474,14 +1816,14 @@ impl<T: Memory> CPU<T> {
             },
             _ => {
                 let old_carry = self.carry_flag as u8;
-                let mut data = self.mem.read(step_info.address);
+                let mut data = self.read_for_modify(step_info.address);
                 if data & 1 == 1 {
                     self.carry_flag = true;
                 } else {
                     self.carry_flag = false;
                 }
                 data = (data >> 1) | (old_carry << 7);
-                self.mem.write(self.cycles, step_info.address, data);
+                self.bus_write(step_info.address, data);
                 self.set_negative(data);
                 self.set_zero(data);
             },
@@ -1490,6 +1832,7 @@ impl<T: Memory> CPU<T> {
 
     // Return from Interrupt
     fn rti(&mut self, _: StepInfo) {
+        self.stack_dummy_read();
         let flags = self.pop();
         self.set_flags(flags);
         self.pc = self.pop16();
@@ -1497,7 +1840,12 @@ impl<T: Memory> CPU<T> {
 
     // RTS - Return from Subroutine
     fn rts(&mut self, _: StepInfo) {
-        self.pc = self.pop16() + 1;
+        self.stack_dummy_read();
+        self.pc = self.pop16();
+        // Read while the return address is incremented.
+        let pc = self.pc;
+        self.bus_read(pc);
+        self.pc += 1;
     }
 
     // Set Decimal Flag
@@ -1507,17 +1855,17 @@ impl<T: Memory> CPU<T> {
 
     // Store Accumulator In Memory
     fn sta(&mut self, step_info: StepInfo) {
-        self.mem.write(self.cycles, step_info.address, self.a);
+        self.bus_write(step_info.address, self.a);
     }
 
     // Store X Index In Memory
     fn stx(&mut self, step_info: StepInfo) {
-        self.mem.write(self.cycles, step_info.address, self.x);
+        self.bus_write(step_info.address, self.x);
     }
 
     // Store Y Index In Memory
     fn sty(&mut self, step_info: StepInfo) {
-        self.mem.write(self.cycles, step_info.address, self.y);
+        self.bus_write(step_info.address, self.y);
     }
 
     // Jump to address
@@ -1525,9 +1873,218 @@ impl<T: Memory> CPU<T> {
         self.pc = step_info.address;
     }
 
-    // For unimplemented instructions
-    fn unimplemented(&mut self, step_info: StepInfo) {
-        panic!("opcode {:#04X} not implemented", step_info.opcode);
+    // Unofficial opcodes. Most of them run two official instructions back to back on the same operand.
+    // https://wiki.nesdev.com/w/index.php/CPU_unofficial_opcodes
+
+    // AND with the immediate value, then copy the negative flag to the carry flag
+    fn anc(&mut self, step_info: StepInfo) {
+        self.and(step_info);
+        self.carry_flag = self.negative_flag;
+    }
+
+    // AND with the immediate value, then LSR the accumulator
+    fn alr(&mut self, step_info: StepInfo) {
+        let data = self.bus_read(step_info.address);
+        self.a = self.a & data;
+        if self.a & 1 == 1 {
+            self.carry_flag = true;
+        } else {
+            self.carry_flag = false;
+        }
+        self.a = self.a >> 1;
+        let a = self.a;
+        self.set_negative(a);
+        self.set_zero(a);
+    }
+
+    // AND with the immediate value, then ROR the accumulator. The carry and overflow flags
+    // come from bits 6 and 5 of the result instead of the usual ROR and ADC rules.
+    fn arr(&mut self, step_info: StepInfo) {
+        let data = self.bus_read(step_info.address);
+        let old_carry = self.carry_flag as u8;
+        self.a = ((self.a & data) >> 1) | (old_carry << 7);
+        let a = self.a;
+        self.set_negative(a);
+        self.set_zero(a);
+        self.carry_flag = (a >> 6) & 1 == 1;
+        self.overflow_flag = ((a >> 6) ^ (a >> 5)) & 1 == 1;
+    }
+
+    // Subtract the immediate value from A AND X, without borrow, and store the result in X
+    fn axs(&mut self, step_info: StepInfo) {
+        let data = self.bus_read(step_info.address);
+        let value = self.a & self.x;
+        if value >= data {
+            self.carry_flag = true;
+        } else {
+            self.carry_flag = false;
+        }
+        self.x = value.wrapping_sub(data);
+        let x = self.x;
+        self.set_negative(x);
+        self.set_zero(x);
+    }
+
+    // Load the accumulator and X with the same value
+    fn lax(&mut self, step_info: StepInfo) {
+        let value = self.bus_read(step_info.address);
+        self.a = value;
+        self.x = value;
+        self.set_negative(value);
+        self.set_zero(value);
+    }
+
+    // Store A AND X
+    fn sax(&mut self, step_info: StepInfo) {
+        let value = self.a & self.x;
+        self.bus_write(step_info.address, value);
+    }
+
+    // DEC memory, then CMP it with the accumulator
+    fn dcp(&mut self, step_info: StepInfo) {
+        let data = self.read_for_modify(step_info.address).wrapping_sub(1);
+        self.bus_write(step_info.address, data);
+        let a = self.a;
+        self.compare(a, data);
+    }
+
+    // INC memory, then SBC it from the accumulator
+    fn isb(&mut self, step_info: StepInfo) {
+        let data = self.read_for_modify(step_info.address).wrapping_add(1);
+        self.bus_write(step_info.address, data);
+        self.subtract_with_carry(data);
+    }
+
+    // ASL memory, then ORA it with the accumulator
+    fn slo(&mut self, step_info: StepInfo) {
+        let mut data = self.read_for_modify(step_info.address);
+        if data & 0x80 == 0x80 {
+            self.carry_flag = true;
+        } else {
+            self.carry_flag = false;
+        }
+        data = data << 1;
+        self.bus_write(step_info.address, data);
+        self.a = self.a | data;
+        let a = self.a;
+        self.set_negative(a);
+        self.set_zero(a);
+    }
+
+    // ROL memory, then AND it with the accumulator
+    fn rla(&mut self, step_info: StepInfo) {
+        let old_carry = self.carry_flag as u8;
+        let mut data = self.read_for_modify(step_info.address);
+        if data & 0x80 == 0x80 {
+            self.carry_flag = true;
+        } else {
+            self.carry_flag = false;
+        }
+        data = (data << 1) | old_carry;
+        self.bus_write(step_info.address, data);
+        self.a = self.a & data;
+        let a = self.a;
+        self.set_negative(a);
+        self.set_zero(a);
+    }
+
+    // LSR memory, then EOR it with the accumulator
+    fn sre(&mut self, step_info: StepInfo) {
+        let mut data = self.read_for_modify(step_info.address);
+        if data & 1 == 1 {
+            self.carry_flag = true;
+        } else {
+            self.carry_flag = false;
+        }
+        data = data >> 1;
+        self.bus_write(step_info.address, data);
+        self.a = self.a ^ data;
+        let a = self.a;
+        self.set_negative(a);
+        self.set_zero(a);
+    }
+
+    // ROR memory, then ADC it to the accumulator
+    fn rra(&mut self, step_info: StepInfo) {
+        let old_carry = self.carry_flag as u8;
+        let mut data = self.read_for_modify(step_info.address);
+        if data & 1 == 1 {
+            self.carry_flag = true;
+        } else {
+            self.carry_flag = false;
+        }
+        data = (data >> 1) | (old_carry << 7);
+        self.bus_write(step_info.address, data);
+        self.add_with_carry(data);
+    }
+
+    // The unstable opcodes below depend on analog effects in the CPU. These follow the
+    // behavior documented for the 2A03, which is what test ROMs expect.
+
+    // A = (A OR CONST) AND X AND the immediate value. CONST is 0xFF on most NES consoles.
+    fn xaa(&mut self, step_info: StepInfo) {
+        let data = self.bus_read(step_info.address);
+        self.a = (self.a | 0xFF) & self.x & data;
+        let a = self.a;
+        self.set_negative(a);
+        self.set_zero(a);
+    }
+
+    // Store A AND X AND the high byte of the address plus one
+    fn ahx(&mut self, step_info: StepInfo) {
+        let value = self.a & self.x;
+        let y = self.y;
+        self.store_and_high_byte(step_info, value, y);
+    }
+
+    // Store X AND the high byte of the address plus one
+    fn shx(&mut self, step_info: StepInfo) {
+        let x = self.x;
+        let y = self.y;
+        self.store_and_high_byte(step_info, x, y);
+    }
+
+    // Store Y AND the high byte of the address plus one
+    fn shy(&mut self, step_info: StepInfo) {
+        let y = self.y;
+        let x = self.x;
+        self.store_and_high_byte(step_info, y, x);
+    }
+
+    // Set the stack pointer to A AND X, then store it AND the high byte of the address plus one
+    fn tas(&mut self, step_info: StepInfo) {
+        self.sp = self.a & self.x;
+        let sp = self.sp;
+        let y = self.y;
+        self.store_and_high_byte(step_info, sp, y);
+    }
+
+    // Load A, X and the stack pointer with memory AND the stack pointer
+    fn las(&mut self, step_info: StepInfo) {
+        let value = self.bus_read(step_info.address) & self.sp;
+        self.a = value;
+        self.x = value;
+        self.sp = value;
+        self.set_negative(value);
+        self.set_zero(value);
+    }
+
+    // Locks up the CPU, it keeps running the same opcode until the console is reset.
+    fn kil(&mut self, _: StepInfo) {
+        self.pc -= 1;
+    }
+
+    // Shared by AHX, SHX, SHY and TAS. The value is ANDed with the high byte of the base address
+    // plus one, and if adding the index crossed a page the result also replaces the high byte
+    // of the address that is written to.
+    fn store_and_high_byte(&mut self, step_info: StepInfo, value: u8, index: u8) {
+        let base_address = step_info.address.wrapping_sub(index as u16);
+        let data = value & ((base_address >> 8) as u8).wrapping_add(1);
+        let mut address = step_info.address;
+        if base_address&0xFF00 != address&0xFF00 {
+            address = ((data as u16) << 8) | (address & 0x00FF);
+        }
+        self.bus_write(address, data);
     }
  
     // Push a 16 bit value onto the stack
@@ -1540,14 +2097,14 @@ impl<T: Memory> CPU<T> {
 
     // Push a value onto the stack
     fn push(&mut self, value: u8) {
-        self.mem.write(self.cycles, 0x0100 | self.sp as u16, value);
-        self.sp -= 1;
+        self.bus_write(0x0100 | self.sp as u16, value);
+        self.sp = self.sp.wrapping_sub(1);
     }
 
     // Pop a value from the stack
     fn pop(&mut self) -> u8 {
-        self.sp += 1;
-        return self.mem.read(0x0100 | self.sp as u16);
+        self.sp = self.sp.wrapping_add(1);
+        return self.bus_read(0x0100 | self.sp as u16);
     }
 
     // Push a 16 bit value from the stack
@@ -1562,9 +2119,84 @@ impl<T: Memory> CPU<T> {
         return self.mem.read(addr);
     }
 
+    // Every bus access takes a CPU cycle, the rest of the system is advanced before it happens.
+    // A DMC fetch can halt the CPU for a few more cycles before the access.
+    fn tick(&mut self, cycle: BusCycle) {
+        let halted = self.mem.tick(cycle) as u64;
+        self.cycles += 1 + halted;
+        self.dmc_halted_cycles += halted;
+    }
+
+    fn bus_read(&mut self, addr: u16) -> u8 {
+        self.tick(BusCycle::Read);
+        let value = self.mem.read(addr);
+        self.poll_interrupts();
+        return value;
+    }
+
+    fn bus_write(&mut self, addr: u16, data: u8) {
+        self.tick(BusCycle::Write);
+        self.mem.write(addr, data);
+        self.poll_interrupts();
+    }
+
+    // A cycle where the CPU is halted by DMA.
+    fn halted_cycle(&mut self) {
+        self.tick(BusCycle::Halted);
+        self.poll_interrupts();
+    }
+
+    // OAM DMA halts the CPU and copies a page to OAM through $2004, reading a byte on one cycle
+    // and writing it on the next. It takes a cycle to halt the CPU, and another to line up
+    // with the reads if it would start on the wrong cycle, 513 or 514 cycles in all.
+    // https://wiki.nesdev.com/w/index.php/PPU_registers#OAMDMA
+    fn oam_dma(&mut self, page: u8) {
+        let odd_cycle = self.cycles % 2 == 1;
+        self.halted_cycle();
+        if odd_cycle {
+            self.halted_cycle();
+        }
+        let mut address = (page as u16) << 8;
+        for _ in 0..256 {
+            self.tick(BusCycle::Halted);
+            let value = self.mem.read(address);
+            self.poll_interrupts();
+            self.tick(BusCycle::Halted);
+            self.mem.write(0x2004, value);
+            self.poll_interrupts();
+            address = address.wrapping_add(1);
+        }
+    }
+
+    // A cycle where the bus access has no effect.
+    fn idle_cycle(&mut self) {
+        self.tick(BusCycle::Read);
+        self.poll_interrupts();
+    }
+
+    // Read-modify-write instructions write the unmodified value back while they modify it.
+    fn read_for_modify(&mut self, addr: u16) -> u8 {
+        let data = self.bus_read(addr);
+        self.bus_write(addr, data);
+        return data;
+    }
+
+    // Read from the stack while the stack pointer is incremented or before a push.
+    fn stack_dummy_read(&mut self) {
+        self.bus_read(0x0100 | self.sp as u16);
+    }
+
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        return self.mem.peek(addr);
+    }
+
+    pub fn poke(&mut self, addr: u16, data: u8) -> bool {
+        return self.mem.poke(addr, data);
+    }
+
     fn read16(&mut self, addr: u16) -> u16 {
-        let lo = self.mem.read(addr);
-        let hi = self.mem.read(addr + 1);
+        let lo = self.bus_read(addr);
+        let hi = self.bus_read(addr + 1);
         return ((hi as u16) << 8) | lo as u16;
     }
 
@@ -1573,7 +2205,9 @@ impl<T: Memory> CPU<T> {
     fn read16_low_byte_wrap(&mut self, addr: u16) -> u16 {
         let addr_low = addr as u16;
         let addr_hi = (addr & 0xFF00) | ((addr as u8).wrapping_add(1)) as u16;
-        return ((self.mem.read(addr_hi) as u16) << 8) | (self.mem.read(addr_low) as u16);
+        let lo = self.bus_read(addr_low);
+        let hi = self.bus_read(addr_hi);
+        return ((hi as u16) << 8) | lo as u16;
     }
 
     // read 16 bits from zero page address. This includes wrap around for 0xFF
@@ -1583,10 +2217,870 @@ impl<T: Memory> CPU<T> {
         if addr != 0xFF {
             addr_hi = (addr.wrapping_add(1)) as u16;
         };
-        return ((self.mem.read(addr_hi) as u16) << 8) | (self.mem.read(addr_low) as u16);
+        let lo = self.bus_read(addr_low);
+        let hi = self.bus_read(addr_hi);
+        return ((hi as u16) << 8) | lo as u16;
+    }
+}
+
+pub fn instruction_addressing_mode(opcode: u8) -> AddressingMode {
+    match opcode {
+        0x00 => AddressingMode::Immediate,
+        0x01 => AddressingMode::IndexedIndirect,
+        0x02 => AddressingMode::Implied,
+        0x03 => AddressingMode::IndexedIndirect,
+        0x04 => AddressingMode::ZeroPage,
+        0x05 => AddressingMode::ZeroPage,
+        0x06 => AddressingMode::ZeroPage,
+        0x07 => AddressingMode::ZeroPage,
+        0x08 => AddressingMode::Implied,
+        0x09 => AddressingMode::Immediate,
+        0x0A => AddressingMode::Accumulator,
+        0x0B => AddressingMode::Immediate,
+        0x0C => AddressingMode::Absolute,
+        0x0D => AddressingMode::Absolute,
+        0x0E => AddressingMode::Absolute,
+        0x0F => AddressingMode::Absolute,
+        0x10 => AddressingMode::Relative,
+        0x11 => AddressingMode::IndirectIndexed,
+        0x12 => AddressingMode::Implied,
+        0x13 => AddressingMode::IndirectIndexed,
+        0x14 => AddressingMode::ZeroPageX,
+        0x15 => AddressingMode::ZeroPageX,
+        0x16 => AddressingMode::ZeroPageX,
+        0x17 => AddressingMode::ZeroPageX,
+        0x18 => AddressingMode::Implied,
+        0x19 => AddressingMode::AbsoluteY,
+        0x1A => AddressingMode::Implied,
+        0x1B => AddressingMode::AbsoluteY,
+        0x1C => AddressingMode::AbsoluteX,
+        0x1D => AddressingMode::AbsoluteX,
+        0x1E => AddressingMode::AbsoluteX,
+        0x1F => AddressingMode::AbsoluteX,
+        0x20 => AddressingMode::Absolute,
+        0x21 => AddressingMode::IndexedIndirect,
+        0x22 => AddressingMode::Implied,
+        0x23 => AddressingMode::IndexedIndirect,
+        0x24 => AddressingMode::ZeroPage,
+        0x25 => AddressingMode::ZeroPage,
+        0x26 => AddressingMode::ZeroPage,
+        0x27 => AddressingMode::ZeroPage,
+        0x28 => AddressingMode::Implied,
+        0x29 => AddressingMode::Immediate,
+        0x2A => AddressingMode::Accumulator,
+        0x2B => AddressingMode::Immediate,
+        0x2C => AddressingMode::Absolute,
+        0x2D => AddressingMode::Absolute,
+        0x2E => AddressingMode::Absolute,
+        0x2F => AddressingMode::Absolute,
+        0x30 => AddressingMode::Relative,
+        0x31 => AddressingMode::IndirectIndexed,
+        0x32 => AddressingMode::Implied,
+        0x33 => AddressingMode::IndirectIndexed,
+        0x34 => AddressingMode::ZeroPageX,
+        0x35 => AddressingMode::ZeroPageX,
+        0x36 => AddressingMode::ZeroPageX,
+        0x37 => AddressingMode::ZeroPageX,
+        0x38 => AddressingMode::Implied,
+        0x39 => AddressingMode::AbsoluteY,
+        0x3A => AddressingMode::Implied,
+        0x3B => AddressingMode::AbsoluteY,
+        0x3C => AddressingMode::AbsoluteX,
+        0x3D => AddressingMode::AbsoluteX,
+        0x3E => AddressingMode::AbsoluteX,
+        0x3F => AddressingMode::AbsoluteX,
+        0x40 => AddressingMode::Implied,
+        0x41 => AddressingMode::IndexedIndirect,
+        0x42 => AddressingMode::Implied,
+        0x43 => AddressingMode::IndexedIndirect,
+        0x44 => AddressingMode::ZeroPage,
+        0x45 => AddressingMode::ZeroPage,
+        0x46 => AddressingMode::ZeroPage,
+        0x47 => AddressingMode::ZeroPage,
+        0x48 => AddressingMode::Implied,
+        0x49 => AddressingMode::Immediate,
+        0x4A => AddressingMode::Accumulator,
+        0x4B => AddressingMode::Immediate,
+        0x4C => AddressingMode::Absolute,
+        0x4D => AddressingMode::Absolute,
+        0x4E => AddressingMode::Absolute,
+        0x4F => AddressingMode::Absolute,
+        0x50 => AddressingMode::Relative,
+        0x51 => AddressingMode::IndirectIndexed,
+        0x52 => AddressingMode::Implied,
+        0x53 => AddressingMode::IndirectIndexed,
+        0x54 => AddressingMode::ZeroPageX,
+        0x55 => AddressingMode::ZeroPageX,
+        0x56 => AddressingMode::ZeroPageX,
+        0x57 => AddressingMode::ZeroPageX,
+        0x58 => AddressingMode::Implied,
+        0x59 => AddressingMode::AbsoluteY,
+        0x5A => AddressingMode::Implied,
+        0x5B => AddressingMode::AbsoluteY,
+        0x5C => AddressingMode::AbsoluteX,
+        0x5D => AddressingMode::AbsoluteX,
+        0x5E => AddressingMode::AbsoluteX,
+        0x5F => AddressingMode::AbsoluteX,
+        0x60 => AddressingMode::Implied,
+        0x61 => AddressingMode::IndexedIndirect,
+        0x62 => AddressingMode::Implied,
+        0x63 => AddressingMode::IndexedIndirect,
+        0x64 => AddressingMode::ZeroPage,
+        0x65 => AddressingMode::ZeroPage,
+        0x66 => AddressingMode::ZeroPage,
+        0x67 => AddressingMode::ZeroPage,
+        0x68 => AddressingMode::Implied,
+        0x69 => AddressingMode::Immediate,
+        0x6A => AddressingMode::Accumulator,
+        0x6B => AddressingMode::Immediate,
+        0x6C => AddressingMode::Indirect,
+        0x6D => AddressingMode::Absolute,
+        0x6E => AddressingMode::Absolute,
+        0x6F => AddressingMode::Absolute,
+        0x70 => AddressingMode::Relative,
+        0x71 => AddressingMode::IndirectIndexed,
+        0x72 => AddressingMode::Implied,
+        0x73 => AddressingMode::IndirectIndexed,
+        0x74 => AddressingMode::ZeroPageX,
+        0x75 => AddressingMode::ZeroPageX,
+        0x76 => AddressingMode::ZeroPageX,
+        0x77 => AddressingMode::ZeroPageX,
+        0x78 => AddressingMode::Implied,
+        0x79 => AddressingMode::AbsoluteY,
+        0x7A => AddressingMode::Implied,
+        0x7B => AddressingMode::AbsoluteY,
+        0x7C => AddressingMode::AbsoluteX,
+        0x7D => AddressingMode::AbsoluteX,
+        0x7E => AddressingMode::AbsoluteX,
+        0x7F => AddressingMode::AbsoluteX,
+        0x80 => AddressingMode::Immediate,
+        0x81 => AddressingMode::IndexedIndirect,
+        0x82 => AddressingMode::Immediate,
+        0x83 => AddressingMode::IndexedIndirect,
+        0x84 => AddressingMode::ZeroPage,
+        0x85 => AddressingMode::ZeroPage,
+        0x86 => AddressingMode::ZeroPage,
+        0x87 => AddressingMode::ZeroPage,
+        0x88 => AddressingMode::Implied,
+        0x89 => AddressingMode::Immediate,
+        0x8A => AddressingMode::Implied,
+        0x8B => AddressingMode::Immediate,
+        0x8C => AddressingMode::Absolute,
+        0x8D => AddressingMode::Absolute,
+        0x8E => AddressingMode::Absolute,
+        0x8F => AddressingMode::Absolute,
+        0x90 => AddressingMode::Relative,
+        0x91 => AddressingMode::IndirectIndexed,
+        0x92 => AddressingMode::Implied,
+        0x93 => AddressingMode::IndirectIndexed,
+        0x94 => AddressingMode::ZeroPageX,
+        0x95 => AddressingMode::ZeroPageX,
+        0x96 => AddressingMode::ZeroPageY,
+        0x97 => AddressingMode::ZeroPageY,
+        0x98 => AddressingMode::Implied,
+        0x99 => AddressingMode::AbsoluteY,
+        0x9A => AddressingMode::Implied,
+        0x9B => AddressingMode::AbsoluteY,
+        0x9C => AddressingMode::AbsoluteX,
+        0x9D => AddressingMode::AbsoluteX,
+        0x9E => AddressingMode::AbsoluteY,
+        0x9F => AddressingMode::AbsoluteY,
+        0xA0 => AddressingMode::Immediate,
+        0xA1 => AddressingMode::IndexedIndirect,
+        0xA2 => AddressingMode::Immediate,
+        0xA3 => AddressingMode::IndexedIndirect,
+        0xA4 => AddressingMode::ZeroPage,
+        0xA5 => AddressingMode::ZeroPage,
+        0xA6 => AddressingMode::ZeroPage,
+        0xA7 => AddressingMode::ZeroPage,
+        0xA8 => AddressingMode::Implied,
+        0xA9 => AddressingMode::Immediate,
+        0xAA => AddressingMode::Implied,
+        0xAB => AddressingMode::Immediate,
+        0xAC => AddressingMode::Absolute,
+        0xAD => AddressingMode::Absolute,
+        0xAE => AddressingMode::Absolute,
+        0xAF => AddressingMode::Absolute,
+        0xB0 => AddressingMode::Relative,
+        0xB1 => AddressingMode::IndirectIndexed,
+        0xB2 => AddressingMode::Implied,
+        0xB3 => AddressingMode::IndirectIndexed,
+        0xB4 => AddressingMode::ZeroPageX,
+        0xB5 => AddressingMode::ZeroPageX,
+        0xB6 => AddressingMode::ZeroPageY,
+        0xB7 => AddressingMode::ZeroPageY,
+        0xB8 => AddressingMode::Implied,
+        0xB9 => AddressingMode::AbsoluteY,
+        0xBA => AddressingMode::Implied,
+        0xBB => AddressingMode::AbsoluteY,
+        0xBC => AddressingMode::AbsoluteX,
+        0xBD => AddressingMode::AbsoluteX,
+        0xBE => AddressingMode::AbsoluteY,
+        0xBF => AddressingMode::AbsoluteY,
+        0xC0 => AddressingMode::Immediate,
+        0xC1 => AddressingMode::IndexedIndirect,
+        0xC2 => AddressingMode::Immediate,
+        0xC3 => AddressingMode::IndexedIndirect,
+        0xC4 => AddressingMode::ZeroPage,
+        0xC5 => AddressingMode::ZeroPage,
+        0xC6 => AddressingMode::ZeroPage,
+        0xC7 => AddressingMode::ZeroPage,
+        0xC8 => AddressingMode::Implied,
+        0xC9 => AddressingMode::Immediate,
+        0xCA => AddressingMode::Implied,
+        0xCB => AddressingMode::Immediate,
+        0xCC => AddressingMode::Absolute,
+        0xCD => AddressingMode::Absolute,
+        0xCE => AddressingMode::Absolute,
+        0xCF => AddressingMode::Absolute,
+        0xD0 => AddressingMode::Relative,
+        0xD1 => AddressingMode::IndirectIndexed,
+        0xD2 => AddressingMode::Implied,
+        0xD3 => AddressingMode::IndirectIndexed,
+        0xD4 => AddressingMode::ZeroPageX,
+        0xD5 => AddressingMode::ZeroPageX,
+        0xD6 => AddressingMode::ZeroPageX,
+        0xD7 => AddressingMode::ZeroPageX,
+        0xD8 => AddressingMode::Implied,
+        0xD9 => AddressingMode::AbsoluteY,
+        0xDA => AddressingMode::Implied,
+        0xDB => AddressingMode::AbsoluteY,
+        0xDC => AddressingMode::AbsoluteX,
+        0xDD => AddressingMode::AbsoluteX,
+        0xDE => AddressingMode::AbsoluteX,
+        0xDF => AddressingMode::AbsoluteX,
+        0xE0 => AddressingMode::Immediate,
+        0xE1 => AddressingMode::IndexedIndirect,
+        0xE2 => AddressingMode::Immediate,
+        0xE3 => AddressingMode::IndexedIndirect,
+        0xE4 => AddressingMode::ZeroPage,
+        0xE5 => AddressingMode::ZeroPage,
+        0xE6 => AddressingMode::ZeroPage,
+        0xE7 => AddressingMode::ZeroPage,
+        0xE8 => AddressingMode::Implied,
+        0xE9 => AddressingMode::Immediate,
+        0xEA => AddressingMode::Implied,
+        0xEB => AddressingMode::Immediate,
+        0xEC => AddressingMode::Absolute,
+        0xED => AddressingMode::Absolute,
+        0xEE => AddressingMode::Absolute,
+        0xEF => AddressingMode::Absolute,
+        0xF0 => AddressingMode::Relative,
+        0xF1 => AddressingMode::IndirectIndexed,
+        0xF2 => AddressingMode::Implied,
+        0xF3 => AddressingMode::IndirectIndexed,
+        0xF4 => AddressingMode::ZeroPageX,
+        0xF5 => AddressingMode::ZeroPageX,
+        0xF6 => AddressingMode::ZeroPageX,
+        0xF7 => AddressingMode::ZeroPageX,
+        0xF8 => AddressingMode::Implied,
+        0xF9 => AddressingMode::AbsoluteY,
+        0xFA => AddressingMode::Implied,
+        0xFB => AddressingMode::AbsoluteY,
+        0xFC => AddressingMode::AbsoluteX,
+        0xFD => AddressingMode::AbsoluteX,
+        0xFE => AddressingMode::AbsoluteX,
+        0xFF => AddressingMode::AbsoluteX,
+    }
+}
+
+pub fn instruction_size(opcode: u8) -> u16 {
+    match opcode {
+        0x00 => 2,
+        0x01 => 2,
+        0x02 => 1,
+        0x03 => 2,
+        0x04 => 2,
+        0x05 => 2,
+        0x06 => 2,
+        0x07 => 2,
+        0x08 => 1,
+        0x09 => 2,
+        0x0A => 1,
+        0x0B => 2,
+        0x0C => 3,
+        0x0D => 3,
+        0x0E => 3,
+        0x0F => 3,
+        0x10 => 2,
+        0x11 => 2,
+        0x12 => 1,
+        0x13 => 2,
+        0x14 => 2,
+        0x15 => 2,
+        0x16 => 2,
+        0x17 => 2,
+        0x18 => 1,
+        0x19 => 3,
+        0x1A => 1,
+        0x1B => 3,
+        0x1C => 3,
+        0x1D => 3,
+        0x1E => 3,
+        0x1F => 3,
+        0x20 => 3,
+        0x21 => 2,
+        0x22 => 1,
+        0x23 => 2,
+        0x24 => 2,
+        0x25 => 2,
+        0x26 => 2,
+        0x27 => 2,
+        0x28 => 1,
+        0x29 => 2,
+        0x2A => 1,
+        0x2B => 2,
+        0x2C => 3,
+        0x2D => 3,
+        0x2E => 3,
+        0x2F => 3,
+        0x30 => 2,
+        0x31 => 2,
+        0x32 => 1,
+        0x33 => 2,
+        0x34 => 2,
+        0x35 => 2,
+        0x36 => 2,
+        0x37 => 2,
+        0x38 => 1,
+        0x39 => 3,
+        0x3A => 1,
+        0x3B => 3,
+        0x3C => 3,
+        0x3D => 3,
+        0x3E => 3,
+        0x3F => 3,
+        0x40 => 1,
+        0x41 => 2,
+        0x42 => 1,
+        0x43 => 2,
+        0x44 => 2,
+        0x45 => 2,
+        0x46 => 2,
+        0x47 => 2,
+        0x48 => 1,
+        0x49 => 2,
+        0x4A => 1,
+        0x4B => 2,
+        0x4C => 3,
+        0x4D => 3,
+        0x4E => 3,
+        0x4F => 3,
+        0x50 => 2,
+        0x51 => 2,
+        0x52 => 1,
+        0x53 => 2,
+        0x54 => 2,
+        0x55 => 2,
+        0x56 => 2,
+        0x57 => 2,
+        0x58 => 1,
+        0x59 => 3,
+        0x5A => 1,
+        0x5B => 3,
+        0x5C => 3,
+        0x5D => 3,
+        0x5E => 3,
+        0x5F => 3,
+        0x60 => 1,
+        0x61 => 2,
+        0x62 => 1,
+        0x63 => 2,
+        0x64 => 2,
+        0x65 => 2,
+        0x66 => 2,
+        0x67 => 2,
+        0x68 => 1,
+        0x69 => 2,
+        0x6A => 1,
+        0x6B => 2,
+        0x6C => 3,
+        0x6D => 3,
+        0x6E => 3,
+        0x6F => 3,
+        0x70 => 2,
+        0x71 => 2,
+        0x72 => 1,
+        0x73 => 2,
+        0x74 => 2,
+        0x75 => 2,
+        0x76 => 2,
+        0x77 => 2,
+        0x78 => 1,
+        0x79 => 3,
+        0x7A => 1,
+        0x7B => 3,
+        0x7C => 3,
+        0x7D => 3,
+        0x7E => 3,
+        0x7F => 3,
+        0x80 => 2,
+        0x81 => 2,
+        0x82 => 2,
+        0x83 => 2,
+        0x84 => 2,
+        0x85 => 2,
+        0x86 => 2,
+        0x87 => 2,
+        0x88 => 1,
+        0x89 => 2,
+        0x8A => 1,
+        0x8B => 2,
+        0x8C => 3,
+        0x8D => 3,
+        0x8E => 3,
+        0x8F => 3,
+        0x90 => 2,
+        0x91 => 2,
+        0x92 => 1,
+        0x93 => 2,
+        0x94 => 2,
+        0x95 => 2,
+        0x96 => 2,
+        0x97 => 2,
+        0x98 => 1,
+        0x99 => 3,
+        0x9A => 1,
+        0x9B => 3,
+        0x9C => 3,
+        0x9D => 3,
+        0x9E => 3,
+        0x9F => 3,
+        0xA0 => 2,
+        0xA1 => 2,
+        0xA2 => 2,
+        0xA3 => 2,
+        0xA4 => 2,
+        0xA5 => 2,
+        0xA6 => 2,
+        0xA7 => 2,
+        0xA8 => 1,
+        0xA9 => 2,
+        0xAA => 1,
+        0xAB => 2,
+        0xAC => 3,
+        0xAD => 3,
+        0xAE => 3,
+        0xAF => 3,
+        0xB0 => 2,
+        0xB1 => 2,
+        0xB2 => 1,
+        0xB3 => 2,
+        0xB4 => 2,
+        0xB5 => 2,
+        0xB6 => 2,
+        0xB7 => 2,
+        0xB8 => 1,
+        0xB9 => 3,
+        0xBA => 1,
+        0xBB => 3,
+        0xBC => 3,
+        0xBD => 3,
+        0xBE => 3,
+        0xBF => 3,
+        0xC0 => 2,
+        0xC1 => 2,
+        0xC2 => 2,
+        0xC3 => 2,
+        0xC4 => 2,
+        0xC5 => 2,
+        0xC6 => 2,
+        0xC7 => 2,
+        0xC8 => 1,
+        0xC9 => 2,
+        0xCA => 1,
+        0xCB => 2,
+        0xCC => 3,
+        0xCD => 3,
+        0xCE => 3,
+        0xCF => 3,
+        0xD0 => 2,
+        0xD1 => 2,
+        0xD2 => 1,
+        0xD3 => 2,
+        0xD4 => 2,
+        0xD5 => 2,
+        0xD6 => 2,
+        0xD7 => 2,
+        0xD8 => 1,
+        0xD9 => 3,
+        0xDA => 1,
+        0xDB => 3,
+        0xDC => 3,
+        0xDD => 3,
+        0xDE => 3,
+        0xDF => 3,
+        0xE0 => 2,
+        0xE1 => 2,
+        0xE2 => 2,
+        0xE3 => 2,
+        0xE4 => 2,
+        0xE5 => 2,
+        0xE6 => 2,
+        0xE7 => 2,
+        0xE8 => 1,
+        0xE9 => 2,
+        0xEA => 1,
+        0xEB => 2,
+        0xEC => 3,
+        0xED => 3,
+        0xEE => 3,
+        0xEF => 3,
+        0xF0 => 2,
+        0xF1 => 2,
+        0xF2 => 1,
+        0xF3 => 2,
+        0xF4 => 2,
+        0xF5 => 2,
+        0xF6 => 2,
+        0xF7 => 2,
+        0xF8 => 1,
+        0xF9 => 3,
+        0xFA => 1,
+        0xFB => 3,
+        0xFC => 3,
+        0xFD => 3,
+        0xFE => 3,
+        0xFF => 3,
+    }
+}
+
+// The mnemonic for every opcode, including the unofficial ones.
+pub fn instruction_name(opcode: u8) -> &'static str {
+    match opcode {
+        0x00 => "BRK",
+        0x01 => "ORA",
+        0x02 => "KIL",
+        0x03 => "SLO",
+        0x04 => "NOP",
+        0x05 => "ORA",
+        0x06 => "ASL",
+        0x07 => "SLO",
+        0x08 => "PHP",
+        0x09 => "ORA",
+        0x0A => "ASL",
+        0x0B => "ANC",
+        0x0C => "NOP",
+        0x0D => "ORA",
+        0x0E => "ASL",
+        0x0F => "SLO",
+        0x10 => "BPL",
+        0x11 => "ORA",
+        0x12 => "KIL",
+        0x13 => "SLO",
+        0x14 => "NOP",
+        0x15 => "ORA",
+        0x16 => "ASL",
+        0x17 => "SLO",
+        0x18 => "CLC",
+        0x19 => "ORA",
+        0x1A => "NOP",
+        0x1B => "SLO",
+        0x1C => "NOP",
+        0x1D => "ORA",
+        0x1E => "ASL",
+        0x1F => "SLO",
+        0x20 => "JSR",
+        0x21 => "AND",
+        0x22 => "KIL",
+        0x23 => "RLA",
+        0x24 => "BIT",
+        0x25 => "AND",
+        0x26 => "ROL",
+        0x27 => "RLA",
+        0x28 => "PLP",
+        0x29 => "AND",
+        0x2A => "ROL",
+        0x2B => "ANC",
+        0x2C => "BIT",
+        0x2D => "AND",
+        0x2E => "ROL",
+        0x2F => "RLA",
+        0x30 => "BMI",
+        0x31 => "AND",
+        0x32 => "KIL",
+        0x33 => "RLA",
+        0x34 => "NOP",
+        0x35 => "AND",
+        0x36 => "ROL",
+        0x37 => "RLA",
+        0x38 => "SEC",
+        0x39 => "AND",
+        0x3A => "NOP",
+        0x3B => "RLA",
+        0x3C => "NOP",
+        0x3D => "AND",
+        0x3E => "ROL",
+        0x3F => "RLA",
+        0x40 => "RTI",
+        0x41 => "EOR",
+        0x42 => "KIL",
+        0x43 => "SRE",
+        0x44 => "NOP",
+        0x45 => "EOR",
+        0x46 => "LSR",
+        0x47 => "SRE",
+        0x48 => "PHA",
+        0x49 => "EOR",
+        0x4A => "LSR",
+        0x4B => "ALR",
+        0x4C => "JMP",
+        0x4D => "EOR",
+        0x4E => "LSR",
+        0x4F => "SRE",
+        0x50 => "BVC",
+        0x51 => "EOR",
+        0x52 => "KIL",
+        0x53 => "SRE",
+        0x54 => "NOP",
+        0x55 => "EOR",
+        0x56 => "LSR",
+        0x57 => "SRE",
+        0x58 => "CLI",
+        0x59 => "EOR",
+        0x5A => "NOP",
+        0x5B => "SRE",
+        0x5C => "NOP",
+        0x5D => "EOR",
+        0x5E => "LSR",
+        0x5F => "SRE",
+        0x60 => "RTS",
+        0x61 => "ADC",
+        0x62 => "KIL",
+        0x63 => "RRA",
+        0x64 => "NOP",
+        0x65 => "ADC",
+        0x66 => "ROR",
+        0x67 => "RRA",
+        0x68 => "PLA",
+        0x69 => "ADC",
+        0x6A => "ROR",
+        0x6B => "ARR",
+        0x6C => "JMP",
+        0x6D => "ADC",
+        0x6E => "ROR",
+        0x6F => "RRA",
+        0x70 => "BVS",
+        0x71 => "ADC",
+        0x72 => "KIL",
+        0x73 => "RRA",
+        0x74 => "NOP",
+        0x75 => "ADC",
+        0x76 => "ROR",
+        0x77 => "RRA",
+        0x78 => "SEI",
+        0x79 => "ADC",
+        0x7A => "NOP",
+        0x7B => "RRA",
+        0x7C => "NOP",
+        0x7D => "ADC",
+        0x7E => "ROR",
+        0x7F => "RRA",
+        0x80 => "NOP",
+        0x81 => "STA",
+        0x82 => "NOP",
+        0x83 => "SAX",
+        0x84 => "STY",
+        0x85 => "STA",
+        0x86 => "STX",
+        0x87 => "SAX",
+        0x88 => "DEY",
+        0x89 => "NOP",
+        0x8A => "TXA",
+        0x8B => "XAA",
+        0x8C => "STY",
+        0x8D => "STA",
+        0x8E => "STX",
+        0x8F => "SAX",
+        0x90 => "BCC",
+        0x91 => "STA",
+        0x92 => "KIL",
+        0x93 => "AHX",
+        0x94 => "STY",
+        0x95 => "STA",
+        0x96 => "STX",
+        0x97 => "SAX",
+        0x98 => "TYA",
+        0x99 => "STA",
+        0x9A => "TXS",
+        0x9B => "TAS",
+        0x9C => "SHY",
+        0x9D => "STA",
+        0x9E => "SHX",
+        0x9F => "AHX",
+        0xA0 => "LDY",
+        0xA1 => "LDA",
+        0xA2 => "LDX",
+        0xA3 => "LAX",
+        0xA4 => "LDY",
+        0xA5 => "LDA",
+        0xA6 => "LDX",
+        0xA7 => "LAX",
+        0xA8 => "TAY",
+        0xA9 => "LDA",
+        0xAA => "TAX",
+        0xAB => "LAX",
+        0xAC => "LDY",
+        0xAD => "LDA",
+        0xAE => "LDX",
+        0xAF => "LAX",
+        0xB0 => "BCS",
+        0xB1 => "LDA",
+        0xB2 => "KIL",
+        0xB3 => "LAX",
+        0xB4 => "LDY",
+        0xB5 => "LDA",
+        0xB6 => "LDX",
+        0xB7 => "LAX",
+        0xB8 => "CLV",
+        0xB9 => "LDA",
+        0xBA => "TSX",
+        0xBB => "LAS",
+        0xBC => "LDY",
+        0xBD => "LDA",
+        0xBE => "LDX",
+        0xBF => "LAX",
+        0xC0 => "CPY",
+        0xC1 => "CMP",
+        0xC2 => "NOP",
+        0xC3 => "DCP",
+        0xC4 => "CPY",
+        0xC5 => "CMP",
+        0xC6 => "DEC",
+        0xC7 => "DCP",
+        0xC8 => "INY",
+        0xC9 => "CMP",
+        0xCA => "DEX",
+        0xCB => "AXS",
+        0xCC => "CPY",
+        0xCD => "CMP",
+        0xCE => "DEC",
+        0xCF => "DCP",
+        0xD0 => "BNE",
+        0xD1 => "CMP",
+        0xD2 => "KIL",
+        0xD3 => "DCP",
+        0xD4 => "NOP",
+        0xD5 => "CMP",
+        0xD6 => "DEC",
+        0xD7 => "DCP",
+        0xD8 => "CLD",
+        0xD9 => "CMP",
+        0xDA => "NOP",
+        0xDB => "DCP",
+        0xDC => "NOP",
+        0xDD => "CMP",
+        0xDE => "DEC",
+        0xDF => "DCP",
+        0xE0 => "CPX",
+        0xE1 => "SBC",
+        0xE2 => "NOP",
+        0xE3 => "ISB",
+        0xE4 => "CPX",
+        0xE5 => "SBC",
+        0xE6 => "INC",
+        0xE7 => "ISB",
+        0xE8 => "INX",
+        0xE9 => "SBC",
+        0xEA => "NOP",
+        0xEB => "SBC",
+        0xEC => "CPX",
+        0xED => "SBC",
+        0xEE => "INC",
+        0xEF => "ISB",
+        0xF0 => "BEQ",
+        0xF1 => "SBC",
+        0xF2 => "KIL",
+        0xF3 => "ISB",
+        0xF4 => "NOP",
+        0xF5 => "SBC",
+        0xF6 => "INC",
+        0xF7 => "ISB",
+        0xF8 => "SED",
+        0xF9 => "SBC",
+        0xFA => "NOP",
+        0xFB => "ISB",
+        0xFC => "NOP",
+        0xFD => "SBC",
+        0xFE => "INC",
+        0xFF => "ISB",
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Registers {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    // The processor status flags as they would be pushed by PHP.
+    pub p: u8,
+    pub sp: u8,
+}
+
+pub type TraceHook = Box<dyn FnMut(&InstructionTrace)>;
+
+pub type MemoryHook = Box<dyn FnMut(&MemoryAccess)>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+// A read or write on the CPU or PPU bus, handed to a memory hook.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: u16,
+    // The value read or written.
+    pub value: u8,
+}
+
+// What the CPU does on the bus during a cycle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BusCycle {
+    Read,
+    Write,
+    // Halted for OAM DMA or a DMC sample fetch.
+    Halted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+}
+
+// The devices that can assert the IRQ line. It stays asserted until every one of them is acknowledged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IrqSource {
+    FrameCounter,
+    Dmc,
+    Mapper,
+}
+
+impl IrqSource {
+    fn mask(self) -> u8 {
+        match self {
+            IrqSource::FrameCounter => 1 << 0,
+            IrqSource::Dmc => 1 << 1,
+            IrqSource::Mapper => 1 << 2,
+        }
     }
 }
 
+// What the CPU is about to execute, handed to the trace hook before every instruction.
+#[derive(Clone, Copy, Debug)]
+pub struct InstructionTrace {
+    pub registers: Registers,
+    // The opcode and operand bytes. Only the first `size` bytes are part of the instruction.
+    pub bytes: [u8; 3],
+    pub size: u16,
+    // CPU cycles since power on.
+    pub cycles: u64,
+}
+
 #[derive(Clone)]
 struct StepInfo {
     opcode: u8,
@@ -1594,8 +3088,8 @@ struct StepInfo {
     addressing_mode: AddressingMode,
 }
 
-#[derive(Clone)]
-enum AddressingMode {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressingMode {
     Absolute,
     AbsoluteX,
     AbsoluteY,
@@ -1618,34 +3112,104 @@ pub struct CPUMemory {
     pub ppu: Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>,
     pub apu: Rc<RefCell<apu::APU>>,
     pub controller1: Rc<RefCell<controller::Controller>>,
-    pub added_stall: u32,
+    pub controller2: Rc<RefCell<controller::Controller>>,
+    // Halted cycles owed to a DMC fetch that landed on a write, paid on the next read.
+    pub dmc_stall: u32,
+    // Set by a write to $4014 until the CPU runs the DMA.
+    pub oam_dma_page: Option<u8>,
+    pub access_hook: Option<MemoryHook>,
+    // A bit per IrqSource that is asserting the IRQ line.
+    pub irq_sources: u8,
+    // The last value read or written on the data bus.
+    pub open_bus: u8,
+    // Set when the PPU starts a new frame, cleared by the Nes.
+    pub frame_change: bool,
+    // CPU cycles since power on, the APU runs some of its units on every other cycle.
+    pub cycle: u64,
 }
 
-impl Memory for CPUMemory {
-    fn read(&mut self, addr: u16) -> u8 {
+impl CPUMemory {
+    pub fn set_irq(&mut self, source: IrqSource, asserted: bool) {
+        if asserted {
+            self.irq_sources |= source.mask();
+        } else {
+            self.irq_sources &= !source.mask();
+        }
+    }
+
+
+    // Every read leaves its value on the data bus, except $4015 which is read inside the CPU.
+    fn read_bus(&mut self, addr: u16) -> u8 {
+        let value = self.read_device(addr);
+        if addr != 0x4015 {
+            self.open_bus = value;
+        }
+        return value;
+    }
+
+    // The APU's interrupt flags drive the IRQ line, this is called whenever they can change.
+    fn update_apu_irq(&mut self) {
+        let frame_irq = self.apu.borrow().frame_irq();
+        let dmc_irq = self.apu.borrow().dmc_irq();
+        self.set_irq(IrqSource::FrameCounter, frame_irq);
+        self.set_irq(IrqSource::Dmc, dmc_irq);
+    }
+
+    // The PPU runs 3 dots per CPU cycle and the mapper watches every dot.
+    fn step_system(&mut self, cycle: BusCycle) {
+        for _ in 0..3 {
+            let output = self.ppu.borrow_mut().step();
+            if output.frame_change {
+                self.frame_change = true;
+            }
+            self.mapper.borrow_mut().step(&self.ppu);
+        }
+        let mapper_irq = self.mapper.borrow().irq();
+        self.set_irq(IrqSource::Mapper, mapper_irq);
+        let apu = Rc::clone(&self.apu);
+        if apu.borrow_mut().step(self.cycle, &mut |addr| self.peek(addr)) {
+            self.dmc_stall += dmc_stall_cycles(cycle);
+        }
+        self.update_apu_irq();
+        self.cycle += 1;
+    }
+
+    // Run the halted cycles a DMC fetch is owed, returning how many there were.
+    fn run_dmc_stall(&mut self) -> u32 {
+        let mut halted = 0;
+        while self.dmc_stall > 0 {
+            self.dmc_stall -= 1;
+            self.step_system(BusCycle::Halted);
+            halted += 1;
+        }
+        return halted;
+    }
+
+    // Reads nothing answers, like the write only APU registers, $4018-$5FFF and PRG RAM a
+    // cartridge doesn't have, see the last value that was on the bus.
+    fn read_device(&mut self, addr: u16) -> u8 {
         if addr < 0x2000 {
             let ram_index = addr % 0x0800;
             return self.ram[ram_index as usize];
         } else if addr >= 0x2000 && addr < 0x4000 {
             return self.ppu.borrow_mut().read_register(addr);
-        } else if addr >= 0x4000 && addr < 0x4014 {
-            // TODO
         } else if addr == 0x4015 {
-            // TODO
+            let status = self.apu.borrow_mut().read_status();
+            self.update_apu_irq();
+            return (self.open_bus & 0x20) | status;
         } else if addr == 0x4016 {
-            return self.controller1.borrow_mut().read_next_button_state();
+            // The controllers only drive the low bits.
+            return (self.open_bus & 0xE0) | self.controller1.borrow_mut().read_next_button_state();
         } else if addr == 0x4017 {
-            // TODO
-        } else if addr < 0x4018 {
-            // TODO implement this
-            //unimplemented!();
-        } else if addr >= 0x6000 {
+            return (self.open_bus & 0xE0) | self.controller2.borrow_mut().read_next_button_state();
+        } else if addr >= 0x6000 && self.mapper.borrow_mut().decodes_read(addr) {
             return self.mapper.borrow_mut().read(addr);
-        } 
-        return 0;
+        }
+        return self.open_bus;
     }
 
-    fn write(&mut self, cycle: u64, addr: u16, data: u8) {
+    fn write_bus(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
         if addr < 0x2000 {
             let ram_index = addr % 0x0800;
             self.ram[ram_index as usize] = data;
@@ -1653,41 +3217,144 @@ impl Memory for CPUMemory {
             self.ppu.borrow_mut().write_register(addr, data);
         } else if addr >= 0x4000 && addr < 0x4014 {
             self.apu.borrow_mut().write_register(addr, data);
+            self.update_apu_irq();
         } else if addr == 0x4014 {
-            let mut address = (data as u16) << 8;
-            let mut oam_data = [0; 256];
-            for i in 0..256 {
-                oam_data[i] = self.read(address);
-                address += 1;
-            }
-            self.ppu.borrow_mut().wrote_to_register(data);
-            self.ppu.borrow_mut().write_oamdma(oam_data);
-            self.added_stall += 513;
-            if cycle%2 == 1 {
-                self.added_stall += 1;
-            }
+            self.oam_dma_page = Some(data);
         } else if addr == 0x4015 {
             self.apu.borrow_mut().write_register(addr, data);
+            self.update_apu_irq();
         } else if addr == 0x4016 {
+            // The strobe is shared by both controller ports.
             if data&1 == 1 {
                 self.controller1.borrow_mut().set_strobe(true);
+                self.controller2.borrow_mut().set_strobe(true);
             } else {
                 self.controller1.borrow_mut().set_strobe(false);
+                self.controller2.borrow_mut().set_strobe(false);
             }
         } else if addr == 0x4017 {
-            // TODO joy pad 2
             self.apu.borrow_mut().write_register(addr, data);
-        } else if addr < 0x4018 {
-            // TODO implement this
-            unimplemented!();
+            self.update_apu_irq();
         } else if addr >= 0x6000 {
             self.mapper.borrow_mut().write(addr, data);
         }
     }
+}
+
+// How long a DMC sample fetch halts the CPU. The CPU can only be halted on a read, so a fetch
+// during a write waits for it to finish, and one during OAM DMA slots in between its cycles.
+// https://wiki.nesdev.com/w/index.php/APU_DMC
+fn dmc_stall_cycles(cycle: BusCycle) -> u32 {
+    match cycle {
+        BusCycle::Read => 4,
+        BusCycle::Write => 3,
+        BusCycle::Halted => 2,
+    }
+}
+
+impl Memory for CPUMemory {
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.read_bus(addr);
+        if let Some(ref mut access_hook) = self.access_hook {
+            access_hook(&MemoryAccess{
+                kind: AccessKind::Read,
+                address: addr,
+                value: value,
+            });
+        }
+        return value;
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.write_bus(addr, data);
+        if let Some(ref mut access_hook) = self.access_hook {
+            access_hook(&MemoryAccess{
+                kind: AccessKind::Write,
+                address: addr,
+                value: data,
+            });
+        }
+    }
 
-    fn get_added_stall(&mut self) -> u32 {
-        let added_stall = self.added_stall;
-        self.added_stall = 0;
-        return added_stall;
+    fn take_oam_dma(&mut self) -> Option<u8> {
+        return self.oam_dma_page.take();
+    }
+
+    // A DMC fetch halts the CPU before the access it lands on, or before the next read if the
+    // CPU is writing, so the rest of the system runs on through the halted cycles.
+    fn tick(&mut self, cycle: BusCycle) -> u32 {
+        let mut halted = 0;
+        if cycle != BusCycle::Write {
+            halted += self.run_dmc_stall();
+        }
+        self.step_system(cycle);
+        if cycle != BusCycle::Write {
+            halted += self.run_dmc_stall();
+        }
+        return halted;
+    }
+
+    fn nmi_line(&mut self) -> bool {
+        return self.ppu.borrow().nmi_line();
+    }
+
+    fn irq_line(&mut self) -> bool {
+        return self.irq_sources != 0;
+    }
+
+    fn peek(&mut self, addr: u16) -> u8 {
+        if addr < 0x2000 {
+            let ram_index = addr % 0x0800;
+            return self.ram[ram_index as usize];
+        } else if addr >= 0x6000 {
+            return self.mapper.borrow_mut().read(addr);
+        }
+        return 0;
+    }
+
+    // The internal RAM and the cartridge's PRG RAM.
+    fn poke(&mut self, addr: u16, data: u8) -> bool {
+        if addr < 0x2000 {
+            let ram_index = addr % 0x0800;
+            self.ram[ram_index as usize] = data;
+            return true;
+        } else if addr >= 0x6000 && addr < 0x8000 {
+            let mut mapper = self.mapper.borrow_mut();
+            if mapper.get_prg_ram().is_empty() {
+                return false;
+            }
+            mapper.write(addr, data);
+            return true;
+        }
+        return false;
+    }
+
+    fn set_access_hook(&mut self, access_hook: Option<MemoryHook>) {
+        self.access_hook = access_hook;
+    }
+
+    // The PPU, APU, controllers and mapper are shared with the Nes, which saves them itself.
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u32(self.dmc_stall);
+        state.write_bool(self.oam_dma_page.is_some());
+        state.write_u8(self.oam_dma_page.unwrap_or(0));
+        state.write_u8(self.irq_sources);
+        state.write_u8(self.open_bus);
+        state.write_bool(self.frame_change);
+        state.write_u64(self.cycle);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.ram)?;
+        self.dmc_stall = state.read_u32()?;
+        let oam_dma_pending = state.read_bool()?;
+        let oam_dma_page = state.read_u8()?;
+        self.oam_dma_page = if oam_dma_pending { Some(oam_dma_page) } else { None };
+        self.irq_sources = state.read_u8()?;
+        self.open_bus = state.read_u8()?;
+        self.frame_change = state.read_bool()?;
+        self.cycle = state.read_u64()?;
+        return Ok(());
     }
 }
\ No newline at end of file
@@ -0,0 +1,290 @@
+// Explicit returns and field names, to match the emulator core.
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use cpu::{AccessKind, Interrupt, MemoryAccess};
+use nes::Nes;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const JSR: u8 = 0x20;
+const RTI: u8 = 0x40;
+const RTS: u8 = 0x60;
+const DOTS_PER_SCANLINE: u32 = 341;
+const SCANLINES_PER_FRAME: u32 = 262;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Breakpoint {
+    // Stop before the instruction at this address runs.
+    Execute(u16),
+    // Watchpoints, these stop after the instruction that made the access.
+    CpuRead(u16),
+    CpuWrite(u16),
+    PpuRead(u16),
+    PpuWrite(u16),
+    // Stop once the interrupt is taken, before the first instruction of its handler.
+    Nmi,
+    Irq,
+    // Stop before any instruction with this opcode runs.
+    Opcode(u8),
+    // Stop once the PPU reaches this scanline and dot.
+    Position(u16, u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    // The step, step over, step out or run to frame finished.
+    Done,
+    // The index of the breakpoint that was hit.
+    Breakpoint(usize),
+    // The index of the watchpoint that was hit and the access that hit it.
+    Watchpoint(usize, MemoryAccess),
+}
+
+// What happened in a single Nes::step.
+struct Step {
+    // The opcode at the PC before the step, only meaningful if an instruction ran.
+    opcode: u8,
+    instruction: bool,
+    interrupt: Option<Interrupt>,
+    frame_change: bool,
+}
+
+// Runs a Nes under control of breakpoints, watchpoints and stepping commands.
+pub struct Debugger {
+    console: Nes,
+    breakpoints: Vec<Breakpoint>,
+    // Accesses made during the current step, only collected while there are watchpoints.
+    cpu_accesses: Rc<RefCell<Vec<MemoryAccess>>>,
+    ppu_accesses: Rc<RefCell<Vec<MemoryAccess>>>,
+}
+
+impl Debugger {
+    pub fn new(console: Nes) -> Debugger {
+        return Debugger{
+            console: console,
+            breakpoints: Vec::new(),
+            cpu_accesses: Rc::new(RefCell::new(Vec::new())),
+            ppu_accesses: Rc::new(RefCell::new(Vec::new())),
+        };
+    }
+
+    pub fn console(&mut self) -> &mut Nes {
+        return &mut self.console;
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        return &self.breakpoints;
+    }
+
+    // Returns the index of the new breakpoint.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(breakpoint);
+        self.update_memory_hooks();
+        return self.breakpoints.len() - 1;
+    }
+
+    // The breakpoints after it move down one index.
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        if index >= self.breakpoints.len() {
+            return None;
+        }
+        let breakpoint = self.breakpoints.remove(index);
+        self.update_memory_hooks();
+        return Some(breakpoint);
+    }
+
+    // Run a single instruction, or take a pending interrupt.
+    pub fn step_into(&mut self) -> StopReason {
+        return self.run(&mut |_, step| step.instruction || step.interrupt.is_some());
+    }
+
+    // Like step_into, except a JSR runs until the subroutine returns.
+    pub fn step_over(&mut self) -> StopReason {
+        let registers = self.console.cpu().get_registers();
+        if self.console.peek(registers.pc) != JSR {
+            return self.step_into();
+        }
+        let return_address = registers.pc.wrapping_add(3);
+        return self.run(&mut |console, _| {
+            let current = console.cpu().get_registers();
+            current.pc == return_address && current.sp == registers.sp
+        });
+    }
+
+    // Run until the current subroutine or interrupt handler returns.
+    pub fn step_out(&mut self) -> StopReason {
+        let sp = self.console.cpu().get_registers().sp;
+        // Returning pops the stack above where it was when the routine was entered.
+        return self.run(&mut |console, step| {
+            step.instruction && (step.opcode == RTS || step.opcode == RTI) && console.cpu().get_registers().sp > sp
+        });
+    }
+
+    // Run until the PPU finishes the current frame.
+    pub fn run_to_frame(&mut self) -> StopReason {
+        return self.run(&mut |_, step| step.frame_change);
+    }
+
+    // Run until a breakpoint or watchpoint is hit.
+    pub fn resume(&mut self) -> StopReason {
+        return self.run(&mut |_, _| false);
+    }
+
+    fn run(&mut self, done: &mut dyn FnMut(&mut Nes, &Step) -> bool) -> StopReason {
+        // Don't stop on a breakpoint at the instruction execution is resuming from.
+        let mut started = false;
+        loop {
+            let pc = self.console.cpu().get_registers().pc;
+            let opcode = self.console.peek(pc);
+            if started {
+                if let Some(index) = self.breakpoint_before_instruction(pc, opcode) {
+                    return StopReason::Breakpoint(index);
+                }
+            }
+
+            let position = self.console.ppu_position();
+            let instruction_count = self.console.cpu().instruction_count();
+            let (_, frame_change) = self.console.step();
+            let step = Step{
+                opcode: opcode,
+                instruction: self.console.cpu().instruction_count() != instruction_count,
+                interrupt: self.console.cpu().last_interrupt(),
+                frame_change: frame_change,
+            };
+            if step.instruction || step.interrupt.is_some() {
+                started = true;
+            }
+
+            if let Some(reason) = self.breakpoint_after_step(&step, position) {
+                return reason;
+            }
+            if done(&mut self.console, &step) {
+                return StopReason::Done;
+            }
+        }
+    }
+
+    fn breakpoint_before_instruction(&self, pc: u16, opcode: u8) -> Option<usize> {
+        for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+            match *breakpoint {
+                Breakpoint::Execute(address) if address == pc => return Some(index),
+                Breakpoint::Opcode(op) if op == opcode => return Some(index),
+                _ => {},
+            }
+        }
+        return None;
+    }
+
+    fn breakpoint_after_step(&mut self, step: &Step, position: (u16, u16)) -> Option<StopReason> {
+        let cpu_accesses: Vec<MemoryAccess> = self.cpu_accesses.borrow_mut().drain(..).collect();
+        let ppu_accesses: Vec<MemoryAccess> = self.ppu_accesses.borrow_mut().drain(..).collect();
+        let (scanline, dot) = self.console.ppu_position();
+        let start = dot_index(position.0, position.1);
+        let dots_stepped = (dot_index(scanline, dot) + frame_dots() - start) % frame_dots();
+
+        for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+            match *breakpoint {
+                Breakpoint::CpuRead(address) => {
+                    if let Some(access) = find_cpu_access(&cpu_accesses, AccessKind::Read, address) {
+                        return Some(StopReason::Watchpoint(index, access));
+                    }
+                },
+                Breakpoint::CpuWrite(address) => {
+                    if let Some(access) = find_cpu_access(&cpu_accesses, AccessKind::Write, address) {
+                        return Some(StopReason::Watchpoint(index, access));
+                    }
+                },
+                Breakpoint::PpuRead(address) => {
+                    if let Some(access) = find_access(&ppu_accesses, AccessKind::Read, address) {
+                        return Some(StopReason::Watchpoint(index, access));
+                    }
+                },
+                Breakpoint::PpuWrite(address) => {
+                    if let Some(access) = find_access(&ppu_accesses, AccessKind::Write, address) {
+                        return Some(StopReason::Watchpoint(index, access));
+                    }
+                },
+                Breakpoint::Nmi if step.interrupt == Some(Interrupt::Nmi) => return Some(StopReason::Breakpoint(index)),
+                Breakpoint::Irq if step.interrupt == Some(Interrupt::Irq) => return Some(StopReason::Breakpoint(index)),
+                Breakpoint::Position(target_scanline, target_dot) => {
+                    // Whether the target was passed somewhere in the dots this step ran.
+                    let target = (dot_index(target_scanline, target_dot) + frame_dots() - start) % frame_dots();
+                    if target > 0 && target <= dots_stepped {
+                        return Some(StopReason::Breakpoint(index));
+                    }
+                },
+                _ => {},
+            }
+        }
+        return None;
+    }
+
+    // Only collect memory accesses while there is a watchpoint that needs them.
+    fn update_memory_hooks(&mut self) {
+        let mut watch_cpu = false;
+        let mut watch_ppu = false;
+        for breakpoint in self.breakpoints.iter() {
+            match *breakpoint {
+                Breakpoint::CpuRead(_) | Breakpoint::CpuWrite(_) => watch_cpu = true,
+                Breakpoint::PpuRead(_) | Breakpoint::PpuWrite(_) => watch_ppu = true,
+                _ => {},
+            }
+        }
+
+        if watch_cpu {
+            let cpu_accesses = Rc::clone(&self.cpu_accesses);
+            self.console.set_cpu_memory_hook(Some(Box::new(move |access: &MemoryAccess| {
+                cpu_accesses.borrow_mut().push(*access);
+            })));
+        } else {
+            self.console.set_cpu_memory_hook(None);
+        }
+        if watch_ppu {
+            let ppu_accesses = Rc::clone(&self.ppu_accesses);
+            self.console.set_ppu_memory_hook(Some(Box::new(move |access: &MemoryAccess| {
+                ppu_accesses.borrow_mut().push(*access);
+            })));
+        } else {
+            self.console.set_ppu_memory_hook(None);
+        }
+    }
+}
+
+// The internal RAM repeats every 2KB up to $1FFF and the PPU registers every 8 bytes up to
+// $3FFF, so a watchpoint on any mirror catches accesses through the others.
+fn cpu_mirror_base(address: u16) -> u16 {
+    if address < 0x2000 {
+        return address & 0x07FF;
+    } else if address < 0x4000 {
+        return 0x2000 | (address & 7);
+    }
+    return address;
+}
+
+fn find_access(accesses: &[MemoryAccess], kind: AccessKind, address: u16) -> Option<MemoryAccess> {
+    for access in accesses.iter() {
+        if access.kind == kind && access.address == address {
+            return Some(*access);
+        }
+    }
+    return None;
+}
+
+fn find_cpu_access(accesses: &[MemoryAccess], kind: AccessKind, address: u16) -> Option<MemoryAccess> {
+    let base = cpu_mirror_base(address);
+    for access in accesses.iter() {
+        if access.kind == kind && cpu_mirror_base(access.address) == base {
+            return Some(*access);
+        }
+    }
+    return None;
+}
+
+fn dot_index(scanline: u16, dot: u16) -> u32 {
+    return scanline as u32 * DOTS_PER_SCANLINE + dot as u32;
+}
+
+fn frame_dots() -> u32 {
+    return DOTS_PER_SCANLINE * SCANLINES_PER_FRAME;
+}
@@ -0,0 +1,248 @@
+// Decoding spells out its returns and indexes the instruction bytes directly.
+#![allow(
+    clippy::needless_range_loop,
+    clippy::needless_return,
+    clippy::new_without_default,
+    clippy::redundant_field_names,
+)]
+
+use cpu;
+use cpu::AddressingMode;
+
+use std::collections::HashMap;
+use std::fmt;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+// Names for the PPU, APU and I/O registers and the interrupt vectors.
+// https://wiki.nesdev.com/w/index.php/PPU_registers
+// https://wiki.nesdev.com/w/index.php/2A03
+const HARDWARE_LABELS: [(u16, &str); 35] = [
+    (0x2000, "PPUCTRL"),
+    (0x2001, "PPUMASK"),
+    (0x2002, "PPUSTATUS"),
+    (0x2003, "OAMADDR"),
+    (0x2004, "OAMDATA"),
+    (0x2005, "PPUSCROLL"),
+    (0x2006, "PPUADDR"),
+    (0x2007, "PPUDATA"),
+    (0x4000, "SQ1_VOL"),
+    (0x4001, "SQ1_SWEEP"),
+    (0x4002, "SQ1_LO"),
+    (0x4003, "SQ1_HI"),
+    (0x4004, "SQ2_VOL"),
+    (0x4005, "SQ2_SWEEP"),
+    (0x4006, "SQ2_LO"),
+    (0x4007, "SQ2_HI"),
+    (0x4008, "TRI_LINEAR"),
+    (0x4009, "APU_UNUSED1"),
+    (0x400A, "TRI_LO"),
+    (0x400B, "TRI_HI"),
+    (0x400C, "NOISE_VOL"),
+    (0x400D, "APU_UNUSED2"),
+    (0x400E, "NOISE_LO"),
+    (0x400F, "NOISE_HI"),
+    (0x4010, "DMC_FREQ"),
+    (0x4011, "DMC_RAW"),
+    (0x4012, "DMC_START"),
+    (0x4013, "DMC_LEN"),
+    (0x4014, "OAMDMA"),
+    (0x4015, "SND_CHN"),
+    (0x4016, "JOY1"),
+    (0x4017, "JOY2"),
+    (NMI_VECTOR, "NMI_VECTOR"),
+    (RESET_VECTOR, "RESET_VECTOR"),
+    (IRQ_VECTOR, "IRQ_VECTOR"),
+];
+
+// Names to show in place of addresses in disassembled operands.
+pub struct Labels {
+    names: HashMap<u16, String>,
+}
+
+impl Labels {
+    // No labels at all, every operand is shown as a plain address.
+    pub fn new() -> Labels {
+        return Labels{
+            names: HashMap::new(),
+        };
+    }
+
+    // The hardware registers and the interrupt vector addresses.
+    pub fn hardware() -> Labels {
+        let mut labels = Labels::new();
+        for &(address, name) in HARDWARE_LABELS.iter() {
+            labels.add(address, name);
+        }
+        return labels;
+    }
+
+    pub fn add(&mut self, address: u16, name: &str) {
+        self.names.insert(address, name.to_string());
+    }
+
+    // Label the handlers the interrupt vectors point at, reading the vectors with `read`.
+    pub fn add_vector_targets(&mut self, read: &mut dyn FnMut(u16) -> u8) {
+        let vectors = [(NMI_VECTOR, "NMI"), (RESET_VECTOR, "RESET"), (IRQ_VECTOR, "IRQ")];
+        for &(vector, name) in vectors.iter() {
+            let lo = read(vector) as u16;
+            let hi = read(vector + 1) as u16;
+            let target = (hi << 8) | lo;
+            // Games often point several vectors at the same handler, keep the first name.
+            if !self.names.contains_key(&target) {
+                self.add(target, name);
+            }
+        }
+    }
+
+    pub fn get(&self, address: u16) -> Option<&str> {
+        return self.names.get(&address).map(|name| name.as_str());
+    }
+}
+
+// A single decoded instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Instruction {
+    pub address: u16,
+    // The opcode and operand bytes. Only the first `size` bytes are part of the instruction.
+    pub bytes: [u8; 3],
+    pub size: u16,
+}
+
+impl Instruction {
+    // Decode the instruction at the start of `bytes`. Operand bytes past the end of `bytes` read as 0.
+    pub fn decode(address: u16, bytes: &[u8]) -> Instruction {
+        let mut instruction_bytes = [0; 3];
+        let size = cpu::instruction_size(bytes[0]);
+        let length = (size as usize).min(bytes.len());
+        instruction_bytes[..length].copy_from_slice(&bytes[..length]);
+        return Instruction{
+            address: address,
+            bytes: instruction_bytes,
+            size: size,
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return cpu::instruction_name(self.bytes[0]);
+    }
+
+    pub fn addressing_mode(&self) -> AddressingMode {
+        return cpu::instruction_addressing_mode(self.bytes[0]);
+    }
+
+    // The address the operand refers to, if it refers to one. For indirect jumps this is the pointer.
+    pub fn target(&self) -> Option<u16> {
+        let byte = self.bytes[1];
+        let word = ((self.bytes[2] as u16) << 8) | self.bytes[1] as u16;
+        match self.addressing_mode() {
+            AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::Indirect => Some(word),
+            AddressingMode::ZeroPage | AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => Some(byte as u16),
+            AddressingMode::IndexedIndirect | AddressingMode::IndirectIndexed => Some(byte as u16),
+            AddressingMode::Relative => Some(self.address.wrapping_add(2).wrapping_add(byte as i8 as u16)),
+            _ => None,
+        }
+    }
+
+    // The operand written the usual 6502 assembler way for its addressing mode, using a
+    // label in place of the address when there is one.
+    pub fn operand(&self, labels: &Labels) -> String {
+        let byte = self.bytes[1];
+        let word = ((self.bytes[2] as u16) << 8) | self.bytes[1] as u16;
+        let address = match self.target().and_then(|target| labels.get(target)) {
+            Some(label) => label.to_string(),
+            None => {
+                match self.addressing_mode() {
+                    AddressingMode::ZeroPage | AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => format!("${:02X}", byte),
+                    AddressingMode::IndexedIndirect | AddressingMode::IndirectIndexed => format!("${:02X}", byte),
+                    AddressingMode::Relative => format!("${:04X}", self.target().unwrap()),
+                    _ => format!("${:04X}", word),
+                }
+            },
+        };
+        match self.addressing_mode() {
+            AddressingMode::Absolute => address,
+            AddressingMode::AbsoluteX => format!("{},X", address),
+            AddressingMode::AbsoluteY => format!("{},Y", address),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::IndexedIndirect => format!("({},X)", address),
+            AddressingMode::Indirect => format!("({})", address),
+            AddressingMode::IndirectIndexed => format!("({}),Y", address),
+            AddressingMode::Immediate => format!("#${:02X}", byte),
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Relative => address,
+            AddressingMode::Unknown => String::new(),
+            AddressingMode::ZeroPage => address,
+            AddressingMode::ZeroPageX => format!("{},X", address),
+            AddressingMode::ZeroPageY => format!("{},Y", address),
+        }
+    }
+
+    // e.g. "STA PPUCTRL" or "LDA ($00),Y"
+    pub fn text(&self, labels: &Labels) -> String {
+        let operand = self.operand(labels);
+        if operand.is_empty() {
+            return self.name().to_string();
+        }
+        return format!("{} {}", self.name(), operand);
+    }
+
+    // The raw bytes, e.g. "4C F5 C5"
+    pub fn hex_bytes(&self) -> String {
+        let mut bytes = Vec::new();
+        for i in 0..self.size as usize {
+            bytes.push(format!("{:02X}", self.bytes[i]));
+        }
+        return bytes.join(" ");
+    }
+}
+
+// Without labels, e.g. "C000  4C F5 C5  JMP $C5F5"
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{:04X}  {:<8}  {}", self.address, self.hex_bytes(), self.text(&Labels::new()));
+    }
+}
+
+// Disassemble start..=end reading through `read`, e.g. the CPU bus. The last instruction
+// may run past `end`.
+pub fn disassemble(read: &mut dyn FnMut(u16) -> u8, start: u16, end: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut address = start as u32;
+    while address <= end as u32 {
+        let mut bytes = [0; 3];
+        for i in 0..3 {
+            bytes[i] = read((address as u16).wrapping_add(i as u16));
+        }
+        let instruction = Instruction::decode(address as u16, &bytes);
+        address += instruction.size as u32;
+        instructions.push(instruction);
+    }
+    return instructions;
+}
+
+// Disassemble a raw PRG ROM bank as if it was mapped in at base_address.
+pub fn disassemble_bank(bank: &[u8], base_address: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < bank.len() {
+        let instruction = Instruction::decode(base_address.wrapping_add(offset as u16), &bank[offset..]);
+        offset += instruction.size as usize;
+        instructions.push(instruction);
+    }
+    return instructions;
+}
+
+// A listing with a line per instruction and a "LABEL:" line before every labelled address.
+pub fn format_listing(instructions: &[Instruction], labels: &Labels) -> String {
+    let mut listing = String::new();
+    for instruction in instructions.iter() {
+        if let Some(label) = labels.get(instruction.address) {
+            listing.push_str(&format!("{}:\n", label));
+        }
+        listing.push_str(&format!("{:04X}  {:<8}  {}\n", instruction.address, instruction.hex_bytes(), instruction.text(labels)));
+    }
+    return listing;
+}
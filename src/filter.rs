@@ -0,0 +1,117 @@
+// First-order filters for the analog stage after the APU's mixer.
+// https://wiki.nesdev.com/w/index.php/APU_Mixer
+
+// Explicit returns and field names, like the APU these filters follow.
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use std::f64::consts::PI;
+
+// Which console's output circuit to emulate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterProfile {
+    // The mixer's output as is, DC offset included.
+    Raw,
+    // High-pass filters at 90Hz and 440Hz, then a low-pass filter at 14kHz.
+    Nes,
+    // A high-pass filter at 37Hz.
+    Famicom,
+}
+
+impl FilterProfile {
+    fn filters(&self) -> Vec<(FilterKind, f64)> {
+        match *self {
+            FilterProfile::Raw => Vec::new(),
+            FilterProfile::Nes => vec![
+                (FilterKind::HighPass, 90.0),
+                (FilterKind::HighPass, 440.0),
+                (FilterKind::LowPass, 14000.0),
+            ],
+            FilterProfile::Famicom => vec![(FilterKind::HighPass, 37.0)],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+struct Filter {
+    kind: FilterKind,
+    // The smoothing factor, from the cutoff frequency and the sample rate.
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl Filter {
+    fn new(kind: FilterKind, cutoff: f64, sample_rate: f64) -> Filter {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let alpha = match kind {
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+        return Filter{
+            kind: kind,
+            alpha: alpha as f32,
+            previous_input: 0.0,
+            previous_output: 0.0,
+        };
+    }
+
+    // Start as if `input` had been coming in for a long time, returning what comes out.
+    fn settle(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass => 0.0,
+            FilterKind::LowPass => input,
+        };
+        self.previous_input = input;
+        self.previous_output = output;
+        return output;
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass => self.alpha * (self.previous_output + input - self.previous_input),
+            FilterKind::LowPass => self.previous_output + self.alpha * (input - self.previous_output),
+        };
+        self.previous_input = input;
+        self.previous_output = output;
+        return output;
+    }
+}
+
+// The filters of a profile, run in order on each output sample.
+pub struct FilterChain {
+    filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    pub fn new(profile: FilterProfile, sample_rate: f64) -> FilterChain {
+        let mut filters = Vec::new();
+        for (kind, cutoff) in profile.filters() {
+            filters.push(Filter::new(kind, cutoff, sample_rate));
+        }
+        return FilterChain{
+            filters: filters,
+        };
+    }
+
+    // Start the filters on a steady `level`, so carrying on from it doesn't make a step.
+    pub fn settle(&mut self, level: f32) {
+        let mut output = level;
+        for filter in self.filters.iter_mut() {
+            output = filter.settle(output);
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let mut output = sample;
+        for filter in self.filters.iter_mut() {
+            output = filter.process(output);
+        }
+        return output;
+    }
+}
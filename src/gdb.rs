@@ -0,0 +1,551 @@
+// A GDB remote serial protocol server, so debugger frontends that speak the protocol can
+// attach to a Nes over TCP. https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+//
+// The registers are numbered a, x, y, p, sp and pc. The first five are 8 bits, pc is 16 bits
+// little endian. The layout is also described by the target.xml served through qXfer.
+
+// Packet handling returns explicitly from every branch, like the rest of the crate.
+#![allow(
+    clippy::manual_is_multiple_of,
+    clippy::needless_return,
+    clippy::redundant_field_names,
+)]
+
+use cpu::{AccessKind, Registers};
+use debugger::{Breakpoint, Debugger, StopReason};
+
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str;
+
+const INTERRUPT: u8 = 0x03;
+// The byte after an escape is XORed with 0x20, so $, #, } and * can appear in packet data.
+const ESCAPE: u8 = b'}';
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+// Limit memory reads to what fits in a reply packet.
+const PACKET_SIZE: usize = 0x1000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nese.6502">
+    <reg name="a" bitsize="8" regnum="0"/>
+    <reg name="x" bitsize="8"/>
+    <reg name="y" bitsize="8"/>
+    <reg name="p" bitsize="8"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+    // An acknowledgement of one of our replies, or a stray byte.
+    Ack,
+}
+
+// What to do after handling a packet.
+enum Response {
+    Reply(String),
+    // Execution was continued, the reply is sent once it stops.
+    Running,
+    // The client detached or killed the session.
+    Close,
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0; 1];
+        self.reader.read_exact(&mut byte)?;
+        return Ok(byte[0]);
+    }
+
+    // Packets look like $data#checksum and are acknowledged with + or - for a bad checksum.
+    fn read_incoming(&mut self) -> io::Result<Incoming> {
+        match self.read_byte()? {
+            b'$' => {
+                // The checksum covers the data as sent, escapes included. An escaped byte is never
+                // a #, so the data ends at the first one.
+                let mut raw = Vec::new();
+                loop {
+                    let byte = self.read_byte()?;
+                    if byte == b'#' {
+                        break;
+                    }
+                    raw.push(byte);
+                }
+                let mut checksum = [0; 2];
+                self.reader.read_exact(&mut checksum)?;
+                let expected = str::from_utf8(&checksum).ok().and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+                if expected == Some(checksum_of(&raw)) {
+                    self.writer.write_all(b"+")?;
+                    return Ok(Incoming::Packet(String::from_utf8_lossy(&unescape(&raw)).into_owned()));
+                }
+                self.writer.write_all(b"-")?;
+                return Ok(Incoming::Ack);
+            },
+            INTERRUPT => return Ok(Incoming::Interrupt),
+            _ => return Ok(Incoming::Ack),
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        write!(self.writer, "${}#{:02x}", data, checksum_of(data.as_bytes()))?;
+        return self.writer.flush();
+    }
+
+    // Whether the client has sent anything, without blocking.
+    fn has_input(&mut self) -> io::Result<bool> {
+        if !self.reader.buffer().is_empty() {
+            return Ok(true);
+        }
+        self.reader.get_ref().set_nonblocking(true)?;
+        let result = match self.reader.fill_buf() {
+            Ok([]) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "client disconnected")),
+            Ok(_) => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.reader.get_ref().set_nonblocking(false)?;
+        return result;
+    }
+
+    // Block until the client sends something.
+    fn wait_for_input(&mut self) -> io::Result<()> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "client disconnected"));
+        }
+        return Ok(());
+    }
+}
+
+pub struct GdbServer {
+    listener: TcpListener,
+}
+
+impl GdbServer {
+    // Listen on localhost only, the protocol has no authentication.
+    pub fn bind(port: u16) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        return Ok(GdbServer{
+            listener: listener,
+        });
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        return self.listener.local_addr();
+    }
+
+    // Wait for a client to connect.
+    pub fn accept(&self) -> io::Result<GdbSession> {
+        self.listener.set_nonblocking(false)?;
+        let (stream, _) = self.listener.accept()?;
+        return GdbSession::new(stream);
+    }
+
+    // Accept a client if one is waiting to connect, without blocking.
+    pub fn try_accept(&self) -> io::Result<Option<GdbSession>> {
+        self.listener.set_nonblocking(true)?;
+        match self.listener.accept() {
+            Ok((stream, _)) => return Ok(Some(GdbSession::new(stream)?)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Wait for a client and serve it until it detaches, kills the session or disconnects.
+    pub fn serve(&self, debugger: &mut Debugger) -> io::Result<()> {
+        let mut session = self.accept()?;
+        loop {
+            if session.running() {
+                session.run_frame(debugger)?;
+            } else if let Err(e) = session.connection.wait_for_input() {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    return Ok(());
+                }
+                return Err(e);
+            }
+            if !session.poll(debugger)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// A connected client. Execution starts out stopped, the owner of the Debugger runs frames
+// with run_frame while the client has continued and polls for packets in between.
+pub struct GdbSession {
+    connection: Connection,
+    // Whether the client continued execution and is waiting for it to stop.
+    running: bool,
+}
+
+impl GdbSession {
+    fn new(stream: TcpStream) -> io::Result<GdbSession> {
+        // Some platforms hand out sockets that inherit the listener's non-blocking mode.
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        return Ok(GdbSession{
+            connection: Connection{
+                reader: BufReader::new(stream.try_clone()?),
+                writer: stream,
+            },
+            running: false,
+        });
+    }
+
+    pub fn running(&self) -> bool {
+        return self.running;
+    }
+
+    // Handle everything the client has sent so far without blocking. Returns false once the
+    // client has detached, killed the session or disconnected.
+    pub fn poll(&mut self, debugger: &mut Debugger) -> io::Result<bool> {
+        match self.handle_input(debugger) {
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            result => return result,
+        }
+    }
+
+    // Run until the PPU finishes a frame, telling the client if a breakpoint stops execution first.
+    pub fn run_frame(&mut self, debugger: &mut Debugger) -> io::Result<()> {
+        let reason = debugger.run_to_frame();
+        if reason != StopReason::Done {
+            self.running = false;
+            self.connection.send_packet(&stop_reply(reason))?;
+        }
+        return Ok(());
+    }
+
+    fn handle_input(&mut self, debugger: &mut Debugger) -> io::Result<bool> {
+        while self.connection.has_input()? {
+            match self.connection.read_incoming()? {
+                Incoming::Packet(packet) => {
+                    match handle_packet(debugger, &mut self.connection, &packet)? {
+                        Response::Reply(reply) => self.connection.send_packet(&reply)?,
+                        Response::Running => self.running = true,
+                        Response::Close => return Ok(false),
+                    }
+                },
+                // Execution may already be stopped, the client still expects a stop reply.
+                Incoming::Interrupt => {
+                    self.running = false;
+                    self.connection.send_packet(&format!("S{:02x}", SIGINT))?;
+                },
+                Incoming::Ack => {},
+            }
+        }
+        return Ok(true);
+    }
+}
+
+// An empty reply means the packet isn't supported.
+fn handle_packet(debugger: &mut Debugger, connection: &mut Connection, packet: &str) -> io::Result<Response> {
+    let (command, arguments) = packet.split_at(packet.len().min(1));
+    let reply = match command {
+        "?" => format!("S{:02x}", SIGTRAP),
+        "g" => encode_registers(&debugger.console().cpu().get_registers()),
+        "G" => {
+            match decode_hex(arguments) {
+                Some(ref bytes) if bytes.len() == 7 => {
+                    debugger.console().cpu().set_registers(Registers{
+                        a: bytes[0],
+                        x: bytes[1],
+                        y: bytes[2],
+                        p: bytes[3],
+                        sp: bytes[4],
+                        pc: ((bytes[6] as u16) << 8) | bytes[5] as u16,
+                    });
+                    "OK".to_string()
+                },
+                _ => "E01".to_string(),
+            }
+        },
+        "p" => {
+            let registers = debugger.console().cpu().get_registers();
+            match usize::from_str_radix(arguments, 16) {
+                Ok(0) => format!("{:02x}", registers.a),
+                Ok(1) => format!("{:02x}", registers.x),
+                Ok(2) => format!("{:02x}", registers.y),
+                Ok(3) => format!("{:02x}", registers.p),
+                Ok(4) => format!("{:02x}", registers.sp),
+                Ok(5) => format!("{:02x}{:02x}", registers.pc & 0xFF, registers.pc >> 8),
+                _ => "E01".to_string(),
+            }
+        },
+        "P" => write_register(debugger, arguments),
+        "m" => {
+            match parse_address_length(arguments) {
+                Some((address, length)) => {
+                    let mut reply = String::new();
+                    for i in 0..length.min(PACKET_SIZE / 2) {
+                        let byte = debugger.console().peek(address.wrapping_add(i as u16));
+                        reply.push_str(&format!("{:02x}", byte));
+                    }
+                    reply
+                },
+                None => "E01".to_string(),
+            }
+        },
+        "M" => write_memory(debugger, arguments),
+        "s" => {
+            if let Some(address) = parse_resume_address(arguments) {
+                debugger.console().cpu().set_pc(address);
+            }
+            let reason = debugger.step_into();
+            stop_reply(reason)
+        },
+        "c" => {
+            if let Some(address) = parse_resume_address(arguments) {
+                debugger.console().cpu().set_pc(address);
+            }
+            // Frames are run a frame at a time by the session, so a Ctrl-C can stop execution.
+            return Ok(Response::Running);
+        },
+        "Z" => update_breakpoint(debugger, arguments, true),
+        "z" => update_breakpoint(debugger, arguments, false),
+        "H" => "OK".to_string(),
+        "D" => {
+            connection.send_packet("OK")?;
+            return Ok(Response::Close);
+        },
+        "k" => return Ok(Response::Close),
+        "q" => query(arguments),
+        _ => String::new(),
+    };
+    return Ok(Response::Reply(reply));
+}
+
+fn query(query: &str) -> String {
+    if query.starts_with("Supported") {
+        return format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE);
+    } else if query == "Attached" {
+        return "1".to_string();
+    } else if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+        // The annex is read in chunks, m means there is more to come and l that it's the last one.
+        return match parse_address_length(range) {
+            Some((offset, length)) => {
+                // Leave room in the reply for the marker.
+                let length = length.min(PACKET_SIZE - 1);
+                let offset = (offset as usize).min(TARGET_XML.len());
+                let end = offset.saturating_add(length).min(TARGET_XML.len());
+                let marker = if end == TARGET_XML.len() { "l" } else { "m" };
+                format!("{}{}", marker, &TARGET_XML[offset..end])
+            },
+            None => "E01".to_string(),
+        };
+    }
+    return String::new();
+}
+
+// P n=value with the value in target byte order.
+fn write_register(debugger: &mut Debugger, arguments: &str) -> String {
+    let parts: Vec<&str> = arguments.splitn(2, '=').collect();
+    if parts.len() != 2 {
+        return "E01".to_string();
+    }
+    let register = match usize::from_str_radix(parts[0], 16) {
+        Ok(register) => register,
+        Err(_) => return "E01".to_string(),
+    };
+    let bytes = match decode_hex(parts[1]) {
+        Some(bytes) => bytes,
+        None => return "E01".to_string(),
+    };
+    let mut registers = debugger.console().cpu().get_registers();
+    match (register, bytes.len()) {
+        (0, 1) => registers.a = bytes[0],
+        (1, 1) => registers.x = bytes[0],
+        (2, 1) => registers.y = bytes[0],
+        (3, 1) => registers.p = bytes[0],
+        (4, 1) => registers.sp = bytes[0],
+        (5, 2) => registers.pc = ((bytes[1] as u16) << 8) | bytes[0] as u16,
+        _ => return "E01".to_string(),
+    }
+    debugger.console().cpu().set_registers(registers);
+    return "OK".to_string();
+}
+
+// M addr,length:bytes. Only RAM and PRG RAM can be written.
+fn write_memory(debugger: &mut Debugger, arguments: &str) -> String {
+    let parts: Vec<&str> = arguments.splitn(2, ':').collect();
+    if parts.len() != 2 {
+        return "E01".to_string();
+    }
+    let (address, length) = match parse_address_length(parts[0]) {
+        Some(address_length) => address_length,
+        None => return "E01".to_string(),
+    };
+    let bytes = match decode_hex(parts[1]) {
+        Some(ref bytes) if bytes.len() == length => bytes.clone(),
+        _ => return "E01".to_string(),
+    };
+    for (i, byte) in bytes.iter().enumerate() {
+        if !debugger.console().poke(address.wrapping_add(i as u16), *byte) {
+            return "E0e".to_string();
+        }
+    }
+    return "OK".to_string();
+}
+
+// Z/z type,addr,kind. Types 0 and 1 are breakpoints, 2 to 4 are write, read and access
+// watchpoints covering `kind` bytes.
+fn update_breakpoint(debugger: &mut Debugger, arguments: &str, insert: bool) -> String {
+    let parts: Vec<&str> = arguments.split(',').collect();
+    if parts.len() < 3 {
+        return "E01".to_string();
+    }
+    let address = match u16::from_str_radix(parts[1], 16) {
+        Ok(address) => address,
+        Err(_) => return "E01".to_string(),
+    };
+    let length = match u16::from_str_radix(parts[2], 16) {
+        Ok(length) => length.max(1),
+        Err(_) => return "E01".to_string(),
+    };
+
+    let mut breakpoints = Vec::new();
+    match parts[0] {
+        "0" | "1" => breakpoints.push(Breakpoint::Execute(address)),
+        "2" | "3" | "4" => {
+            for i in 0..length {
+                let watched = address.wrapping_add(i);
+                if parts[0] != "3" {
+                    breakpoints.push(Breakpoint::CpuWrite(watched));
+                }
+                if parts[0] != "2" {
+                    breakpoints.push(Breakpoint::CpuRead(watched));
+                }
+            }
+        },
+        _ => return String::new(),
+    }
+
+    for breakpoint in breakpoints {
+        if insert {
+            debugger.add_breakpoint(breakpoint);
+        } else if let Some(index) = debugger.breakpoints().iter().position(|b| *b == breakpoint) {
+            debugger.remove_breakpoint(index);
+        }
+    }
+    return "OK".to_string();
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Watchpoint(_, access) => {
+            let kind = match access.kind {
+                AccessKind::Read => "rwatch",
+                AccessKind::Write => "watch",
+            };
+            format!("T{:02x}{}:{:04x};", SIGTRAP, kind, access.address)
+        },
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn encode_registers(registers: &Registers) -> String {
+    return format!("{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        registers.a, registers.x, registers.y, registers.p, registers.sp, registers.pc & 0xFF, registers.pc >> 8);
+}
+
+// s and c can be given an address to resume from.
+fn parse_resume_address(arguments: &str) -> Option<u16> {
+    if arguments.is_empty() {
+        return None;
+    }
+    return u16::from_str_radix(arguments, 16).ok();
+}
+
+// addr,length
+fn parse_address_length(arguments: &str) -> Option<(u16, usize)> {
+    let parts: Vec<&str> = arguments.split(',').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+    let address = u16::from_str_radix(parts[0], 16).ok()?;
+    let length = usize::from_str_radix(parts[1], 16).ok()?;
+    return Some((address, length));
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    for i in (0..hex.len()).step_by(2) {
+        bytes.push(u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()?);
+    }
+    return Some(bytes);
+}
+
+// Packet data as sent, with each escaped byte restored.
+fn unescape(raw: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut bytes = raw.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == ESCAPE {
+            if let Some(&escaped) = bytes.next() {
+                data.push(escaped ^ 0x20);
+            }
+        } else {
+            data.push(byte);
+        }
+    }
+    return data;
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    return data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_is_the_sum_of_the_bytes_modulo_256() {
+        assert_eq!(checksum_of(b""), 0x00);
+        assert_eq!(checksum_of(b"OK"), 0x9A);
+        assert_eq!(checksum_of(&[0xFF, 0x02]), 0x01);
+    }
+
+    #[test]
+    fn escaped_bytes_are_xored_with_0x20() {
+        // # and } escaped, the escapes are dropped.
+        assert_eq!(unescape(b"X}\x03}\x5dY"), b"X#}Y".to_vec());
+        assert_eq!(unescape(b"m8000,2"), b"m8000,2".to_vec());
+        // A dangling escape at the end of the data is ignored.
+        assert_eq!(unescape(b"a}"), b"a".to_vec());
+    }
+
+    #[test]
+    fn address_and_length_are_hex() {
+        assert_eq!(parse_address_length("c000,10"), Some((0xC000, 0x10)));
+        assert_eq!(parse_address_length("0,0"), Some((0, 0)));
+        assert_eq!(parse_address_length("c000"), None);
+        assert_eq!(parse_address_length("c000,10,1"), None);
+        assert_eq!(parse_address_length("zz,1"), None);
+        // Addresses are 16 bits.
+        assert_eq!(parse_address_length("10000,1"), None);
+    }
+
+    #[test]
+    fn target_xml_reads_are_clamped() {
+        let whole = format!("l{}", TARGET_XML);
+        assert_eq!(query("Xfer:features:read:target.xml:0,ffffffffffffffff"), whole);
+        assert_eq!(query("Xfer:features:read:target.xml:ffff,ffffffffffffffff"), "l");
+        assert_eq!(query("Xfer:features:read:target.xml:0,5"), format!("m{}", &TARGET_XML[..5]));
+        assert_eq!(query("Xfer:features:read:target.xml:0"), "E01");
+    }
+}
@@ -1,58 +1,168 @@
-use std::fs::File;
+// The header fields are built and returned explicitly.
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
 use std::io::prelude::*;
-use std::str;
 
 const INES_HEADER_MAGIC_NUMBER: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 pub const PRG_ROM_UNIT_SIZE: u32 = 16384;
 const CHR_ROM_UNIT_SIZE: u32 = 8192;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    // The game works on both NTSC and PAL consoles.
+    MultipleRegion,
+    Dendy,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    // One of the extended console types from byte 13 of a NES 2.0 header.
+    Extended(u8),
+}
+
 pub struct INESData {
     pub prg: Vec<u8>,
     pub chr: Vec<u8>,
-    pub mapper: u8,
+    // The mapper number. NES 2.0 headers can use up to 12 bits.
+    pub mapper: u16,
+    // Always 0 for iNES headers.
+    pub submapper: u8,
     pub nametable_mirroring: u8,
+    // The cartridge has battery backed PRG RAM at $6000-$7FFF.
+    pub battery: bool,
+    // The header is in the NES 2.0 format rather than iNES.
+    pub nes2: bool,
+    // Sizes in bytes of the volatile and battery backed RAM on the cartridge.
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    // The NES 2.0 default expansion device number, 0 if unspecified.
+    pub default_expansion_device: u8,
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    // The ROM file couldn't be opened or its header couldn't be read.
+    Io(io::Error),
+    // The file doesn't start with the iNES magic number.
+    BadMagic,
+    // The file ended before all of the PRG ROM the header declares.
+    TruncatedPrgRom { expected: usize, actual: usize },
+    // The file ended before all of the CHR ROM the header declares.
+    TruncatedChrRom { expected: usize, actual: usize },
+    UnsupportedMapper(u16),
+    UnsupportedMirroring(u8),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::Io(ref e) => write!(f, "could not read ROM file: {}", e),
+            RomError::BadMagic => write!(f, "not an iNES ROM file, the header magic number is incorrect"),
+            RomError::TruncatedPrgRom { expected, actual } => write!(f, "PRG ROM is truncated, expected {} bytes but found {}", expected, actual),
+            RomError::TruncatedChrRom { expected, actual } => write!(f, "CHR ROM is truncated, expected {} bytes but found {}", expected, actual),
+            RomError::UnsupportedMapper(mapper) => write!(f, "mapper is {}, but we can only emulate mapper 0, 2, 3, 7, 1, and 4 at this time", mapper),
+            RomError::UnsupportedMirroring(mirroring) => write!(f, "nametable mirroring {} is not supported", mirroring),
+        }
+    }
+}
+
+impl error::Error for RomError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            RomError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(e: io::Error) -> RomError {
+        return RomError::Io(e);
+    }
+}
+
+// NES 2.0 ROM sizes. When the MSB nibble is 0xF the LSB is an exponent-multiplier
+// pair where the size is 2^E * (MM*2+1) bytes.
+fn nes2_rom_size(lsb: u8, msb: u8, unit_size: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = ((lsb & 0x03) as usize * 2) + 1;
+        return 2usize.saturating_pow(exponent).saturating_mul(multiplier);
+    }
+    return (((msb as usize) << 8) | lsb as usize) * unit_size;
 }
 
+// NES 2.0 RAM sizes are given as a shift count where the size is 64 << shift bytes, or 0 if the shift is 0.
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        return 0;
+    }
+    return 64 << shift;
+}
+
+// Read up to size bytes, returning fewer if the data ends first.
+fn read_up_to(file: &mut &[u8], size: usize) -> Vec<u8> {
+    let length = size.min(file.len());
+    let (data, rest) = file.split_at(length);
+    *file = rest;
+    return data.to_vec();
+}
+
+pub fn load_ines_file(file_name: &str) -> Result<INESData, RomError> {
+    let data = fs::read(file_name)?;
+    return parse_ines(&data);
+}
+
+// Parse a whole ROM image, header included.
 // https://wiki.nesdev.com/w/index.php/INES
-pub fn load_ines_file(file_name: &str) -> INESData {
-    // TODO need to acknowledge error instead of unwrap
-    let mut file = File::open(file_name).unwrap();
+pub fn parse_ines(data: &[u8]) -> Result<INESData, RomError> {
+    let mut file = data;
 
     let mut header_magic_number: [u8; 4] = [0; 4];
-    // TODO need to acknowledge error instead of unwrap
-    file.read_exact(&mut header_magic_number).unwrap();
+    file.read_exact(&mut header_magic_number)?;
 
     if header_magic_number != INES_HEADER_MAGIC_NUMBER {
-        // TODO need to acknowledge error if the  magic number if incorrect 
-        panic!("Error with header magic number")
+        return Err(RomError::BadMagic);
     }
 
     let mut prg_rom_size: [u8; 1] = [0];
-    // TODO need to acknowledge error instead of unwrap
-    file.read_exact(&mut prg_rom_size).unwrap();
+    file.read_exact(&mut prg_rom_size)?;
 
     let mut chr_rom_size: [u8; 1] = [0];
-    // TODO need to acknowledge error instead of unwrap
-    file.read_exact(&mut chr_rom_size).unwrap();
+    file.read_exact(&mut chr_rom_size)?;
 
     let mut flags6: [u8; 1] = [0];
-    // TODO need to acknowledge error instead of unwrap
-    file.read_exact(&mut flags6).unwrap();
+    file.read_exact(&mut flags6)?;
 
     let mut flags7: [u8; 1] = [0];
-    // TODO need to acknowledge error instead of unwrap
-    file.read_exact(&mut flags7).unwrap();
-    if flags7[0] & 0x0C == 0x08 {
-        // TODO then nes 2.0
-    } else if flags7[0] & 0x0C == 0 {
-        // TODO then iNES
-    } else {
-        // archaic iNES
+    file.read_exact(&mut flags7)?;
+
+    // Bytes 8-15 of the header. Only NES 2.0 gives all of them a meaning.
+    let mut header_tail: [u8; 8] = [0; 8];
+    file.read_exact(&mut header_tail)?;
+
+    let nes2 = flags7[0] & 0x0C == 0x08;
+    if !nes2 && (flags7[0] & 0x0C != 0 || header_tail[4..8] != [0, 0, 0, 0]) {
+        // archaic iNES, the rest of the header may contain garbage such as "DiskDude!"
         flags7[0] = 0;
+        header_tail = [0; 8];
     }
 
-    let mut mapper = flags6[0]>>4;
-    mapper = mapper | flags7[0]&0xF0;
+    let mut mapper = ((flags6[0]>>4) | (flags7[0]&0xF0)) as u16;
+    let mut submapper = 0;
 
     let low_mirror = flags6[0] & 1;
     let high_mirror = (flags6[0] >> 3) & 1;
@@ -61,41 +171,115 @@ pub fn load_ines_file(file_name: &str) -> INESData {
         nametable_mirroring = 4;
     }
 
-    if mapper != 0 && mapper != 2 && mapper != 3 && mapper != 7 && mapper != 1 && mapper != 4 {
-        // TODO properly propagate this error up
-        panic!("mapper is {}, but we can only emulate mapper 0, 2, 3, 7, 1, and 4 at this time.", mapper);
+    let battery = flags6[0] & 0x02 == 0x02;
+
+    let prg_rom_len;
+    let chr_rom_len;
+    let prg_ram_size;
+    let prg_nvram_size;
+    let chr_ram_size;
+    let chr_nvram_size;
+    let timing;
+    let console_type;
+    let mut default_expansion_device = 0;
+    if nes2 {
+        // https://wiki.nesdev.com/w/index.php/NES_2.0
+        mapper |= ((header_tail[0] & 0x0F) as u16) << 8;
+        submapper = header_tail[0] >> 4;
+        prg_rom_len = nes2_rom_size(prg_rom_size[0], header_tail[1] & 0x0F, PRG_ROM_UNIT_SIZE as usize);
+        chr_rom_len = nes2_rom_size(chr_rom_size[0], header_tail[1] >> 4, CHR_ROM_UNIT_SIZE as usize);
+        prg_ram_size = nes2_ram_size(header_tail[2] & 0x0F);
+        prg_nvram_size = nes2_ram_size(header_tail[2] >> 4);
+        chr_ram_size = nes2_ram_size(header_tail[3] & 0x0F);
+        chr_nvram_size = nes2_ram_size(header_tail[3] >> 4);
+        timing = match header_tail[4] & 0x03 {
+            0 => Timing::Ntsc,
+            1 => Timing::Pal,
+            2 => Timing::MultipleRegion,
+            _ => Timing::Dendy,
+        };
+        console_type = match flags7[0] & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(header_tail[5] & 0x0F),
+        };
+        default_expansion_device = header_tail[7] & 0x3F;
+    } else {
+        // https://wiki.nesdev.com/w/index.php/INES
+        prg_rom_len = prg_rom_size[0] as usize * PRG_ROM_UNIT_SIZE as usize;
+        chr_rom_len = chr_rom_size[0] as usize * CHR_ROM_UNIT_SIZE as usize;
+        // iNES only gives the PRG RAM size in byte 8, and 0 means 8KB for compatibility.
+        let mut ram_size = header_tail[0] as usize * 8192;
+        if ram_size == 0 {
+            ram_size = 8192;
+        }
+        if battery {
+            prg_ram_size = 0;
+            prg_nvram_size = ram_size;
+        } else {
+            prg_ram_size = ram_size;
+            prg_nvram_size = 0;
+        }
+        if chr_rom_len == 0 {
+            chr_ram_size = CHR_ROM_UNIT_SIZE as usize;
+        } else {
+            chr_ram_size = 0;
+        }
+        chr_nvram_size = 0;
+        if header_tail[1] & 1 == 1 {
+            timing = Timing::Pal;
+        } else {
+            timing = Timing::Ntsc;
+        }
+        match flags7[0] & 0x03 {
+            1 => console_type = ConsoleType::VsSystem,
+            2 => console_type = ConsoleType::Playchoice10,
+            _ => console_type = ConsoleType::Nes,
+        }
     }
 
-    // TODO ignoring the last 8 bytes, but they may be useful at some point
-    let mut ignore: [u8; 8] = [0; 8];
-    // TODO need to acknowledge error instead of unwrap
-    file.read_exact(&mut ignore).unwrap();
+    if mapper != 0 && mapper != 2 && mapper != 3 && mapper != 7 && mapper != 1 && mapper != 4 {
+        return Err(RomError::UnsupportedMapper(mapper));
+    }
 
     // Read the trainer if it is present.
     // TODO this will currently ignore the trainer after reading it. I don't think it is crucial to the operation of most ROMs.
     if flags6[0] & 0x04 == 4 {
         let mut trainer: [u8; 512] = [0; 512];
-        // TODO need to acknowledge error instead of unwrap
-        file.read_exact(&mut trainer).unwrap();
+        file.read_exact(&mut trainer)?;
     }
 
+    let prg_rom = read_up_to(&mut file, prg_rom_len);
+    if prg_rom.len() != prg_rom_len {
+        return Err(RomError::TruncatedPrgRom{
+            expected: prg_rom_len,
+            actual: prg_rom.len(),
+        });
+    }
 
-    let mut prg_rom = vec![0u8; prg_rom_size[0] as usize * PRG_ROM_UNIT_SIZE as usize];
-    // TODO need to acknowledge error instead of unwrap
-    file.read_exact(&mut prg_rom).unwrap();
-
-    let mut chr_rom = vec![0u8; chr_rom_size[0] as usize * CHR_ROM_UNIT_SIZE as usize];
-    // TODO need to acknowledge error instead of unwrap
-    file.read_exact(&mut chr_rom).unwrap();
-
-    if chr_rom_size[0] == 0 {
-        chr_rom = vec![0u8; CHR_ROM_UNIT_SIZE as usize];
+    let chr_rom = read_up_to(&mut file, chr_rom_len);
+    if chr_rom.len() != chr_rom_len {
+        return Err(RomError::TruncatedChrRom{
+            expected: chr_rom_len,
+            actual: chr_rom.len(),
+        });
     }
 
-    INESData{
+    Ok(INESData{
         prg: prg_rom,
         chr: chr_rom,
         mapper: mapper,
+        submapper: submapper,
         nametable_mirroring: nametable_mirroring,
-    }
+        battery: battery,
+        nes2: nes2,
+        prg_ram_size: prg_ram_size,
+        prg_nvram_size: prg_nvram_size,
+        chr_ram_size: chr_ram_size,
+        chr_nvram_size: chr_nvram_size,
+        timing: timing,
+        console_type: console_type,
+        default_expansion_device: default_expansion_device,
+    })
 }
@@ -0,0 +1,20 @@
+extern crate image;
+
+pub mod ines;
+pub mod blip;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod filter;
+pub mod gdb;
+pub mod ppu;
+pub mod apu;
+pub mod controller;
+pub mod mapper;
+pub mod nes;
+pub mod state;
+pub mod trace;
+
+pub use nes::Nes;
+pub use controller::Buttons;
+pub use ines::RomError;
@@ -1,6 +1,16 @@
+// Bank switching spells out its address ranges, casts and returns to match the mapper documentation.
+#![allow(
+    clippy::manual_is_multiple_of,
+    clippy::manual_range_contains,
+    clippy::needless_return,
+    clippy::redundant_field_names,
+    clippy::unnecessary_cast,
+)]
 
-use cpu;
+
+use ines;
 use ppu;
+use state::{StateError, StateReader, StateWriter};
 
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -9,21 +19,83 @@ pub trait Mapper {
     fn read(&mut self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, data: u8); 
     fn get_chr(&mut self) -> Vec<u8>;
-    fn step(&mut self, ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>, cpu: &mut cpu::CPU<cpu::CPUMemory>);
+    // The PRG RAM mapped at $6000-$7FFF, volatile and battery backed together. Any battery
+    // backed RAM comes first.
+    fn get_prg_ram(&mut self) -> &mut [u8];
+    // Whether the cartridge answers a CPU read from addr. Reads it doesn't answer see open bus.
+    fn decodes_read(&mut self, addr: u16) -> bool {
+        if addr >= 0x6000 && addr < 0x8000 {
+            return !self.get_prg_ram().is_empty();
+        }
+        return addr >= 0x8000;
+    }
+    // Called after every PPU dot.
+    fn step(&mut self, ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>);
+    // Whether the mapper is asserting the CPU's IRQ line.
+    fn irq(&self) -> bool {
+        return false;
+    }
+    // Persist the banking and IRQ registers along with any writable memory.
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+// PRG RAM is mapped at $6000-$7FFF and mirrored when it is smaller than 8KB.
+// Cartridges without PRG RAM ignore writes, the CPU sees open bus on reads.
+fn read_prg_ram(prg_ram: &[u8], addr: u16) -> u8 {
+    if prg_ram.is_empty() {
+        return 0;
+    }
+    return prg_ram[(addr-0x6000) as usize % prg_ram.len()];
+}
+
+fn write_prg_ram(prg_ram: &mut [u8], addr: u16, data: u8) {
+    if prg_ram.is_empty() {
+        return;
+    }
+    let len = prg_ram.len();
+    prg_ram[(addr-0x6000) as usize % len] = data;
+}
+
+// Saves the memory every mapper has. PRG ROM is only recorded by size so a
+// save state can't be loaded into a different game. CHR is written in full
+// since it is RAM for many games.
+fn save_memory_state(state: &mut StateWriter, prg: &[u8], chr: &[u8], save_ram: &[u8]) {
+    state.write_u32(prg.len() as u32);
+    state.write_bytes(chr);
+    state.write_bytes(save_ram);
+}
+
+// A banking register restored from a save state has to be one the cartridge could hold,
+// anything else would index past its memory later.
+fn check_state_register(value: u8, limit: usize, what: &'static str) -> Result<u8, StateError> {
+    if value as usize >= limit {
+        return Err(StateError::Mismatch(what));
+    }
+    return Ok(value);
+}
+
+fn load_memory_state(state: &mut StateReader, prg: &[u8], chr: &mut [u8], save_ram: &mut [u8]) -> Result<(), StateError> {
+    if state.read_u32()? as usize != prg.len() {
+        return Err(StateError::Mismatch("PRG ROM size"));
+    }
+    state.read_bytes_into(chr)?;
+    state.read_bytes_into(save_ram)?;
+    return Ok(());
 }
 
 struct Mapper0 {
     chr: Vec<u8>,
     prg: Vec<u8>,
-    save_ram: [u8; 8192],
+    save_ram: Vec<u8>,
 }
 
 impl Mapper0 {
-    fn new(chr: Vec<u8>, prg: Vec<u8>) -> Mapper0 {
+    fn new(chr: Vec<u8>, prg: Vec<u8>, prg_ram_size: usize) -> Mapper0 {
         Mapper0{
             chr: chr,
             prg: prg,
-            save_ram: [0; 8192],
+            save_ram: vec![0; prg_ram_size],
         }
     }
 
@@ -52,7 +124,7 @@ impl Mapper for Mapper0 {
         if addr < 0x2000 {
             return self.chr[addr as usize];
         } else if addr >= 0x6000 && addr < 0x8000 {
-            return self.save_ram[(addr-0x6000) as usize];
+            return read_prg_ram(&self.save_ram, addr);
         } else if addr >= 0x8000 {
             let address = self.get_actual_addr(addr);
             return self.prg[address as usize];
@@ -65,7 +137,7 @@ impl Mapper for Mapper0 {
         if addr < 0x2000 {
             self.chr[addr as usize] = data;
         } else if addr >= 0x6000 && addr < 0x8000 {
-            self.save_ram[(addr-0x6000) as usize] = data;
+            write_prg_ram(&mut self.save_ram, addr, data);
         } else {
             unimplemented!();
         }
@@ -75,13 +147,26 @@ impl Mapper for Mapper0 {
         return self.chr.clone();
     }
 
-    fn step(&mut self, _ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>, _cpu: &mut cpu::CPU<cpu::CPUMemory>) {}
+    fn get_prg_ram(&mut self) -> &mut [u8] {
+        return &mut self.save_ram;
+    }
+
+    fn step(&mut self, _ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        save_memory_state(state, &self.prg, &self.chr, &self.save_ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        load_memory_state(state, &self.prg, &mut self.chr, &mut self.save_ram)?;
+        return Ok(());
+    }
 }
 
 struct Mapper1 {
     chr: Vec<u8>,
     prg: Vec<u8>,
-    save_ram: [u8; 8192],
+    save_ram: Vec<u8>,
     shift_register: u8,
     control: u8,
     prg_bank_mode: u8,
@@ -94,11 +179,11 @@ struct Mapper1 {
 }
 
 impl Mapper1 {
-    fn new(chr: Vec<u8>, prg: Vec<u8>, nametable_mirror_type: Rc<RefCell<Box<ppu::NametableMirroring>>>) -> Mapper1 {
+    fn new(chr: Vec<u8>, prg: Vec<u8>, prg_ram_size: usize, nametable_mirror_type: Rc<RefCell<Box<ppu::NametableMirroring>>>) -> Mapper1 {
         Mapper1{
             chr: chr,
             prg: prg,
-            save_ram: [0; 8192],
+            save_ram: vec![0; prg_ram_size],
             shift_register: 0x10,
             control: 0,
             prg_bank_mode: 0,
@@ -148,7 +233,7 @@ impl Mapper1 {
             self.shift_register |= (v & 1) << 4;
             if write_finished {
                 if addr <= 0x9FFF {
-                    let sr = self.shift_register.clone();
+                    let sr = self.shift_register;
                     self.write_control(sr);
                 } else if addr <= 0xBFFF {
                     self.chr_0_bank = self.shift_register;
@@ -218,12 +303,12 @@ impl Mapper1 {
         if self.chr_bank_mode == 0 {
             if bank == 0 {
                 let mut index = (self.chr_0_bank & 0xFE) as usize;
-                index %= (self.prg.len() as usize) / 0x1000;
+                index %= (self.chr.len() as usize) / 0x1000;
                 let begin_bank_offset = index * 0x1000;
                 return (begin_bank_offset+offset as usize) as usize;
             } else if bank == 1 {
                 let mut index = (self.chr_0_bank | 0x01) as usize;
-                index %= (self.prg.len() as usize) / 0x1000;
+                index %= (self.chr.len() as usize) / 0x1000;
                 let begin_bank_offset = index * 0x1000;
                 return (begin_bank_offset+offset as usize) as usize;
             } else {
@@ -232,12 +317,12 @@ impl Mapper1 {
         } else if self.chr_bank_mode == 1{
             if bank == 0 {
                 let mut index = self.chr_0_bank as u16;
-                index %= (self.prg.len() as u16) / 0x1000;
+                index %= (self.chr.len() as u16) / 0x1000;
                 let begin_bank_offset = index * 0x1000;
                 return (begin_bank_offset+offset) as usize;
             } else if bank == 1 {
                 let mut index = self.chr_1_bank as u16;
-                index %= (self.prg.len() as u16) / 0x1000;
+                index %= (self.chr.len() as u16) / 0x1000;
                 let begin_bank_offset = index * 0x1000;
                 return (begin_bank_offset+offset) as usize;
             } else {
@@ -254,7 +339,7 @@ impl Mapper for Mapper1 {
             let chr_addr = self.get_chr_addr(addr);
             return self.chr[chr_addr];
         } else if addr >= 0x6000 && addr < 0x8000 {
-            return self.save_ram[(addr-0x6000) as usize];
+            return read_prg_ram(&self.save_ram, addr);
         } else if addr >= 0x8000 {
             let prg_addr = self.get_prg_addr(addr);
             return self.prg[prg_addr];
@@ -268,7 +353,7 @@ impl Mapper for Mapper1 {
             let chr_addr = self.get_chr_addr(addr);
             self.chr[chr_addr] = data;
         } else if addr >= 0x6000 && addr < 0x8000 {
-            self.save_ram[(addr-0x6000) as usize];
+            write_prg_ram(&mut self.save_ram, addr, data);
         } else if addr >= 0x8000 {
             self.write_to_shift_register(addr, data);
         } else {
@@ -280,23 +365,55 @@ impl Mapper for Mapper1 {
         return self.chr.clone();
     }
 
-    fn step(&mut self, _ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>, _cpu: &mut cpu::CPU<cpu::CPUMemory>) {}
+    fn get_prg_ram(&mut self) -> &mut [u8] {
+        return &mut self.save_ram;
+    }
+
+    fn step(&mut self, _ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        save_memory_state(state, &self.prg, &self.chr, &self.save_ram);
+        state.write_u8(self.shift_register);
+        state.write_u8(self.control);
+        state.write_u8(self.prg_bank_mode);
+        state.write_u8(self.chr_bank_mode);
+        state.write_u8(self.prg_bank);
+        state.write_u8(self.chr_0_bank);
+        state.write_u8(self.chr_1_bank);
+        state.write_bool(self.beginning_fix_last_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        load_memory_state(state, &self.prg, &mut self.chr, &mut self.save_ram)?;
+        self.shift_register = state.read_u8()?;
+        self.control = state.read_u8()?;
+        // The bank registers are 5 bits wide and wrapped to the cartridge's size when used.
+        self.prg_bank_mode = check_state_register(state.read_u8()?, 4, "MMC1 PRG bank mode")?;
+        self.chr_bank_mode = check_state_register(state.read_u8()?, 2, "MMC1 CHR bank mode")?;
+        self.prg_bank = check_state_register(state.read_u8()?, 0x10, "MMC1 PRG bank")?;
+        self.chr_0_bank = check_state_register(state.read_u8()?, 0x20, "MMC1 CHR bank")?;
+        self.chr_1_bank = check_state_register(state.read_u8()?, 0x20, "MMC1 CHR bank")?;
+        self.beginning_fix_last_bank = state.read_bool()?;
+        return Ok(());
+    }
 }
 
 struct Mapper2 {
     chr: Vec<u8>,
     prg: Vec<u8>,
-    save_ram: [u8; 8192],
+    save_ram: Vec<u8>,
     selected_bank_1: u8,
+    bus_conflicts: bool,
 }
 
 impl Mapper2 {
-    fn new(chr: Vec<u8>, prg: Vec<u8>) -> Mapper2 {
+    fn new(chr: Vec<u8>, prg: Vec<u8>, prg_ram_size: usize, bus_conflicts: bool) -> Mapper2 {
         Mapper2{
             chr: chr,
             prg: prg,
-            save_ram: [0; 8192],
+            save_ram: vec![0; prg_ram_size],
             selected_bank_1: 0,
+            bus_conflicts: bus_conflicts,
         }
     }
 
@@ -310,7 +427,7 @@ impl Mapper for Mapper2 {
         if addr < 0x2000 {
             return self.chr[addr as usize];
         } else if addr >= 0x6000 && addr < 0x8000 {
-            return self.save_ram[(addr-0x6000) as usize];
+            return read_prg_ram(&self.save_ram, addr);
         } else if addr >= 0x8000 && addr < 0xC000 {
             let address = (self.selected_bank_1 as u32)*(0x4000 as u32) + ((addr - 0x8000) as u32);
             return self.prg[address as usize];
@@ -327,8 +444,12 @@ impl Mapper for Mapper2 {
         if addr < 0x2000 {
             self.chr[addr as usize] = data;
         } else if addr >= 0x6000 && addr < 0x8000 {
-            self.save_ram[(addr-0x6000) as usize] = data;
+            write_prg_ram(&mut self.save_ram, addr, data);
         } else if addr >= 0x8000 {
+            let mut data = data;
+            if self.bus_conflicts {
+                data &= self.read(addr);
+            }
             self.selected_bank_1 = ((data as u16) % (self.num_banks() as u16)) as u8;
         } else {
             unimplemented!();
@@ -339,27 +460,48 @@ impl Mapper for Mapper2 {
         return self.chr.clone();
     }
 
-    fn step(&mut self, _ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>, _cpu: &mut cpu::CPU<cpu::CPUMemory>) {}
+    fn get_prg_ram(&mut self) -> &mut [u8] {
+        return &mut self.save_ram;
+    }
+
+    fn step(&mut self, _ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        save_memory_state(state, &self.prg, &self.chr, &self.save_ram);
+        state.write_u8(self.selected_bank_1);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        load_memory_state(state, &self.prg, &mut self.chr, &mut self.save_ram)?;
+        self.selected_bank_1 = check_state_register(state.read_u8()?, self.num_banks(), "UxROM PRG bank")?;
+        return Ok(());
+    }
 }
 
 struct Mapper3 {
     chr: Vec<u8>,
     prg: Vec<u8>,
-    save_ram: [u8; 8192],
+    save_ram: Vec<u8>,
     selected_chr_bank: u8,
+    bus_conflicts: bool,
 }
 
 
 impl Mapper3 {
-    fn new(chr: Vec<u8>, prg: Vec<u8>) -> Mapper3 {
+    fn new(chr: Vec<u8>, prg: Vec<u8>, prg_ram_size: usize, bus_conflicts: bool) -> Mapper3 {
         Mapper3{
             chr: chr,
             prg: prg,
-            save_ram: [0; 8192],
+            save_ram: vec![0; prg_ram_size],
             selected_chr_bank: 0,
+            bus_conflicts: bus_conflicts,
         }
     }
 
+    fn num_banks(&mut self) -> usize {
+        return self.chr.len() / 0x2000;
+    }
+
     fn get_actual_addr(&mut self, addr: u16) -> u16 {
         let num_banks = self.prg.len() / 0x4000;
         let mut address = addr;
@@ -386,7 +528,7 @@ impl Mapper for Mapper3 {
             let address = (self.selected_chr_bank as u32)*(0x2000 as u32) + (addr as u32);
             return self.chr[address as usize];
         } else if addr >= 0x6000 && addr < 0x8000 {
-            return self.save_ram[(addr-0x6000) as usize];
+            return read_prg_ram(&self.save_ram, addr);
         } else if addr >= 0x8000 {
             let address = self.get_actual_addr(addr);
             return self.prg[address as usize];
@@ -400,9 +542,13 @@ impl Mapper for Mapper3 {
             let address = (self.selected_chr_bank as u32)*(0x2000 as u32) + (addr as u32);
             self.chr[address as usize] = data;
         } else if addr >= 0x6000 && addr < 0x8000 {
-            self.save_ram[(addr-0x6000) as usize] = data;
+            write_prg_ram(&mut self.save_ram, addr, data);
         } else if addr >= 0x8000 {
-            self.selected_chr_bank = data;
+            let mut data = data;
+            if self.bus_conflicts {
+                data &= self.read(addr);
+            }
+            self.selected_chr_bank = ((data as u16) % (self.num_banks() as u16)) as u8;
         } else {
             unimplemented!();
         }
@@ -412,13 +558,28 @@ impl Mapper for Mapper3 {
         return self.chr.clone();
     }
 
-    fn step(&mut self, _ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>, _cpu: &mut cpu::CPU<cpu::CPUMemory>) {}
+    fn get_prg_ram(&mut self) -> &mut [u8] {
+        return &mut self.save_ram;
+    }
+
+    fn step(&mut self, _ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        save_memory_state(state, &self.prg, &self.chr, &self.save_ram);
+        state.write_u8(self.selected_chr_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        load_memory_state(state, &self.prg, &mut self.chr, &mut self.save_ram)?;
+        self.selected_chr_bank = check_state_register(state.read_u8()?, self.num_banks(), "CNROM CHR bank")?;
+        return Ok(());
+    }
 }
 
 struct Mapper4 {
     chr: Vec<u8>,
     prg: Vec<u8>,
-    save_ram: [u8; 8192],
+    save_ram: Vec<u8>,
     nametable_mirror_type: Rc<RefCell<Box<ppu::NametableMirroring>>>,
     bank_registers: [u8; 8],
     selected_bank_register: u8,
@@ -427,16 +588,17 @@ struct Mapper4 {
     irq_enable: bool,
     irq_counter: u8,
     irq_counter_reload_value: u8,
+    irq_pending: bool,
     startup_banks: bool,
 
 }
 
 impl Mapper4 {
-    fn new(chr: Vec<u8>, prg: Vec<u8>, nametable_mirror_type: Rc<RefCell<Box<ppu::NametableMirroring>>>) -> Mapper4 {
+    fn new(chr: Vec<u8>, prg: Vec<u8>, prg_ram_size: usize, nametable_mirror_type: Rc<RefCell<Box<ppu::NametableMirroring>>>) -> Mapper4 {
         return Mapper4{
             chr: chr,
             prg: prg,
-            save_ram: [0; 8192],
+            save_ram: vec![0; prg_ram_size],
             nametable_mirror_type: nametable_mirror_type,
             bank_registers: [0; 8],
             selected_bank_register: 0,
@@ -445,6 +607,7 @@ impl Mapper4 {
             irq_enable: false,
             irq_counter: 0,
             irq_counter_reload_value: 0,
+            irq_pending: false,
             startup_banks: true,
         }
     }
@@ -467,7 +630,9 @@ impl Mapper4 {
         } else if addr >= 0xC000 && addr <= 0xDFFF && addr %2 == 1 {
             self.irq_counter = 0; // TODO is this really correct ?
         } else if addr >= 0xE000 && addr %2 == 0 {
+            // Disabling also acknowledges a pending IRQ.
             self.irq_enable = false;
+            self.irq_pending = false;
         } else if addr >= 0xE000 && addr %2 == 1 {
             self.irq_enable = true;
         } else {
@@ -614,7 +779,7 @@ impl Mapper4 {
                 return (begin_bank_offset+offset as usize) as usize;
             } else if bank == 5 {
                 let mut index = (self.bank_registers[0] | 0x01) as usize;
-                index %= (self.prg.len() as usize) / 0x400;
+                index %= (self.chr.len() as usize) / 0x400;
                 let begin_bank_offset = index * 0x400;
                 return (begin_bank_offset+offset as usize) as usize;
             } else if bank == 6 {
@@ -656,7 +821,7 @@ impl Mapper for Mapper4 {
             let chr_addr = self.get_chr_addr(addr);
             return self.chr[chr_addr];
         } else if addr >= 0x6000 && addr < 0x8000 {
-            return self.save_ram[(addr-0x6000) as usize];
+            return read_prg_ram(&self.save_ram, addr);
         } else if addr >= 0x8000 {
             let prg_addr = self.get_prg_addr(addr);
             return self.prg[prg_addr];
@@ -670,7 +835,7 @@ impl Mapper for Mapper4 {
             let chr_addr = self.get_chr_addr(addr);
             self.chr[chr_addr] = data;
         } else if addr >= 0x6000 && addr < 0x8000 {
-            self.save_ram[(addr-0x6000) as usize] = data;
+            write_prg_ram(&mut self.save_ram, addr, data);
         } else if addr >= 0x8000 {
             self.write_register(addr, data);
         } else {
@@ -682,7 +847,11 @@ impl Mapper for Mapper4 {
         return self.chr.clone();
     }
 
-    fn step(&mut self, ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>, cpu: &mut cpu::CPU<cpu::CPUMemory>) {
+    fn get_prg_ram(&mut self) -> &mut [u8] {
+        return &mut self.save_ram;
+    }
+
+    fn step(&mut self, ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>) {
         if ppu.borrow().cycle() != 260 {
             return;
         }
@@ -697,28 +866,62 @@ impl Mapper for Mapper4 {
         } else {
             self.irq_counter -= 1;
             if self.irq_counter == 0 && self.irq_enable {
-                cpu.set_irq();
+                self.irq_pending = true;
             }
         }
     }
+
+    fn irq(&self) -> bool {
+        return self.irq_pending;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        save_memory_state(state, &self.prg, &self.chr, &self.save_ram);
+        state.write_bytes(&self.bank_registers);
+        state.write_u8(self.selected_bank_register);
+        state.write_u8(self.prg_bank_mode);
+        state.write_u8(self.chr_bank_mode);
+        state.write_bool(self.irq_enable);
+        state.write_u8(self.irq_counter);
+        state.write_u8(self.irq_counter_reload_value);
+        state.write_bool(self.irq_pending);
+        state.write_bool(self.startup_banks);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        load_memory_state(state, &self.prg, &mut self.chr, &mut self.save_ram)?;
+        // The bank registers hold any 8 bit value and are wrapped to the cartridge's size when used.
+        state.read_bytes_into(&mut self.bank_registers)?;
+        self.selected_bank_register = check_state_register(state.read_u8()?, 8, "MMC3 bank register select")?;
+        self.prg_bank_mode = check_state_register(state.read_u8()?, 2, "MMC3 PRG bank mode")?;
+        self.chr_bank_mode = check_state_register(state.read_u8()?, 2, "MMC3 CHR bank mode")?;
+        self.irq_enable = state.read_bool()?;
+        self.irq_counter = state.read_u8()?;
+        self.irq_counter_reload_value = state.read_u8()?;
+        self.irq_pending = state.read_bool()?;
+        self.startup_banks = state.read_bool()?;
+        return Ok(());
+    }
 }
 
 struct Mapper7 {
     chr: Vec<u8>,
     prg: Vec<u8>,
-    save_ram: [u8; 8192],
+    save_ram: Vec<u8>,
     nametable_mirror_type: Rc<RefCell<Box<ppu::NametableMirroring>>>,
     selected_bank: u8,
+    bus_conflicts: bool,
 }
 
 impl Mapper7 {
-    fn new(chr: Vec<u8>, prg: Vec<u8>, nametable_mirror_type: Rc<RefCell<Box<ppu::NametableMirroring>>>) -> Mapper7 {
+    fn new(chr: Vec<u8>, prg: Vec<u8>, prg_ram_size: usize, nametable_mirror_type: Rc<RefCell<Box<ppu::NametableMirroring>>>, bus_conflicts: bool) -> Mapper7 {
         return Mapper7{
             chr: chr,
             prg: prg,
-            save_ram: [0; 8192],
+            save_ram: vec![0; prg_ram_size],
             selected_bank: 0,
             nametable_mirror_type: nametable_mirror_type,
+            bus_conflicts: bus_conflicts,
         }
     }
 }
@@ -728,7 +931,7 @@ impl Mapper for Mapper7 {
         if addr < 0x2000 {
             return self.chr[addr as usize];
         } else if addr >= 0x6000 && addr < 0x8000 {
-            return self.save_ram[(addr-0x6000) as usize];
+            return read_prg_ram(&self.save_ram, addr);
         } else if addr >= 0x8000 {
             let address = ((self.selected_bank as u32)*0x8000 as u32) + (addr-0x8000) as u32;
             return self.prg[address as usize];
@@ -741,8 +944,12 @@ impl Mapper for Mapper7 {
         if addr < 0x2000 {
             self.chr[addr as usize] = data;
         } else if addr >= 0x6000 && addr < 0x8000 {
-            self.save_ram[(addr-0x6000) as usize] = data;
+            write_prg_ram(&mut self.save_ram, addr, data);
         } else if addr >= 0x8000 {
+            let mut data = data;
+            if self.bus_conflicts {
+                data &= self.read(addr);
+            }
             self.selected_bank = data & 7;
             match (data >> 4) & 1 {
                 0 => {
@@ -762,32 +969,62 @@ impl Mapper for Mapper7 {
         return self.chr.clone();
     }
 
-    fn step(&mut self, _ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>, _cpu: &mut cpu::CPU<cpu::CPUMemory>) {}
+    fn get_prg_ram(&mut self) -> &mut [u8] {
+        return &mut self.save_ram;
+    }
+
+    fn step(&mut self, _ppu: &Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        save_memory_state(state, &self.prg, &self.chr, &self.save_ram);
+        state.write_u8(self.selected_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        load_memory_state(state, &self.prg, &mut self.chr, &mut self.save_ram)?;
+        self.selected_bank = state.read_u8()?;
+        return Ok(());
+    }
 }
 
 
-pub fn create_mapper(mapper: u8, chr: Vec<u8>, prg: Vec<u8>, nametable_mirror_type: Rc<RefCell<Box<ppu::NametableMirroring>>>) -> Box<dyn Mapper> {
-    match mapper{
+pub fn create_mapper(ines_data: ines::INESData, nametable_mirror_type: Rc<RefCell<Box<ppu::NametableMirroring>>>) -> Result<Box<dyn Mapper>, ines::RomError> {
+    let prg = ines_data.prg;
+    let mut chr = ines_data.chr;
+    // Games without CHR ROM use CHR RAM instead. Every mapper expects at least 8KB of it.
+    if chr.is_empty() {
+        let chr_ram_size = ines_data.chr_ram_size + ines_data.chr_nvram_size;
+        chr = vec![0u8; chr_ram_size.max(0x2000)];
+    }
+    let prg_ram_size = (ines_data.prg_ram_size + ines_data.prg_nvram_size).min(0x2000);
+    // NES 2.0 submapper 2 of the discrete logic mappers marks boards with bus conflicts,
+    // 1 marks boards without and 0 leaves it unspecified.
+    // https://wiki.nesdev.com/w/index.php/NES_2.0_submappers
+    // Boards with bus conflicts leave the ROM driving the data bus while a register is written,
+    // so the value written is ANDed with the ROM byte at that address.
+    // https://wiki.nesdev.com/w/index.php/Bus_conflict
+    let bus_conflicts = ines_data.submapper == 2;
+    match ines_data.mapper {
         0 => {
-            return Box::new(Mapper0::new(chr, prg));
+            return Ok(Box::new(Mapper0::new(chr, prg, prg_ram_size)));
         },
         1 => {
-            return Box::new(Mapper1::new(chr, prg, nametable_mirror_type));
+            return Ok(Box::new(Mapper1::new(chr, prg, prg_ram_size, nametable_mirror_type)));
         },
         2 => {
-            return Box::new(Mapper2::new(chr, prg));
+            return Ok(Box::new(Mapper2::new(chr, prg, prg_ram_size, bus_conflicts)));
         },
         3 => {
-            return Box::new(Mapper3::new(chr, prg));
+            return Ok(Box::new(Mapper3::new(chr, prg, prg_ram_size, bus_conflicts)));
         },
         4 => {
-            return Box::new(Mapper4::new(chr, prg, nametable_mirror_type));
+            return Ok(Box::new(Mapper4::new(chr, prg, prg_ram_size, nametable_mirror_type)));
         }
         7 => {
-            return Box::new(Mapper7::new(chr, prg, nametable_mirror_type));
+            return Ok(Box::new(Mapper7::new(chr, prg, prg_ram_size, nametable_mirror_type, bus_conflicts)));
         },
-        _ => {
-            panic!("Game uses unsupported mapper {:}", mapper);
+        mapper => {
+            return Err(ines::RomError::UnsupportedMapper(mapper));
         }
     }
-}
\ No newline at end of file
+}
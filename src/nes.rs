@@ -1,53 +1,62 @@
-extern crate time;
+// Explicit returns and field names, like the components it wires together.
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
 
 use apu;
 use controller;
 use cpu;
+use disasm;
+use filter;
 use ines;
 use mapper;
 use ppu;
+use state;
+use trace;
 
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{RefCell, RefMut};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::SyncSender;
 
-const SAMPLE_RATE: f64 = 44100.0;
-
 pub struct Nes {
     apu: Rc<RefCell<apu::APU>>,
     ppu: Rc<RefCell<ppu::PPU<ppu::PPUMemory>>>,
     controller1: Rc<RefCell<controller::Controller>>,
+    controller2: Rc<RefCell<controller::Controller>>,
     cpu: cpu::CPU<cpu::CPUMemory>,
-    ppu_step_output: ppu::StepOutput,
-    cpu_cycle_count: u64,
-    apu_last_sample_time: u64,
+    // Samples read from the APU, kept to reuse the allocation.
+    audio_frames: Vec<(f32, f32)>,
     mapper: Rc<RefCell<Box<dyn mapper::Mapper>>>,
+    audio_sender: Option<SyncSender<(f32, f32)>>,
+    audio_frames_sent: u64,
+    // Where battery backed PRG RAM is persisted. None if the cartridge has no battery.
+    save_file_name: Option<PathBuf>,
+    // How much of the start of PRG RAM the battery keeps, only this much is persisted.
+    battery_ram_size: usize,
+    // The battery RAM contents as of the last load or flush, used to skip unchanged writes.
+    saved_battery_ram: Vec<u8>,
 }
 
 impl Nes {
 
-    pub fn new(gamefile: String) -> Nes {
-        let ines_data = ines::load_ines_file(&gamefile);
+    pub fn new(gamefile: String) -> Result<Nes, ines::RomError> {
+        let ines_data = ines::load_ines_file(&gamefile)?;
+        let battery = ines_data.battery;
+        let prg_nvram_size = ines_data.prg_nvram_size;
 
-        let nametable_mirror_type;
-        match ines_data.nametable_mirroring{
-            0 => {
-                nametable_mirror_type = ppu::NametableMirrorType::Horizontal;
-            },
-            1 => {
-                nametable_mirror_type = ppu::NametableMirrorType::Vertical;
-            },
-            4 => {
-                nametable_mirror_type = ppu::NametableMirrorType::Four;
-            }
-            _ => unimplemented!(),
+        let nametable_mirror_type = match ines_data.nametable_mirroring{
+            0 => ppu::NametableMirrorType::Horizontal,
+            1 => ppu::NametableMirrorType::Vertical,
+            4 => ppu::NametableMirrorType::Four,
+            m => return Err(ines::RomError::UnsupportedMirroring(m)),
         };
         let nametable_mirror = Box::new(ppu::NametableMirroring{
             nametable_mirror_type: nametable_mirror_type,
         });
         let rc_nametable_mirror = Rc::new(RefCell::new(nametable_mirror));
 
-        let m: Box<dyn mapper::Mapper> = mapper::create_mapper(ines_data.mapper, ines_data.chr, ines_data.prg, Rc::clone(&rc_nametable_mirror));
+        let m: Box<dyn mapper::Mapper> = mapper::create_mapper(ines_data, Rc::clone(&rc_nametable_mirror))?;
         let mapper = Rc::new(RefCell::new(m));
         let ppu_memory = ppu::PPUMemory{
             mapper: Rc::clone(&mapper),
@@ -55,6 +64,7 @@ impl Nes {
         };
 
         let controller1 = Rc::new(RefCell::new(controller::Controller::new()));
+        let controller2 = Rc::new(RefCell::new(controller::Controller::new()));
 
         let apu = Rc::new(RefCell::new(apu::APU::new()));
         let ppu = Rc::new(RefCell::new(ppu::PPU::new(ppu_memory)));
@@ -64,63 +74,233 @@ impl Nes {
             ppu: Rc::clone(&ppu),
             apu: Rc::clone(&apu),
             controller1: Rc::clone(&controller1),
-            added_stall: 0,
+            controller2: Rc::clone(&controller2),
+            dmc_stall: 0,
+            oam_dma_page: None,
+            access_hook: None,
+            irq_sources: 0,
+            open_bus: 0,
+            frame_change: false,
+            cycle: 0,
         };
+        let mut save_file_name = None;
+        let mut battery_ram_size = 0;
+        let mut saved_battery_ram = Vec::new();
+        if battery {
+            battery_ram_size = prg_nvram_size.min(mapper.borrow_mut().get_prg_ram().len());
+            let file_name = Path::new(&gamefile).with_extension("sav");
+            // A missing or unreadable save file just means the game starts without save data.
+            if let Ok(data) = fs::read(&file_name) {
+                let mut m = mapper.borrow_mut();
+                let battery_ram = &mut m.get_prg_ram()[..battery_ram_size];
+                let len = data.len().min(battery_ram.len());
+                battery_ram[..len].copy_from_slice(&data[..len]);
+            }
+            saved_battery_ram = mapper.borrow_mut().get_prg_ram()[..battery_ram_size].to_vec();
+            save_file_name = Some(file_name);
+        }
+
         let cpu = cpu::CPU::new(memory);
-        return Nes{
+        return Ok(Nes{
             apu: apu,
             ppu: Rc::clone(&ppu),
             controller1: Rc::clone(&controller1),
+            controller2: Rc::clone(&controller2),
             cpu: cpu,
-            ppu_step_output: ppu::StepOutput{
-                nmi: false,
-                frame_change: false,
-            },
-            cpu_cycle_count: 0,
-            apu_last_sample_time: time::precise_time_ns(),
+            audio_frames: Vec::new(),
             mapper: Rc::clone(&mapper),
-        };
+            audio_sender: None,
+            audio_frames_sent: 0,
+            save_file_name: save_file_name,
+            battery_ram_size: battery_ram_size,
+            saved_battery_ram: saved_battery_ram,
+        });
     }
 
-    pub fn step(&mut self, audio_sender: & SyncSender<f32>) -> (u64, bool) {
-        let mut frame_change = false;
-        let mut nmi = false;
-        let step_cpu_cycles = self.cpu.step(self.ppu_step_output.nmi);
-        self.ppu_step_output.nmi = false;
-        for _ in 0..(3*step_cpu_cycles) {
-            self.ppu_step_output = self.ppu.borrow_mut().step();
-            if self.ppu_step_output.nmi {
-                nmi = true;
-            }
-            if self.ppu_step_output.frame_change {
-                frame_change = true;
+    // Audio frames are only sent when a sender has been set, so a headless Nes can be
+    // stepped without anything draining them. Each frame is a (left, right) pair of samples,
+    // the two are the same unless the APU's output is stereo.
+    pub fn set_audio_sender(&mut self, audio_sender: Option<SyncSender<(f32, f32)>>) {
+        self.audio_sender = audio_sender;
+    }
+
+    // The number of frames sent to the audio sender. A frontend compares this with the frames
+    // it has played to know how many are still waiting.
+    pub fn audio_frames_sent(&self) -> u64 {
+        return self.audio_frames_sent;
+    }
+
+    // The rate of the samples sent to the audio sender, 44100 by default.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.apu.borrow_mut().set_sample_rate(sample_rate);
+    }
+
+    // Scale the sample rate by a ratio close to 1 without dropping samples, so a frontend can
+    // keep its audio queue at a steady length.
+    pub fn set_audio_rate_adjustment(&mut self, ratio: f64) {
+        self.apu.borrow_mut().set_rate_adjustment(ratio);
+    }
+
+    // The APU, for its per-channel mixing controls.
+    pub fn apu(&self) -> RefMut<'_, apu::APU> {
+        return self.apu.borrow_mut();
+    }
+
+    // The output filtering to emulate, the NES's by default.
+    pub fn set_filter_profile(&mut self, profile: filter::FilterProfile) {
+        self.apu.borrow_mut().set_filter_profile(profile);
+    }
+
+    // Run one CPU step. The PPU, APU and mapper are advanced by the CPU as it accesses the bus.
+    pub fn step(&mut self) -> (u64, bool) {
+        let step_cpu_cycles = self.cpu.step();
+        let frame_change = self.cpu.memory().frame_change;
+        self.cpu.memory().frame_change = false;
+        // The APU synthesizes samples as it runs, they are always read so they don't pile up.
+        self.audio_frames.clear();
+        self.apu.borrow_mut().read_frames(&mut self.audio_frames);
+        if let Some(ref audio_sender) = self.audio_sender {
+            for frame in self.audio_frames.iter() {
+                audio_sender.send(*frame).unwrap();
             }
-            self.mapper.borrow_mut().step(&self.ppu, &mut self.cpu);
-        }
-        if frame_change {
-            self.ppu_step_output.frame_change = true;
+            self.audio_frames_sent += self.audio_frames.len() as u64;
         }
-        if nmi {
-            self.ppu_step_output.nmi = true;
-        }
-        for _ in 0..step_cpu_cycles {
-            self.apu.borrow_mut().step(self.cpu_cycle_count, &mut self.cpu);
+        return (step_cpu_cycles, frame_change);
+    }
 
-            let t = time::precise_time_ns();
-            if (t - self.apu_last_sample_time) as f64 >= (1000000000.0 as f64/SAMPLE_RATE as f64) {
-                audio_sender.send(self.apu.borrow_mut().output()).unwrap();
-                self.apu_last_sample_time = t - ((t - self.apu_last_sample_time) as u64 - (1000000000.0 as f64/SAMPLE_RATE as f64) as u64);
-            }
+    // Press the reset button. Unlike power cycling, RAM and the cartridge keep their contents.
+    pub fn reset(&mut self) {
+        // Reset silences the APU and turns off rendering and NMIs.
+        self.apu.borrow_mut().write_register(0x4015, 0);
+        self.ppu.borrow_mut().write_register(0x2000, 0);
+        self.ppu.borrow_mut().write_register(0x2001, 0);
+        self.cpu.reset();
+    }
+
+    pub fn has_battery(&self) -> bool {
+        return self.save_file_name.is_some();
+    }
 
-            self.cpu_cycle_count += 1;
+    // Write battery backed PRG RAM to the .sav file next to the ROM.
+    // This does nothing if the cartridge has no battery or the RAM hasn't changed since the last flush.
+    pub fn save_battery_ram(&mut self) -> io::Result<()> {
+        let file_name = match self.save_file_name {
+            Some(ref file_name) => file_name.clone(),
+            None => return Ok(()),
+        };
+        let battery_ram = self.mapper.borrow_mut().get_prg_ram()[..self.battery_ram_size].to_vec();
+        if battery_ram == self.saved_battery_ram {
+            return Ok(());
         }
-        return (step_cpu_cycles, frame_change);
+        fs::write(&file_name, &battery_ram)?;
+        self.saved_battery_ram = battery_ram;
+        return Ok(());
+    }
+
+    // Snapshot the whole console so it can later be restored with load_state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = state::StateWriter::new();
+        self.cpu.save_state(&mut state);
+        self.ppu.borrow().save_state(&mut state);
+        self.apu.borrow().save_state(&mut state);
+        self.controller1.borrow().save_state(&mut state);
+        self.controller2.borrow().save_state(&mut state);
+        self.mapper.borrow().save_state(&mut state);
+        return state.into_bytes();
+    }
+
+    // Restore a snapshot created by save_state for the same game.
+    // If an error is returned the console is left in an unspecified state.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), state::StateError> {
+        let mut state = state::StateReader::new(data)?;
+        self.cpu.load_state(&mut state)?;
+        self.ppu.borrow_mut().load_state(&mut state)?;
+        self.apu.borrow_mut().load_state(&mut state)?;
+        self.controller1.borrow_mut().load_state(&mut state)?;
+        self.controller2.borrow_mut().load_state(&mut state)?;
+        self.mapper.borrow_mut().load_state(&mut state)?;
+        return Ok(());
     }
 
     pub fn set_controller1_button_state(&mut self, button: controller::Buttons, state: bool) {
         self.controller1.borrow_mut().set_button_state(button, state);
     }
 
+    pub fn set_controller2_button_state(&mut self, button: controller::Buttons, state: bool) {
+        self.controller2.borrow_mut().set_button_state(button, state);
+    }
+
+    // Read from the cartridge through the mapper, without going through the CPU bus.
+    pub fn read_cartridge(&mut self, addr: u16) -> u8 {
+        return self.mapper.borrow_mut().read(addr);
+    }
+
+    // Read through the CPU bus without side effects, see cpu::Memory::peek.
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        return self.cpu.peek(addr);
+    }
+
+    // Write RAM or PRG RAM without side effects, see cpu::Memory::poke.
+    pub fn poke(&mut self, addr: u16, data: u8) -> bool {
+        return self.cpu.poke(addr, data);
+    }
+
+    // Disassemble start..=end as the CPU currently sees it, with the mapper's current banks.
+    pub fn disassemble(&mut self, start: u16, end: u16) -> Vec<disasm::Instruction> {
+        let cpu = &mut self.cpu;
+        return disasm::disassemble(&mut |addr| cpu.peek(addr), start, end);
+    }
+
+    // The hardware register labels, plus labels for the handlers the vectors currently point at.
+    pub fn labels(&mut self) -> disasm::Labels {
+        let cpu = &mut self.cpu;
+        let mut labels = disasm::Labels::hardware();
+        labels.add_vector_targets(&mut |addr| cpu.peek(addr));
+        return labels;
+    }
+
+    // Trace every instruction the CPU executes, or stop tracing with None.
+    pub fn set_tracer(&mut self, tracer: Option<trace::Tracer>) {
+        match tracer {
+            Some(mut tracer) => {
+                let ppu = Rc::clone(&self.ppu);
+                self.cpu.set_trace_hook(Some(Box::new(move |instruction: &cpu::InstructionTrace| {
+                    let (scanline, dot) = {
+                        let ppu = ppu.borrow();
+                        (ppu.scanline(), ppu.cycle())
+                    };
+                    tracer.record(trace::TraceEntry{
+                        instruction: *instruction,
+                        scanline: scanline,
+                        dot: dot,
+                    });
+                })));
+            },
+            None => self.cpu.set_trace_hook(None),
+        }
+    }
+
+    // Called with every read and write on the CPU bus.
+    pub fn set_cpu_memory_hook(&mut self, memory_hook: Option<cpu::MemoryHook>) {
+        self.cpu.set_memory_hook(memory_hook);
+    }
+
+    // Called with every read and write the CPU makes to PPU memory through PPUDATA.
+    pub fn set_ppu_memory_hook(&mut self, memory_hook: Option<cpu::MemoryHook>) {
+        self.ppu.borrow_mut().set_memory_hook(memory_hook);
+    }
+
+    // Direct access to the CPU for test harnesses and debugging tools.
+    pub fn cpu(&mut self) -> &mut cpu::CPU<cpu::CPUMemory> {
+        return &mut self.cpu;
+    }
+
+    // The PPU scanline (0-261) and the cycle within it (0-340).
+    pub fn ppu_position(&self) -> (u16, u16) {
+        let ppu = self.ppu.borrow();
+        return (ppu.scanline(), ppu.cycle());
+    }
+
     pub fn get_frame_buffer(&mut self) -> Vec<u8> {
         return self.ppu.borrow_mut().frame_buffer.clone().into_raw();
     }
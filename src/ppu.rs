@@ -1,5 +1,18 @@
+// The rendering pipeline spells out its bit operations, ranges and returns dot by dot.
+#![allow(
+    clippy::assign_op_pattern,
+    clippy::identity_op,
+    clippy::manual_is_multiple_of,
+    clippy::manual_range_contains,
+    clippy::needless_return,
+    clippy::redundant_field_names,
+)]
+
 use image::{ImageBuffer,Rgb};
+use cpu::{AccessKind, MemoryAccess, MemoryHook};
 use mapper;
+use state::{StateError, StateReader, StateWriter};
+use std::mem;
 use std::rc::Rc;
 use std::cell::RefCell;
 
@@ -91,12 +104,41 @@ impl NametableMirroring {
     pub fn get_nametable_mirror_type(&mut self) -> NametableMirrorType {
         return self.nametable_mirror_type.clone();
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        let mirror_type = match self.nametable_mirror_type {
+            NametableMirrorType::Horizontal => 0,
+            NametableMirrorType::Vertical => 1,
+            NametableMirrorType::Single0 => 2,
+            NametableMirrorType::Single1 => 3,
+            NametableMirrorType::Four => 4,
+        };
+        state.write_u8(mirror_type);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.nametable_mirror_type = match state.read_u8()? {
+            0 => NametableMirrorType::Horizontal,
+            1 => NametableMirrorType::Vertical,
+            2 => NametableMirrorType::Single0,
+            3 => NametableMirrorType::Single1,
+            4 => NametableMirrorType::Four,
+            _ => return Err(StateError::Mismatch("nametable mirroring")),
+        };
+        return Ok(());
+    }
 }
 
 pub trait Memory {
     fn read(&mut self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, data: u8);
     fn get_nametable_index(&mut self, address: u16) -> u16;
+
+    // Hooks to persist any state held by the memory in a save state.
+    fn save_state(&self, _state: &mut StateWriter) {}
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+        return Ok(());
+    }
 }
 
 pub struct PPU<T: Memory> {
@@ -166,10 +208,12 @@ pub struct PPU<T: Memory> {
     cycle: u16, // 0-340
 
     pub frame_buffer: ImageBuffer<Rgb<u8>, Vec<u8>>,
+
+    // Called with every access made through PPUDATA, for debugging tools.
+    access_hook: Option<MemoryHook>,
 }
 
 pub struct StepOutput {
-    pub nmi: bool,
     pub frame_change: bool,
 }
 
@@ -238,6 +282,8 @@ impl<T: Memory> PPU<T> {
             cycle: 0,
 
             frame_buffer: ImageBuffer::new(256, 240),
+
+            access_hook: None,
         }
     }
 
@@ -274,10 +320,6 @@ impl<T: Memory> PPU<T> {
         };
     }
 
-    pub fn wrote_to_register(&mut self, data: u8) {
-        self.previous_write_data = data;
-    }
-
     pub fn write_register(&mut self, addr: u16, data: u8) {
         if addr < 0x2000 || addr >= 0x4000 {
             panic!("read register addr {:} is out of range", addr);
@@ -348,7 +390,7 @@ impl<T: Memory> PPU<T> {
     // write $2004: Write OAM data
     fn write_oam_data(&mut self, data: u8) {
         self.oam_data[self.oam_addr as usize] = data;
-        self.oam_addr += 1;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
     // write $2005: PPUSCROLL
@@ -378,6 +420,7 @@ impl<T: Memory> PPU<T> {
 
     // write $2007: PPUDATA
     fn write_data(&mut self, data: u8) {
+        self.call_access_hook(AccessKind::Write, data);
         if self.v < 0x2000 {
             self.mem.write(self.v, data);
         } else if self.v < 0x3000 {
@@ -400,16 +443,6 @@ impl<T: Memory> PPU<T> {
         }
     }
 
-    // write $4014: OAMDMA
-    pub fn write_oamdma(&mut self, oam_data: [u8; 256]) {
-        self.oam_data = oam_data;
-        let mut i = 1;
-        while i <=256 {
-            i += 4;
-        }
-        self.oam_addr += 255;
-    }
-
     // read $2007: PPUDATA
     fn read_data(&mut self) -> u8 {
         let mut data;
@@ -429,10 +462,9 @@ impl<T: Memory> PPU<T> {
             // This gets us past that issue.
             data = 0;
         }
+        self.call_access_hook(AccessKind::Read, data);
         if self.v <= 0x3EFF {
-            let buffer_data = self.ppu_data_buffer;
-            self.ppu_data_buffer = data;
-            data = buffer_data;
+            mem::swap(&mut self.ppu_data_buffer, &mut data);
         }
 
         if self.vram_increment_flag == 0 {
@@ -443,6 +475,17 @@ impl<T: Memory> PPU<T> {
         return data;
     }
 
+    fn call_access_hook(&mut self, kind: AccessKind, value: u8) {
+        let address = self.v & 0x3FFF;
+        if let Some(ref mut access_hook) = self.access_hook {
+            access_hook(&MemoryAccess{
+                kind: kind,
+                address: address,
+                value: value,
+            });
+        }
+    }
+
     // read $2002 PPUSTATUS
     fn read_status(&mut self) -> u8 {
         let mut status: u8 = self.previous_write_data & 0x1F;
@@ -551,7 +594,7 @@ impl<T: Memory> PPU<T> {
         for i in 0..self.sprite_count {
             let x_range = (self.cycle as i32 - 1) - self.sprite_positions[i as usize] as i32;
             if x_range >= 0 && x_range <=7 {
-                let pattern_bits = ((self.high_bit_sprite_bitmaps[i as usize] >> (7 - x_range) & 1) << 1) | ((self.low_bit_sprite_bitmaps[i as usize] >> (7 - x_range) & 1));
+                let pattern_bits = ((self.high_bit_sprite_bitmaps[i as usize] >> (7 - x_range) & 1) << 1) | (self.low_bit_sprite_bitmaps[i as usize] >> (7 - x_range) & 1);
                 let attr_bits = (self.sprite_attributes[i as usize] & 0x03) << 2;
                 let pixel = attr_bits | pattern_bits;
                 if pixel%4 == 0 {
@@ -687,7 +730,6 @@ impl<T: Memory> PPU<T> {
     // step the ppu one cycle.
     pub fn step(&mut self) -> StepOutput {
         let mut step_output = StepOutput{
-            nmi: false,
             frame_change: false,
         };
 
@@ -766,9 +808,6 @@ impl<T: Memory> PPU<T> {
         if self.cycle == 1 && self.scanline == 241 {
             self.nmi_occurred = true;
             step_output.frame_change = true;
-            if self.nmi_occurred && self.nmi_output {
-                step_output.nmi = true;
-            }
         }
 
         if self.cycle == 1 && self.scanline == 261 {
@@ -790,6 +829,113 @@ impl<T: Memory> PPU<T> {
         return step_output;
     }
 
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.nmi_occurred);
+        state.write_bool(self.nmi_output);
+        state.write_u8(self.base_nametable_addr_flag);
+        state.write_u8(self.vram_increment_flag);
+        state.write_u8(self.sprite_table_addr_flag);
+        state.write_u8(self.background_table_addr_flag);
+        state.write_u8(self.sprite_size_flag);
+        state.write_u8(self.master_slave_flag);
+        state.write_u8(self.greyscale_flag);
+        state.write_u8(self.show_left_background_flag);
+        state.write_u8(self.show_left_sprites_flag);
+        state.write_u8(self.show_background_flag);
+        state.write_u8(self.show_sprites_flag);
+        state.write_u8(self.emphasize_red_flag);
+        state.write_u8(self.emphasize_green_flag);
+        state.write_u8(self.emphasize_blue_flag);
+        state.write_bool(self.sprite_overflow_flag);
+        state.write_bool(self.sprite_zero_hit_flag);
+        state.write_u16(self.v);
+        state.write_u16(self.t);
+        state.write_u8(self.x);
+        state.write_u8(self.w);
+        state.write_u8(self.previous_write_data);
+        state.write_bytes(&self.nametable_data);
+        state.write_u8(self.oam_addr);
+        state.write_bytes(&self.oam_data);
+        state.write_u8(self.ppu_data_buffer);
+        state.write_bytes(&self.palette_data);
+        state.write_u8(self.nametable_byte);
+        state.write_u8(self.attribute_byte);
+        state.write_u8(self.low_bg_tile_byte);
+        state.write_u8(self.high_bg_tile_byte);
+        state.write_u16(self.low_bit_bitmap_bg_shift_register);
+        state.write_u16(self.high_bit_bitmap_bg_shift_register);
+        state.write_u16(self.low_bit_palette_attr_bg_shift_register);
+        state.write_u16(self.high_bit_palette_attr_bg_shift_register);
+        state.write_bytes(&self.sprite_attributes);
+        state.write_bytes(&self.sprite_positions);
+        state.write_bytes(&self.sprite_indexes);
+        state.write_u8(self.sprite_count);
+        state.write_bytes(&self.low_bit_sprite_bitmaps);
+        state.write_bytes(&self.high_bit_sprite_bitmaps);
+        state.write_u16(self.scanline);
+        state.write_u16(self.cycle);
+        self.mem.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.nmi_occurred = state.read_bool()?;
+        self.nmi_output = state.read_bool()?;
+        self.base_nametable_addr_flag = state.read_u8()?;
+        self.vram_increment_flag = state.read_u8()?;
+        self.sprite_table_addr_flag = state.read_u8()?;
+        self.background_table_addr_flag = state.read_u8()?;
+        self.sprite_size_flag = state.read_u8()?;
+        self.master_slave_flag = state.read_u8()?;
+        self.greyscale_flag = state.read_u8()?;
+        self.show_left_background_flag = state.read_u8()?;
+        self.show_left_sprites_flag = state.read_u8()?;
+        self.show_background_flag = state.read_u8()?;
+        self.show_sprites_flag = state.read_u8()?;
+        self.emphasize_red_flag = state.read_u8()?;
+        self.emphasize_green_flag = state.read_u8()?;
+        self.emphasize_blue_flag = state.read_u8()?;
+        self.sprite_overflow_flag = state.read_bool()?;
+        self.sprite_zero_hit_flag = state.read_bool()?;
+        self.v = state.read_u16()?;
+        self.t = state.read_u16()?;
+        self.x = state.read_u8()?;
+        self.w = state.read_u8()?;
+        self.previous_write_data = state.read_u8()?;
+        state.read_bytes_into(&mut self.nametable_data)?;
+        self.oam_addr = state.read_u8()?;
+        state.read_bytes_into(&mut self.oam_data)?;
+        self.ppu_data_buffer = state.read_u8()?;
+        state.read_bytes_into(&mut self.palette_data)?;
+        self.nametable_byte = state.read_u8()?;
+        self.attribute_byte = state.read_u8()?;
+        self.low_bg_tile_byte = state.read_u8()?;
+        self.high_bg_tile_byte = state.read_u8()?;
+        self.low_bit_bitmap_bg_shift_register = state.read_u16()?;
+        self.high_bit_bitmap_bg_shift_register = state.read_u16()?;
+        self.low_bit_palette_attr_bg_shift_register = state.read_u16()?;
+        self.high_bit_palette_attr_bg_shift_register = state.read_u16()?;
+        state.read_bytes_into(&mut self.sprite_attributes)?;
+        state.read_bytes_into(&mut self.sprite_positions)?;
+        state.read_bytes_into(&mut self.sprite_indexes)?;
+        self.sprite_count = state.read_u8()?;
+        state.read_bytes_into(&mut self.low_bit_sprite_bitmaps)?;
+        state.read_bytes_into(&mut self.high_bit_sprite_bitmaps)?;
+        self.scanline = state.read_u16()?;
+        self.cycle = state.read_u16()?;
+        return self.mem.load_state(state);
+    }
+
+    // Called with every read and write the CPU makes to PPU memory through PPUDATA. The
+    // fetches made while rendering aren't reported.
+    pub fn set_memory_hook(&mut self, memory_hook: Option<MemoryHook>) {
+        self.access_hook = memory_hook;
+    }
+
+    // The PPU holds the CPU's NMI line asserted while the vblank flag and PPUCTRL's NMI enable are both set.
+    pub fn nmi_line(&self) -> bool {
+        return self.nmi_occurred && self.nmi_output;
+    }
+
     pub fn cycle(&self) -> u16 {
         return self.cycle;
     }
@@ -869,4 +1015,13 @@ impl Memory for PPUMemory {
         }
         return (table_index*1024)+table_offset;
     }
+
+    // The mapper is shared with the CPU memory and is saved by the Nes.
+    fn save_state(&self, state: &mut StateWriter) {
+        self.nametable_mirror.borrow().save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        return self.nametable_mirror.borrow_mut().load_state(state);
+    }
 }
@@ -0,0 +1,167 @@
+// Every reader and writer returns explicitly, matching the components that call them.
+#![allow(
+    clippy::needless_range_loop,
+    clippy::needless_return,
+    clippy::new_without_default,
+    clippy::redundant_field_names,
+)]
+
+use std::error;
+use std::fmt;
+
+// Save states are a flat little endian byte stream. Every component writes
+// its fields in a fixed order and reads them back in the same order.
+const STATE_MAGIC_NUMBER: [u8; 4] = [0x4E, 0x45, 0x53, 0x53];
+const STATE_VERSION: u8 = 1;
+
+#[derive(Debug)]
+pub enum StateError {
+    // The data does not start with the save state magic number.
+    BadMagic,
+    // The save state was written by an incompatible version of the emulator.
+    UnsupportedVersion(u8),
+    // The data ended before every component was restored.
+    UnexpectedEnd,
+    // The save state does not match the loaded game, e.g. it was saved with a different ROM.
+    Mismatch(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "save state has an invalid magic number"),
+            StateError::UnsupportedVersion(v) => write!(f, "save state version {} is not supported", v),
+            StateError::UnexpectedEnd => write!(f, "save state data ended unexpectedly"),
+            StateError::Mismatch(what) => write!(f, "save state does not match the loaded game: {}", what),
+        }
+    }
+}
+
+impl error::Error for StateError {}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut writer = StateWriter{
+            data: Vec::new(),
+        };
+        writer.data.extend_from_slice(&STATE_MAGIC_NUMBER);
+        writer.write_u8(STATE_VERSION);
+        return writer;
+    }
+
+    pub fn write_u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    pub fn write_bool(&mut self, v: bool) {
+        self.write_u8(v as u8);
+    }
+
+    pub fn write_u16(&mut self, v: u16) {
+        self.data.push(v as u8);
+        self.data.push((v >> 8) as u8);
+    }
+
+    pub fn write_u32(&mut self, v: u32) {
+        self.write_u16(v as u16);
+        self.write_u16((v >> 16) as u16);
+    }
+
+    pub fn write_u64(&mut self, v: u64) {
+        self.write_u32(v as u32);
+        self.write_u32((v >> 32) as u32);
+    }
+
+    // Writes a length prefixed block of bytes.
+    pub fn write_bytes(&mut self, v: &[u8]) {
+        self.write_u32(v.len() as u32);
+        self.data.extend_from_slice(v);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        return self.data;
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<StateReader<'a>, StateError> {
+        let mut reader = StateReader{
+            data: data,
+            position: 0,
+        };
+        let mut magic_number = [0; 4];
+        for i in 0..4 {
+            magic_number[i] = reader.read_u8()?;
+        }
+        if magic_number != STATE_MAGIC_NUMBER {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.read_u8()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        return Ok(reader);
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        if self.position >= self.data.len() {
+            return Err(StateError::UnexpectedEnd);
+        }
+        let v = self.data[self.position];
+        self.position += 1;
+        return Ok(v);
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        return Ok(self.read_u8()? != 0);
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let lo = self.read_u8()? as u16;
+        let hi = self.read_u8()? as u16;
+        return Ok((hi << 8) | lo);
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let lo = self.read_u16()? as u32;
+        let hi = self.read_u16()? as u32;
+        return Ok((hi << 16) | lo);
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let lo = self.read_u32()? as u64;
+        let hi = self.read_u32()? as u64;
+        return Ok((hi << 32) | lo);
+    }
+
+    // Reads a length prefixed block of bytes.
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.read_u32()? as usize;
+        if self.data.len() - self.position < len {
+            return Err(StateError::UnexpectedEnd);
+        }
+        let bytes = &self.data[self.position..self.position+len];
+        self.position += len;
+        return Ok(bytes);
+    }
+
+    // Reads a length prefixed block of bytes into a fixed size buffer.
+    // The block must be exactly the size of the buffer.
+    pub fn read_bytes_into(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(StateError::Mismatch("memory size"));
+        }
+        buffer.copy_from_slice(bytes);
+        return Ok(());
+    }
+}
@@ -0,0 +1,113 @@
+// Explicit returns and field names, like the CPU it traces.
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+
+use cpu::InstructionTrace;
+use disasm;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::thread;
+
+// One executed instruction and where the PPU was when it started.
+#[derive(Clone, Copy, Debug)]
+pub struct TraceEntry {
+    pub instruction: InstructionTrace,
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+// Entries are written in the same layout as nestest.log, e.g.
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let instruction = &self.instruction;
+        let registers = &instruction.registers;
+        let disassembly = disasm::Instruction{
+            address: registers.pc,
+            bytes: instruction.bytes,
+            size: instruction.size,
+        };
+        return write!(f, "{:04X}  {:<8}  {:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            registers.pc, disassembly.hex_bytes(), disassembly.text(&disasm::Labels::new()), registers.a, registers.x, registers.y, registers.p, registers.sp,
+            self.scanline, self.dot, instruction.cycles);
+    }
+}
+
+// Logs executed instructions. Entries can be written out as they happen, kept in a ring
+// buffer of the most recent instructions, or both. The ring buffer is printed to stderr
+// if the emulator panics, so the instructions that led up to a crash can be seen.
+pub struct Tracer {
+    output: Option<Box<dyn Write>>,
+    // Only instructions with a PC inside this inclusive range are traced.
+    pc_range: Option<(u16, u16)>,
+    ring_buffer: VecDeque<TraceEntry>,
+    ring_buffer_size: usize,
+}
+
+impl Tracer {
+    // A ring buffer size of 0 turns the ring buffer off.
+    pub fn new(output: Option<Box<dyn Write>>, ring_buffer_size: usize) -> Tracer {
+        return Tracer{
+            output: output,
+            pc_range: None,
+            ring_buffer: VecDeque::with_capacity(ring_buffer_size),
+            ring_buffer_size: ring_buffer_size,
+        };
+    }
+
+    pub fn set_pc_range(&mut self, start: u16, end: u16) {
+        self.pc_range = Some((start, end));
+    }
+
+    pub fn record(&mut self, entry: TraceEntry) {
+        let pc = entry.instruction.registers.pc;
+        if let Some((start, end)) = self.pc_range {
+            if pc < start || pc > end {
+                return;
+            }
+        }
+
+        let mut write_failed = false;
+        if let Some(ref mut output) = self.output {
+            if let Err(e) = writeln!(output, "{}", entry) {
+                eprintln!("Failed to write trace, tracing to the output is turned off: {}", e);
+                write_failed = true;
+            }
+        }
+        if write_failed {
+            self.output = None;
+        }
+
+        if self.ring_buffer_size > 0 {
+            if self.ring_buffer.len() == self.ring_buffer_size {
+                self.ring_buffer.pop_front();
+            }
+            self.ring_buffer.push_back(entry);
+        }
+    }
+
+    // Write the ring buffer out, oldest instruction first.
+    pub fn dump_ring_buffer(&self, output: &mut dyn Write) -> io::Result<()> {
+        for entry in self.ring_buffer.iter() {
+            writeln!(output, "{}", entry)?;
+        }
+        return Ok(());
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        if let Some(ref mut output) = self.output {
+            let _ = output.flush();
+        }
+        // The tracer is dropped with the console while a panic unwinds.
+        if thread::panicking() && !self.ring_buffer.is_empty() {
+            let stderr = io::stderr();
+            let mut stderr = stderr.lock();
+            let _ = writeln!(stderr, "Last {} instructions before the panic:", self.ring_buffer.len());
+            let _ = self.dump_ring_buffer(&mut stderr);
+        }
+    }
+}
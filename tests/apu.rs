@@ -0,0 +1,331 @@
+#![allow(clippy::needless_return)]
+
+// The frame counter's IRQ and $4017 timing, the DMC's memory reader, and how the channels are mixed, run a CPU cycle at a time.
+// https://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+// https://wiki.nesdev.com/w/index.php/APU_DMC
+extern crate nese;
+
+use nese::apu::{Channel, APU};
+use nese::cpu::{BusCycle, Memory};
+use nese::filter::FilterProfile;
+
+const FRAME_IRQ_STATUS: u8 = 0x40;
+const DMC_ACTIVE_STATUS: u8 = 0x10;
+const DMC_IRQ_STATUS: u8 = 0x80;
+// $4010 flags, the fastest rate keeps the tests short.
+const DMC_IRQ_ENABLED: u8 = 0x80;
+const DMC_LOOP: u8 = 0x40;
+const DMC_FASTEST_RATE: u8 = 0x0F;
+// Enough cycles to play a 65 byte sample at the fastest rate.
+const DMC_TEST_CYCLES: u64 = 50000;
+// The frame counter sets its IRQ flag this many cycles into a 4 step sequence.
+const FRAME_IRQ_CYCLE: u64 = 29828;
+
+fn no_memory(_: u16) -> u8 {
+    return 0;
+}
+
+// Step the APU through CPU cycles up to, not including, `end`.
+fn run_cycles(apu: &mut APU, cycle: &mut u64, end: u64) {
+    while *cycle < end {
+        apu.step(*cycle, &mut no_memory);
+        *cycle += 1;
+    }
+}
+
+// Write $4017 during `write_cycle`, then return the cycle the frame IRQ flag is first set in.
+fn frame_irq_cycle_after_write(write_cycle: u64, value: u8) -> Option<u64> {
+    let mut apu = APU::new();
+    let mut cycle = 0;
+    run_cycles(&mut apu, &mut cycle, write_cycle + 1);
+    apu.write_register(0x4017, value);
+    apu.read_status();
+    while cycle < write_cycle + 2 * FRAME_IRQ_CYCLE {
+        apu.step(cycle, &mut no_memory);
+        if apu.frame_irq() {
+            return Some(cycle);
+        }
+        cycle += 1;
+    }
+    return None;
+}
+
+#[test]
+fn frame_irq_is_set_at_the_end_of_the_4_step_sequence() {
+    let mut apu = APU::new();
+    let mut cycle = 0;
+    run_cycles(&mut apu, &mut cycle, FRAME_IRQ_CYCLE - 1);
+    assert!(!apu.frame_irq());
+    run_cycles(&mut apu, &mut cycle, FRAME_IRQ_CYCLE);
+    assert!(apu.frame_irq());
+}
+
+#[test]
+fn reading_status_clears_the_frame_irq() {
+    let mut apu = APU::new();
+    let mut cycle = 0;
+    run_cycles(&mut apu, &mut cycle, FRAME_IRQ_CYCLE);
+    assert!(apu.frame_irq());
+    assert_eq!(apu.read_status() & FRAME_IRQ_STATUS, FRAME_IRQ_STATUS);
+    assert!(!apu.frame_irq());
+    assert_eq!(apu.read_status() & FRAME_IRQ_STATUS, 0);
+}
+
+#[test]
+fn frame_irq_is_set_again_after_being_cleared_by_the_last_steps() {
+    // The flag is set on 3 cycles in a row, a read during the first doesn't stop the other two.
+    let mut apu = APU::new();
+    let mut cycle = 0;
+    run_cycles(&mut apu, &mut cycle, FRAME_IRQ_CYCLE);
+    apu.read_status();
+    run_cycles(&mut apu, &mut cycle, FRAME_IRQ_CYCLE + 1);
+    assert!(apu.frame_irq());
+}
+
+#[test]
+fn frame_counter_write_restarts_the_sequence_3_or_4_cycles_later() {
+    // A write on an even cycle restarts the sequence 3 cycles later, one on an odd cycle 4.
+    let even = frame_irq_cycle_after_write(1000, 0x00);
+    let odd = frame_irq_cycle_after_write(1001, 0x00);
+    assert_eq!(even, Some(1000 + 3 + FRAME_IRQ_CYCLE));
+    assert_eq!(odd, Some(1001 + 4 + FRAME_IRQ_CYCLE));
+}
+
+#[test]
+fn frame_irq_inhibit_clears_and_blocks_the_flag() {
+    let mut apu = APU::new();
+    let mut cycle = 0;
+    run_cycles(&mut apu, &mut cycle, FRAME_IRQ_CYCLE);
+    assert!(apu.frame_irq());
+    apu.write_register(0x4017, 0x40);
+    assert!(!apu.frame_irq());
+    assert_eq!(frame_irq_cycle_after_write(1000, 0x40), None);
+}
+
+#[test]
+fn five_step_mode_never_sets_the_frame_irq() {
+    assert_eq!(frame_irq_cycle_after_write(1000, 0x80), None);
+}
+
+// Start a DMC sample at $C000 + address * 64 that is length * 16 + 1 bytes long, run it and
+// return the addresses the memory reader fetched.
+fn play_dmc_sample(apu: &mut APU, controls: u8, address: u8, length: u8) -> Vec<u16> {
+    apu.write_register(0x4010, controls);
+    apu.write_register(0x4012, address);
+    apu.write_register(0x4013, length);
+    apu.write_register(0x4015, 0x10);
+    let mut fetched = Vec::new();
+    for cycle in 0..DMC_TEST_CYCLES {
+        apu.step(cycle, &mut |addr| {
+            fetched.push(addr);
+            return 0;
+        });
+    }
+    return fetched;
+}
+
+#[test]
+fn dmc_address_wraps_to_8000() {
+    let mut apu = APU::new();
+    let fetched = play_dmc_sample(&mut apu, DMC_FASTEST_RATE, 0xFF, 0x04);
+    assert_eq!(fetched.len(), 65);
+    assert_eq!(fetched[0], 0xFFC0);
+    assert_eq!(fetched[63], 0xFFFF);
+    assert_eq!(fetched[64], 0x8000);
+    assert_eq!(apu.read_status() & DMC_ACTIVE_STATUS, 0);
+}
+
+#[test]
+fn dmc_sets_its_irq_when_the_sample_ends() {
+    let mut apu = APU::new();
+    play_dmc_sample(&mut apu, DMC_IRQ_ENABLED | DMC_FASTEST_RATE, 0x00, 0x00);
+    assert!(apu.dmc_irq());
+    // Reading the status doesn't acknowledge it, a $4015 write does.
+    assert_eq!(apu.read_status() & DMC_IRQ_STATUS, DMC_IRQ_STATUS);
+    assert!(apu.dmc_irq());
+    apu.write_register(0x4015, 0x00);
+    assert!(!apu.dmc_irq());
+}
+
+#[test]
+fn dmc_loop_restarts_the_sample_without_an_irq() {
+    let mut apu = APU::new();
+    let fetched = play_dmc_sample(&mut apu, DMC_IRQ_ENABLED | DMC_LOOP | DMC_FASTEST_RATE, 0x00, 0x00);
+    assert!(fetched.len() > 1);
+    assert!(fetched.iter().all(|addr| *addr == 0xC000));
+    assert!(!apu.dmc_irq());
+    assert_eq!(apu.read_status() & DMC_ACTIVE_STATUS, DMC_ACTIVE_STATUS);
+}
+
+// The halted cycles each tick ran before its access, with the DMC reader about to fetch.
+fn dmc_stall_during(cycles: &[BusCycle]) -> Vec<u32> {
+    let mut console = nese::Nes::new("test-roms/nestest/nestest.nes".to_string()).unwrap();
+    console.apu().write_register(0x4010, DMC_FASTEST_RATE);
+    console.apu().write_register(0x4015, 0x10);
+    let memory = console.cpu().memory();
+    return cycles.iter().map(|cycle| memory.tick(*cycle)).collect();
+}
+
+#[test]
+fn dmc_fetch_stall_depends_on_what_the_cpu_is_doing() {
+    use nese::cpu::BusCycle::{Halted, Read, Write};
+    // The memory reader fetches on the first cycle. A read waits 4 cycles for it and OAM DMA 2,
+    // both before the access the fetch lands on.
+    assert_eq!(dmc_stall_during(&[Read, Read, Read]), vec![4, 0, 0]);
+    assert_eq!(dmc_stall_during(&[Halted, Halted]), vec![2, 0]);
+    // The CPU can't be halted on a write, so it finishes its writes and waits 3 cycles at the next read.
+    assert_eq!(dmc_stall_during(&[Write, Write, Read, Read]), vec![0, 0, 3, 0]);
+}
+
+// Enough cycles for the slowest high-pass filter to settle, about a quarter of a second.
+const SETTLE_CYCLES: u64 = 450000;
+
+// Run for `cycles` more cycles and return the frames synthesized.
+fn output_after(apu: &mut APU, cycle: &mut u64, cycles: u64) -> Vec<(f32, f32)> {
+    let end = *cycle + cycles;
+    run_cycles(apu, cycle, end);
+    let mut frames = Vec::new();
+    apu.read_frames(&mut frames);
+    return frames;
+}
+
+// An APU with the filters settled on the DMC holding a level set through $4011.
+fn apu_holding_a_level(profile: FilterProfile) -> (APU, u64) {
+    let mut apu = APU::new();
+    let mut cycle = 0;
+    apu.set_filter_profile(profile);
+    apu.write_register(0x4011, 0x40);
+    output_after(&mut apu, &mut cycle, SETTLE_CYCLES);
+    return (apu, cycle);
+}
+
+#[test]
+fn raw_output_keeps_the_dc_level_and_the_consoles_remove_it() {
+    let (mut raw, mut cycle) = apu_holding_a_level(FilterProfile::Raw);
+    let frames = output_after(&mut raw, &mut cycle, SETTLE_CYCLES);
+    let level = frames[0].0;
+    assert!(level > 0.1);
+    assert!(frames.iter().all(|frame| *frame == (level, level)));
+
+    for profile in [FilterProfile::Nes, FilterProfile::Famicom].iter() {
+        let (mut apu, mut cycle) = apu_holding_a_level(*profile);
+        let frames = output_after(&mut apu, &mut cycle, SETTLE_CYCLES);
+        assert!(frames.iter().all(|frame| frame.0.abs() < 1e-4), "{:?} output keeps a DC offset", profile);
+    }
+}
+
+#[test]
+fn changing_the_output_carries_on_from_the_current_level() {
+    for profile in [FilterProfile::Raw, FilterProfile::Nes, FilterProfile::Famicom].iter() {
+        let (mut apu, mut cycle) = apu_holding_a_level(*profile);
+        let settled = output_after(&mut apu, &mut cycle, 10000);
+        let level = settled[settled.len() - 1].0;
+        // Each of these restarts or refilters the output, none of them should make a step.
+        apu.set_sample_rate(48000.0);
+        let after_sample_rate = output_after(&mut apu, &mut cycle, 10000);
+        apu.set_stereo(true);
+        let after_stereo = output_after(&mut apu, &mut cycle, 10000);
+        apu.set_filter_profile(*profile);
+        let after_filter = output_after(&mut apu, &mut cycle, 10000);
+        for frame in after_sample_rate.iter().chain(after_stereo.iter()).chain(after_filter.iter()) {
+            assert!((frame.0 - level).abs() < 1e-4, "{:?} left output jumped from {} to {}", profile, level, frame.0);
+            assert!((frame.1 - level).abs() < 1e-4, "{:?} right output jumped from {} to {}", profile, level, frame.1);
+        }
+    }
+}
+
+// Play the same notes on all five channels for `cycles` cycles, with the mixing set up by `mixing`
+// first, and the DMC holding `dmc_level`.
+fn play_all_channels(mixing: &dyn Fn(&mut APU), dmc_level: u8, cycles: u64) -> Vec<(f32, f32)> {
+    let mut apu = APU::new();
+    let mut cycle = 0;
+    apu.set_filter_profile(FilterProfile::Raw);
+    mixing(&mut apu);
+    apu.write_register(0x4015, 0x0F);
+    // Both pulses at constant volume 15 and different pitches.
+    apu.write_register(0x4000, 0xBF);
+    apu.write_register(0x4002, 0xFD);
+    apu.write_register(0x4003, 0x00);
+    apu.write_register(0x4004, 0x7F);
+    apu.write_register(0x4006, 0x80);
+    apu.write_register(0x4007, 0x01);
+    // The triangle with its counters halted.
+    apu.write_register(0x4008, 0xFF);
+    apu.write_register(0x400A, 0x40);
+    apu.write_register(0x400B, 0x01);
+    // The noise at constant volume 12.
+    apu.write_register(0x400C, 0x3C);
+    apu.write_register(0x400E, 0x04);
+    apu.write_register(0x400F, 0x00);
+    apu.write_register(0x4011, dmc_level);
+    return output_after(&mut apu, &mut cycle, cycles);
+}
+
+fn assert_same_output(actual: &[(f32, f32)], expected: &[(f32, f32)]) {
+    assert_eq!(actual.len(), expected.len());
+    for (i, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+        assert!((a.0 - e.0).abs() < 1e-5 && (a.1 - e.1).abs() < 1e-5, "frame {} is {:?}, expected {:?}", i, a, e);
+    }
+}
+
+const MIXING_TEST_CYCLES: u64 = 40000;
+
+#[test]
+fn muting_a_channel_leaves_the_others_playing() {
+    let all = play_all_channels(&|_| {}, 0x40, MIXING_TEST_CYCLES);
+    let without_dmc = play_all_channels(&|_| {}, 0x00, MIXING_TEST_CYCLES);
+    let dmc_muted = play_all_channels(&|apu| apu.set_channel_muted(Channel::Dmc, true), 0x40, MIXING_TEST_CYCLES);
+    assert!(all.iter().zip(dmc_muted.iter()).any(|(a, m)| (a.0 - m.0).abs() > 1e-3));
+    assert_same_output(&dmc_muted, &without_dmc);
+
+    // Unmuting puts it back.
+    let unmuted = play_all_channels(&|apu| {
+        apu.set_channel_muted(Channel::Dmc, true);
+        apu.set_channel_muted(Channel::Dmc, false);
+    }, 0x40, MIXING_TEST_CYCLES);
+    assert_same_output(&unmuted, &all);
+
+    // A gain of 0 is the same as muting, and doubling a level is the same as doubling the gain.
+    let silent = play_all_channels(&|apu| apu.set_channel_gain(Channel::Dmc, 0.0), 0x40, MIXING_TEST_CYCLES);
+    assert_same_output(&silent, &without_dmc);
+    let doubled = play_all_channels(&|apu| apu.set_channel_gain(Channel::Dmc, 2.0), 0x20, MIXING_TEST_CYCLES);
+    assert_same_output(&doubled, &all);
+}
+
+#[test]
+fn soloing_a_channel_mutes_the_others() {
+    let soloed = play_all_channels(&|apu| apu.set_channel_soloed(Channel::Pulse1, true), 0x40, MIXING_TEST_CYCLES);
+    let others_muted = play_all_channels(&|apu| {
+        for channel in Channel::ALL.iter() {
+            apu.set_channel_muted(*channel, *channel != Channel::Pulse1);
+        }
+    }, 0x40, MIXING_TEST_CYCLES);
+    assert!(soloed.iter().any(|frame| frame.0 > 1e-3));
+    assert_same_output(&soloed, &others_muted);
+
+    // A soloed channel is heard even if it's muted.
+    let soloed_and_muted = play_all_channels(&|apu| {
+        apu.set_channel_muted(Channel::Pulse1, true);
+        apu.set_channel_soloed(Channel::Pulse1, true);
+    }, 0x40, MIXING_TEST_CYCLES);
+    assert_same_output(&soloed_and_muted, &soloed);
+}
+
+#[test]
+fn panning_a_channel_moves_it_to_one_side() {
+    let all = play_all_channels(&|_| {}, 0x40, MIXING_TEST_CYCLES);
+    let dmc_muted = play_all_channels(&|apu| apu.set_channel_muted(Channel::Dmc, true), 0x40, MIXING_TEST_CYCLES);
+
+    // Centred, stereo output is the same on both sides as mono.
+    let centred = play_all_channels(&|apu| apu.set_stereo(true), 0x40, MIXING_TEST_CYCLES);
+    assert_same_output(&centred, &all);
+
+    let panned = play_all_channels(&|apu| {
+        apu.set_stereo(true);
+        apu.set_channel_pan(Channel::Dmc, -1.0);
+    }, 0x40, MIXING_TEST_CYCLES);
+    let left: Vec<(f32, f32)> = panned.iter().map(|frame| (frame.0, frame.0)).collect();
+    let right: Vec<(f32, f32)> = panned.iter().map(|frame| (frame.1, frame.1)).collect();
+    assert_same_output(&left, &all);
+    assert_same_output(&right, &dmc_muted);
+}
@@ -0,0 +1,53 @@
+#![allow(clippy::manual_is_multiple_of, clippy::needless_return)]
+
+// Steps added to a BlipBuffer settle at exactly their height and stay there, so a DC level
+// survives and a signal that returns to 0 returns to exactly 0.
+extern crate nese;
+
+use nese::blip::{BlipBuffer, LEVEL_SCALE};
+
+const CLOCK_RATE: f64 = 1789773.0;
+const SAMPLE_RATE: f64 = 44100.0;
+// About 2 seconds of output, read a frame at a time.
+const FRAME_CLOCKS: u32 = 1000;
+const FRAMES: u32 = 3600;
+
+// Run the buffer with `delta` added at the clocks `step_at` picks, and return every sample.
+fn run(step_at: &dyn Fn(u32, u32) -> Option<i64>) -> Vec<f32> {
+    let mut blip = BlipBuffer::new(CLOCK_RATE, SAMPLE_RATE);
+    let mut samples = Vec::new();
+    for frame in 0..FRAMES {
+        for time in 0..FRAME_CLOCKS {
+            if let Some(delta) = step_at(frame, time) {
+                blip.add_delta(time, delta);
+            }
+        }
+        blip.end_frame(FRAME_CLOCKS);
+        blip.read_samples(&mut samples);
+    }
+    return samples;
+}
+
+#[test]
+fn a_step_holds_its_level() {
+    let level = 12345;
+    let samples = run(&|frame, time| if frame == 0 && time == 0 { Some(level) } else { None });
+    let expected = level as f32 / LEVEL_SCALE;
+    assert!(samples[samples.len() / 2..].iter().all(|sample| *sample == expected));
+}
+
+#[test]
+fn a_square_wave_returns_to_exactly_zero() {
+    // Toggle at an odd period so the steps land on every phase of the kernel, and stop low.
+    let period = 37;
+    let steps = ((FRAMES - 100) * FRAME_CLOCKS / period) & !1;
+    let samples = run(&|frame, time| {
+        let clock = frame * FRAME_CLOCKS + time;
+        if clock % period != 0 || clock / period >= steps {
+            return None;
+        }
+        return Some(if (clock / period) % 2 == 0 { 54321 } else { -54321 });
+    });
+    assert!(samples.iter().any(|sample| *sample != 0.0));
+    assert!(samples[samples.len() - 100..].iter().all(|sample| *sample == 0.0));
+}
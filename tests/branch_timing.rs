@@ -0,0 +1,61 @@
+#![allow(clippy::needless_return)]
+
+extern crate nese;
+
+// The ROMs take a few seconds of emulated time, this is only a safety net in case one never finishes.
+const MAX_FRAMES: u32 = 600;
+// Zero page address the ROMs keep their result code in. 1 means every test passed, 2 and up
+// is the number of the failed test from test-roms/branch_timing_tests/readme.txt.
+const RESULT_ADDRESS: u16 = 0xF8;
+const JMP_ABSOLUTE: u8 = 0x4C;
+
+// Once the result is reported the ROM sits in a `jmp` to itself forever.
+fn is_finished(console: &mut nese::Nes) -> bool {
+    let pc = console.cpu().get_registers().pc;
+    if console.cpu().read(pc) != JMP_ABSOLUTE {
+        return false;
+    }
+    let lo = console.cpu().read(pc.wrapping_add(1)) as u16;
+    let hi = console.cpu().read(pc.wrapping_add(2)) as u16;
+    return (hi << 8) | lo == pc;
+}
+
+// Run a ROM headless until it reports its result, returning the result code.
+fn run_test_rom(file_name: &str) -> u8 {
+    let mut console = nese::Nes::new(file_name.to_string()).unwrap();
+    let mut frames = 0;
+    while !is_finished(&mut console) {
+        let (_, frame_change) = console.step();
+        if frame_change {
+            frames += 1;
+            if frames > MAX_FRAMES {
+                panic!("{} did not finish within {} frames", file_name, MAX_FRAMES);
+            }
+        }
+    }
+    return console.cpu().read(RESULT_ADDRESS);
+}
+
+fn assert_passes(file_name: &str) {
+    let result = run_test_rom(file_name);
+    match result {
+        1 => {},
+        0 => panic!("{} reported an internal error", file_name),
+        code => panic!("{} failed with error code {}", file_name, code),
+    }
+}
+
+#[test]
+fn branch_basics() {
+    assert_passes("test-roms/branch_timing_tests/1.Branch_Basics.nes");
+}
+
+#[test]
+fn backward_branch() {
+    assert_passes("test-roms/branch_timing_tests/2.Backward_Branch.nes");
+}
+
+#[test]
+fn forward_branch() {
+    assert_passes("test-roms/branch_timing_tests/3.Forward_Branch.nes");
+}
@@ -0,0 +1,107 @@
+// A CPU on a flat 64KB of RAM, for tests that drive the CPU directly.
+// Not every test file uses every helper.
+#![allow(dead_code)]
+
+use nese::cpu::{BusCycle, CPU, Memory, Registers};
+
+use std::env;
+use std::fs;
+use std::ops::Range;
+
+pub const PROGRAM_START: u16 = 0x8000;
+pub const NMI_HANDLER: u16 = 0x9000;
+pub const IRQ_HANDLER: u16 = 0xA000;
+
+pub struct TestBus {
+    pub ram: Vec<u8>,
+    // The CPU cycles run so far, every bus access and halted cycle is one.
+    pub cycles: u64,
+    // The cycles the interrupt lines are asserted during, counted from power on.
+    pub nmi_cycles: Range<u64>,
+    pub irq_cycles: Range<u64>,
+    pub writes: Vec<(u16, u8)>,
+}
+
+impl Memory for TestBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        return self.ram[addr as usize];
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.writes.push((addr, data));
+        self.ram[addr as usize] = data;
+    }
+
+    fn tick(&mut self, _cycle: BusCycle) -> u32 {
+        self.cycles += 1;
+        return 0;
+    }
+
+    // The lines are sampled at the end of the cycle that was just ticked.
+    fn nmi_line(&mut self) -> bool {
+        return self.nmi_cycles.contains(&(self.cycles - 1));
+    }
+
+    fn irq_line(&mut self) -> bool {
+        return self.irq_cycles.contains(&(self.cycles - 1));
+    }
+
+    fn peek(&mut self, addr: u16) -> u8 {
+        return self.ram[addr as usize];
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) -> bool {
+        self.ram[addr as usize] = data;
+        return true;
+    }
+}
+
+// A CPU that starts running program at PROGRAM_START. The NMI and IRQ vectors point at
+// NMI_HANDLER and IRQ_HANDLER, which each jump to themselves forever.
+pub fn cpu_with_program(program: &[u8]) -> CPU<TestBus> {
+    let mut ram = vec![0; 0x10000];
+    let start = PROGRAM_START as usize;
+    ram[start..start + program.len()].copy_from_slice(program);
+    for &(vector, handler) in [(0xFFFA, NMI_HANDLER), (0xFFFC, PROGRAM_START), (0xFFFE, IRQ_HANDLER)].iter() {
+        ram[vector] = (handler & 0xFF) as u8;
+        ram[vector + 1] = (handler >> 8) as u8;
+    }
+    for &handler in [NMI_HANDLER, IRQ_HANDLER].iter() {
+        let handler_start = handler as usize;
+        ram[handler_start..handler_start + 3].copy_from_slice(&[0x4C, (handler & 0xFF) as u8, (handler >> 8) as u8]);
+    }
+    return CPU::new(TestBus{
+        ram,
+        cycles: 0,
+        nmi_cycles: 0..0,
+        irq_cycles: 0..0,
+        writes: Vec::new(),
+    });
+}
+
+// Set A, X and Y, leaving the rest as they are.
+pub fn set_axy(cpu: &mut CPU<TestBus>, a: u8, x: u8, y: u8) {
+    let registers = cpu.get_registers();
+    cpu.set_registers(Registers{
+        a,
+        x,
+        y,
+        ..registers
+    });
+}
+
+// Write a ROM image to a file of its own in the temp directory, for tests that need a cartridge
+// none of the test ROMs have. Returns the path to hand to Nes::new.
+pub fn write_rom(name: &str, data: &[u8]) -> String {
+    let path = env::temp_dir().join(format!("nese-{}-{}.nes", name, std::process::id()));
+    fs::write(&path, data).unwrap();
+    return path.to_str().unwrap().to_string();
+}
+
+// An iNES 1.0 image for a mapper, with PRG and CHR banks of zeros.
+pub fn ines_rom(mapper: u8, prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+    let mut data = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks, mapper << 4, mapper & 0xF0];
+    data.resize(16, 0);
+    data.resize(16 + prg_banks as usize * 0x4000 + chr_banks as usize * 0x2000, 0);
+    return data;
+}
@@ -0,0 +1,67 @@
+#![allow(clippy::needless_return)]
+
+// What the CPU sees on its bus where nothing drives every bit, and what the debugger can poke.
+// https://wiki.nesdev.com/w/index.php/Open_bus_behavior
+extern crate nese;
+
+mod common;
+
+use nese::cpu::Memory;
+
+const GAME: &str = "test-roms/nestest/nestest.nes";
+
+// Leave `value` on the data bus with a write to RAM.
+fn drive_bus(console: &mut nese::Nes, value: u8) {
+    console.cpu().memory().write(0x0000, value);
+}
+
+fn read(console: &mut nese::Nes, addr: u16) -> u8 {
+    return console.cpu().memory().read(addr);
+}
+
+#[test]
+fn controller_reads_keep_the_upper_bits_of_the_bus() {
+    let mut console = nese::Nes::new(GAME.to_string()).unwrap();
+    drive_bus(&mut console, 0xFF);
+    assert_eq!(read(&mut console, 0x4016), 0xE0);
+    assert_eq!(read(&mut console, 0x4017), 0xE0);
+    drive_bus(&mut console, 0x1F);
+    assert_eq!(read(&mut console, 0x4016), 0x00);
+    assert_eq!(read(&mut console, 0x4017), 0x00);
+}
+
+#[test]
+fn status_read_keeps_bit_5_of_the_bus_and_leaves_it_alone() {
+    let mut console = nese::Nes::new(GAME.to_string()).unwrap();
+    drive_bus(&mut console, 0xFF);
+    assert_eq!(read(&mut console, 0x4015), 0x20);
+    // $4015 is read inside the CPU, so the bus still holds what was there before.
+    assert_eq!(read(&mut console, 0x4016), 0xE0);
+    drive_bus(&mut console, 0xDF);
+    assert_eq!(read(&mut console, 0x4015), 0x00);
+}
+
+#[test]
+fn unmapped_reads_see_the_last_value_on_the_bus() {
+    let mut console = nese::Nes::new(GAME.to_string()).unwrap();
+    drive_bus(&mut console, 0xA5);
+    assert_eq!(read(&mut console, 0x5000), 0xA5);
+    drive_bus(&mut console, 0x3C);
+    assert_eq!(read(&mut console, 0x4018), 0x3C);
+}
+
+#[test]
+fn poke_only_writes_prg_ram_a_cartridge_has() {
+    // iNES 1.0 always has 8KB of PRG RAM.
+    let mut console = nese::Nes::new(GAME.to_string()).unwrap();
+    assert!(console.poke(0x6000, 0x42));
+    assert_eq!(console.peek(0x6000), 0x42);
+
+    // A NES 2.0 header can say there is none.
+    let mut rom = common::ines_rom(0, 1, 1);
+    rom[7] = 0x08;
+    let mut console = nese::Nes::new(common::write_rom("no-prg-ram", &rom)).unwrap();
+    assert!(!console.poke(0x6000, 0x42));
+    assert!(!console.poke(0x7FFF, 0x42));
+    assert!(console.poke(0x0000, 0x42));
+}
@@ -0,0 +1,106 @@
+#![allow(clippy::needless_return)]
+
+// Stepping, breakpoints and watchpoints on a small program in an NROM cartridge.
+extern crate nese;
+
+mod common;
+
+use nese::cpu::AccessKind;
+use nese::debugger::{Breakpoint, Debugger, StopReason};
+
+const PROGRAM: [u8; 16] = [
+    0xA9, 0x01,       // 8000 LDA #$01
+    0x8D, 0x00, 0x08, // 8002 STA $0800, a mirror of $0000
+    0x20, 0x0E, 0x80, // 8005 JSR $800E
+    0xAD, 0xFA, 0x3F, // 8008 LDA $3FFA, a mirror of $2002
+    0x4C, 0x0B, 0x80, // 800B JMP $800B
+    0xE8,             // 800E INX
+    0x60,             // 800F RTS
+];
+
+fn debugger(name: &str) -> Debugger {
+    let mut rom = common::ines_rom(0, 1, 1);
+    rom[16..16 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+    // The reset vector, the 16KB bank is mirrored at $C000.
+    rom[16 + 0x3FFC] = 0x00;
+    rom[16 + 0x3FFD] = 0x80;
+    let console = nese::Nes::new(common::write_rom(name, &rom)).unwrap();
+    return Debugger::new(console);
+}
+
+fn pc(debugger: &mut Debugger) -> u16 {
+    return debugger.console().cpu().get_registers().pc;
+}
+
+#[test]
+fn step_into_runs_one_instruction_at_a_time() {
+    let mut debugger = debugger("debugger-step-into");
+    assert_eq!(debugger.step_into(), StopReason::Done);
+    assert_eq!(pc(&mut debugger), 0x8002);
+    debugger.step_into();
+    debugger.step_into();
+    assert_eq!(pc(&mut debugger), 0x800E);
+}
+
+#[test]
+fn step_over_runs_a_subroutine_and_step_out_returns_from_one() {
+    let mut debugger = debugger("debugger-step-over");
+    debugger.step_into();
+    debugger.step_into();
+    assert_eq!(debugger.step_over(), StopReason::Done);
+    assert_eq!(pc(&mut debugger), 0x8008);
+    assert_eq!(debugger.console().cpu().get_registers().x, 1);
+
+    let mut debugger = self::debugger("debugger-step-out");
+    debugger.step_into();
+    debugger.step_into();
+    debugger.step_into();
+    assert_eq!(pc(&mut debugger), 0x800E);
+    assert_eq!(debugger.step_out(), StopReason::Done);
+    assert_eq!(pc(&mut debugger), 0x8008);
+}
+
+#[test]
+fn execute_and_opcode_breakpoints_stop_before_the_instruction() {
+    let mut debugger = debugger("debugger-execute");
+    assert_eq!(debugger.add_breakpoint(Breakpoint::Execute(0x8008)), 0);
+    assert_eq!(debugger.resume(), StopReason::Breakpoint(0));
+    assert_eq!(pc(&mut debugger), 0x8008);
+
+    let mut debugger = self::debugger("debugger-opcode");
+    debugger.add_breakpoint(Breakpoint::Opcode(0x60));
+    assert_eq!(debugger.resume(), StopReason::Breakpoint(0));
+    assert_eq!(pc(&mut debugger), 0x800F);
+    // Removing it lets execution carry on to the next breakpoint.
+    assert_eq!(debugger.remove_breakpoint(0), Some(Breakpoint::Opcode(0x60)));
+    debugger.add_breakpoint(Breakpoint::Execute(0x800B));
+    assert_eq!(debugger.resume(), StopReason::Breakpoint(0));
+}
+
+#[test]
+fn watchpoints_catch_accesses_through_mirrors() {
+    let mut debugger = debugger("debugger-watch-write");
+    debugger.add_breakpoint(Breakpoint::CpuWrite(0x1000));
+    // Stepping stops on them too, so a missed access doesn't run on forever.
+    assert_eq!(debugger.step_into(), StopReason::Done);
+    match debugger.step_into() {
+        StopReason::Watchpoint(0, access) => {
+            assert_eq!(access.kind, AccessKind::Write);
+            assert_eq!(access.address, 0x0800);
+            assert_eq!(access.value, 0x01);
+        },
+        reason => panic!("expected the write watchpoint, got {:?}", reason),
+    }
+    // Watchpoints stop after the instruction that made the access.
+    assert_eq!(pc(&mut debugger), 0x8005);
+
+    let mut debugger = self::debugger("debugger-watch-read");
+    debugger.add_breakpoint(Breakpoint::CpuRead(0x2002));
+    debugger.add_breakpoint(Breakpoint::Execute(0x8008));
+    assert_eq!(debugger.resume(), StopReason::Breakpoint(1));
+    match debugger.step_into() {
+        StopReason::Watchpoint(0, access) => assert_eq!(access.address, 0x3FFA),
+        reason => panic!("expected the read watchpoint, got {:?}", reason),
+    }
+    assert_eq!(pc(&mut debugger), 0x800B);
+}
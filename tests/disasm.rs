@@ -0,0 +1,91 @@
+#![allow(clippy::needless_return)]
+
+// Decoding, operand syntax for each addressing mode, labels and listings.
+extern crate nese;
+
+use nese::cpu::AddressingMode;
+use nese::disasm::{disassemble, disassemble_bank, format_listing, Instruction, Labels};
+
+fn text(address: u16, bytes: &[u8], labels: &Labels) -> String {
+    return Instruction::decode(address, bytes).text(labels);
+}
+
+#[test]
+fn decode_reads_the_opcodes_size_and_pads_missing_operands() {
+    let instruction = Instruction::decode(0xC000, &[0x4C, 0xF5, 0xC5, 0xEA]);
+    assert_eq!(instruction, Instruction{address: 0xC000, bytes: [0x4C, 0xF5, 0xC5], size: 3});
+    assert_eq!(instruction.name(), "JMP");
+    assert_eq!(instruction.addressing_mode(), AddressingMode::Absolute);
+    assert_eq!(instruction.hex_bytes(), "4C F5 C5");
+    assert_eq!(instruction.to_string(), "C000  4C F5 C5  JMP $C5F5");
+
+    // The operand ran off the end of the data.
+    let instruction = Instruction::decode(0xFFFF, &[0xAD]);
+    assert_eq!(instruction.bytes, [0xAD, 0x00, 0x00]);
+    assert_eq!(instruction.size, 3);
+}
+
+#[test]
+fn operands_use_the_addressing_mode_syntax() {
+    let labels = Labels::new();
+    assert_eq!(text(0x8000, &[0x60], &labels), "RTS");
+    assert_eq!(text(0x8000, &[0x0A], &labels), "ASL A");
+    assert_eq!(text(0x8000, &[0xA9, 0x0F], &labels), "LDA #$0F");
+    assert_eq!(text(0x8000, &[0xA5, 0x10], &labels), "LDA $10");
+    assert_eq!(text(0x8000, &[0xB5, 0x10], &labels), "LDA $10,X");
+    assert_eq!(text(0x8000, &[0xB6, 0x10], &labels), "LDX $10,Y");
+    assert_eq!(text(0x8000, &[0xAD, 0x34, 0x12], &labels), "LDA $1234");
+    assert_eq!(text(0x8000, &[0xBD, 0x34, 0x12], &labels), "LDA $1234,X");
+    assert_eq!(text(0x8000, &[0xB9, 0x34, 0x12], &labels), "LDA $1234,Y");
+    assert_eq!(text(0x8000, &[0xA1, 0x20], &labels), "LDA ($20,X)");
+    assert_eq!(text(0x8000, &[0xB1, 0x20], &labels), "LDA ($20),Y");
+    assert_eq!(text(0x8000, &[0x6C, 0xFC, 0xFF], &labels), "JMP ($FFFC)");
+    // Branches show where they go, counted from the next instruction.
+    assert_eq!(text(0x8005, &[0xD0, 0xFC], &labels), "BNE $8003");
+    assert_eq!(text(0x80FE, &[0x10, 0x7F], &labels), "BPL $817F");
+}
+
+#[test]
+fn labels_replace_the_addresses_they_name() {
+    let mut labels = Labels::hardware();
+    labels.add(0x8003, "loop");
+    assert_eq!(text(0x8000, &[0x8D, 0x00, 0x20], &labels), "STA PPUCTRL");
+    assert_eq!(text(0x8000, &[0xBD, 0x17, 0x40], &labels), "LDA JOY2,X");
+    assert_eq!(text(0x8000, &[0x6C, 0xFC, 0xFF], &labels), "JMP (RESET_VECTOR)");
+    assert_eq!(text(0x8005, &[0xD0, 0xFC], &labels), "BNE loop");
+    // Immediate values and addresses without a label are left alone.
+    assert_eq!(text(0x8000, &[0xA9, 0x03], &labels), "LDA #$03");
+    assert_eq!(text(0x8000, &[0x8D, 0x08, 0x20], &labels), "STA $2008");
+}
+
+#[test]
+fn vector_targets_are_labelled_once() {
+    // NMI and IRQ share a handler, it keeps the first name.
+    let mut memory = vec![0; 0x10000];
+    memory[0xFFFA..0x10000].copy_from_slice(&[0x00, 0x90, 0x00, 0x80, 0x00, 0x90]);
+    let mut labels = Labels::new();
+    labels.add_vector_targets(&mut |addr| memory[addr as usize]);
+    assert_eq!(labels.get(0x8000), Some("RESET"));
+    assert_eq!(labels.get(0x9000), Some("NMI"));
+    assert_eq!(labels.get(0xA000), None);
+}
+
+#[test]
+fn listings_have_a_line_per_instruction_and_one_per_label() {
+    let program = [0xA2, 0x00, 0xE8, 0xD0, 0xFD, 0x60];
+    let mut labels = Labels::new();
+    labels.add(0x8002, "loop");
+
+    let instructions = disassemble_bank(&program, 0x8000);
+    assert_eq!(format_listing(&instructions, &labels), concat!(
+        "8000  A2 00     LDX #$00\n",
+        "loop:\n",
+        "8002  E8        INX\n",
+        "8003  D0 FD     BNE loop\n",
+        "8005  60        RTS\n",
+    ));
+
+    // Through a bus the same bytes decode the same, stopping after the instruction at `end`.
+    let mut read = |addr: u16| program.get(addr.wrapping_sub(0x8000) as usize).cloned().unwrap_or(0xEA);
+    assert_eq!(disassemble(&mut read, 0x8000, 0x8003), instructions[..3].to_vec());
+}
@@ -0,0 +1,146 @@
+#![allow(clippy::needless_return)]
+
+// ROM headers parsed from memory, and the errors for files that can't be played.
+// https://wiki.nesdev.com/w/index.php/INES
+extern crate nese;
+
+mod common;
+
+use nese::ines::{parse_ines, ConsoleType, RomError, Timing};
+use nese::mapper::create_mapper;
+use nese::ppu::{NametableMirrorType, NametableMirroring};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn bad_magic_number_is_rejected() {
+    let mut rom = common::ines_rom(0, 1, 1);
+    rom[3] = 0x00;
+    match parse_ines(&rom) {
+        Err(RomError::BadMagic) => {},
+        result => panic!("expected a bad magic error, got {:?}", result.err()),
+    }
+}
+
+#[test]
+fn short_header_is_an_io_error() {
+    match parse_ines(&common::ines_rom(0, 1, 1)[..10]) {
+        Err(RomError::Io(_)) => {},
+        result => panic!("expected an I/O error, got {:?}", result.err()),
+    }
+}
+
+#[test]
+fn truncated_prg_and_chr_rom_are_rejected() {
+    let rom = common::ines_rom(0, 2, 1);
+    let prg_end = 16 + 2 * 0x4000;
+    match parse_ines(&rom[..prg_end - 1]) {
+        Err(RomError::TruncatedPrgRom{expected, actual}) => assert_eq!((expected, actual), (0x8000, 0x7FFF)),
+        result => panic!("expected a truncated PRG ROM error, got {:?}", result.err()),
+    }
+    match parse_ines(&rom[..prg_end + 0x1000]) {
+        Err(RomError::TruncatedChrRom{expected, actual}) => assert_eq!((expected, actual), (0x2000, 0x1000)),
+        result => panic!("expected a truncated CHR ROM error, got {:?}", result.err()),
+    }
+    assert!(parse_ines(&rom).is_ok());
+}
+
+#[test]
+fn unsupported_mapper_is_an_error() {
+    match parse_ines(&common::ines_rom(5, 1, 1)) {
+        Err(RomError::UnsupportedMapper(mapper)) => assert_eq!(mapper, 5),
+        result => panic!("expected an unsupported mapper error, got {:?}", result.err()),
+    }
+
+    // create_mapper checks too, for data that didn't come from parse_ines.
+    let mut ines_data = parse_ines(&common::ines_rom(0, 1, 1)).unwrap();
+    ines_data.mapper = 5;
+    let mirroring = Rc::new(RefCell::new(Box::new(NametableMirroring{
+        nametable_mirror_type: NametableMirrorType::Vertical,
+    })));
+    match create_mapper(ines_data, mirroring) {
+        Err(RomError::UnsupportedMapper(mapper)) => assert_eq!(mapper, 5),
+        result => panic!("expected an unsupported mapper error, got {:?}", result.err()),
+    }
+}
+
+// A NES 2.0 image with bytes 6-15 of the header as given, followed by `data_len` bytes of ROM.
+fn nes2_rom(header_tail: [u8; 10], data_len: usize) -> Vec<u8> {
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x00, 0x00];
+    rom.extend_from_slice(&header_tail);
+    rom[7] |= 0x08;
+    rom.resize(16 + data_len, 0);
+    return rom;
+}
+
+#[test]
+fn nes2_mapper_and_submapper() {
+    // Mapper 4 submapper 1, the MMC6.
+    let rom = nes2_rom([0x40, 0x00, 0x10, 0, 0, 0, 0, 0, 0, 0], 0);
+    let ines_data = parse_ines(&rom).unwrap();
+    assert!(ines_data.nes2);
+    assert_eq!((ines_data.mapper, ines_data.submapper), (4, 1));
+
+    // Byte 8 holds bits 8-11 of the mapper number.
+    let rom = nes2_rom([0x30, 0x20, 0x01, 0, 0, 0, 0, 0, 0, 0], 0);
+    match parse_ines(&rom) {
+        Err(RomError::UnsupportedMapper(mapper)) => assert_eq!(mapper, 0x123),
+        result => panic!("expected an unsupported mapper error, got {:?}", result.err()),
+    }
+}
+
+#[test]
+fn nes2_rom_sizes() {
+    // The MSB nibbles in byte 9 extend the unit counts in bytes 4 and 5.
+    let mut rom = nes2_rom([0, 0, 0, 0x01, 0, 0, 0, 0, 0, 0], 0x100 * 0x4000 + 0x2000);
+    rom[5] = 0x01;
+    let ines_data = parse_ines(&rom).unwrap();
+    assert_eq!(ines_data.prg.len(), 0x100 * 0x4000);
+    assert_eq!(ines_data.chr.len(), 0x2000);
+
+    // An MSB nibble of $F makes the LSB 2^E * (MM*2+1) bytes, here 2^14 * 3 of PRG.
+    let mut rom = nes2_rom([0, 0, 0, 0x0F, 0, 0, 0, 0, 0, 0], 3 * 0x4000);
+    rom[4] = (14 << 2) | 1;
+    let ines_data = parse_ines(&rom).unwrap();
+    assert_eq!(ines_data.prg.len(), 3 * 0x4000);
+    assert!(ines_data.chr.is_empty());
+}
+
+#[test]
+fn nes2_ram_sizes_are_64_shifted_left() {
+    let rom = nes2_rom([0x02, 0, 0, 0, 0x97, 0x07, 0, 0, 0, 0], 0);
+    let ines_data = parse_ines(&rom).unwrap();
+    assert!(ines_data.battery);
+    assert_eq!(ines_data.prg_ram_size, 64 << 7);
+    assert_eq!(ines_data.prg_nvram_size, 64 << 9);
+    assert_eq!(ines_data.chr_ram_size, 64 << 7);
+    // A shift of 0 means none.
+    assert_eq!(ines_data.chr_nvram_size, 0);
+}
+
+#[test]
+fn nes2_timing_console_type_and_expansion_device() {
+    let rom = nes2_rom([0, 0x03, 0, 0, 0, 0, 0x03, 0x0B, 0, 0x2A], 0);
+    let ines_data = parse_ines(&rom).unwrap();
+    assert_eq!(ines_data.timing, Timing::Dendy);
+    assert_eq!(ines_data.console_type, ConsoleType::Extended(0x0B));
+    assert_eq!(ines_data.default_expansion_device, 0x2A);
+
+    let rom = nes2_rom([0, 0x01, 0, 0, 0, 0, 0x02, 0, 0, 0], 0);
+    let ines_data = parse_ines(&rom).unwrap();
+    assert_eq!(ines_data.timing, Timing::MultipleRegion);
+    assert_eq!(ines_data.console_type, ConsoleType::VsSystem);
+}
+
+#[test]
+fn archaic_ines_ignores_garbage_after_byte_7() {
+    // "DiskDude!" over bytes 7-15 would otherwise make mapper 0x44 out of mapper 4.
+    let mut rom = common::ines_rom(4, 1, 1);
+    rom[7..16].copy_from_slice(b"DiskDude!");
+    let ines_data = parse_ines(&rom).unwrap();
+    assert!(!ines_data.nes2);
+    assert_eq!(ines_data.mapper, 4);
+    assert_eq!(ines_data.timing, Timing::Ntsc);
+    assert_eq!(ines_data.console_type, ConsoleType::Nes);
+}
@@ -0,0 +1,112 @@
+#![allow(clippy::needless_return)]
+
+// NMI edge detection, the IRQ line and interrupt hijacking, cycle by cycle against a bus
+// whose interrupt lines are asserted during set ranges of cycles.
+extern crate nese;
+
+mod common;
+
+use common::{cpu_with_program, TestBus, IRQ_HANDLER, NMI_HANDLER, PROGRAM_START};
+use nese::cpu::{CPU, Interrupt};
+
+const BREAK_FLAG: u8 = 0x10;
+const NOP: u8 = 0xEA;
+const CLI: u8 = 0x58;
+const BRK: u8 = 0x00;
+
+// Step until the bus has run `cycles` cycles, returning the interrupts taken on the way.
+fn run_until(cpu: &mut CPU<TestBus>, cycles: u64) -> Vec<Interrupt> {
+    let mut interrupts = Vec::new();
+    while cpu.memory().cycles < cycles {
+        cpu.step();
+        if let Some(interrupt) = cpu.last_interrupt() {
+            interrupts.push(interrupt);
+        }
+    }
+    return interrupts;
+}
+
+// The flags and return address an interrupt pushed, with the stack pointer where it left it.
+fn pushed_by_interrupt(cpu: &mut CPU<TestBus>) -> (u8, u16) {
+    let sp = cpu.get_registers().sp as usize;
+    let ram = &cpu.memory().ram;
+    let flags = ram[0x0100 + sp + 1];
+    let return_address = ((ram[0x0100 + sp + 3] as u16) << 8) | ram[0x0100 + sp + 2] as u16;
+    return (flags, return_address);
+}
+
+#[test]
+fn nmi_is_taken_once_per_rising_edge() {
+    let mut cpu = cpu_with_program(&[NOP; 0x40]);
+    // Held high for a long time, then pulsed for a single cycle.
+    cpu.memory().nmi_cycles = 10..200;
+    assert_eq!(run_until(&mut cpu, 250), vec![Interrupt::Nmi]);
+
+    cpu.memory().nmi_cycles = 260..261;
+    assert_eq!(run_until(&mut cpu, 300), vec![Interrupt::Nmi]);
+}
+
+#[test]
+fn irq_waits_for_the_instruction_after_cli() {
+    // I is set at power on, the IRQ line is asserted the whole time.
+    let mut cpu = cpu_with_program(&[NOP, CLI, NOP, NOP, NOP]);
+    cpu.memory().irq_cycles = 0..1000;
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.last_interrupt(), None);
+    // CLI's change to I is only seen when the lines are polled during the next instruction.
+    cpu.step();
+    assert_eq!(cpu.last_interrupt(), None);
+    cpu.step();
+    assert_eq!(cpu.last_interrupt(), Some(Interrupt::Irq));
+    assert_eq!(cpu.get_registers().pc, IRQ_HANDLER);
+    let (_, return_address) = pushed_by_interrupt(&mut cpu);
+    assert_eq!(return_address, PROGRAM_START + 3);
+}
+
+#[test]
+fn nmi_during_irq_push_hijacks_the_vector() {
+    // CLI at cycles 0-1, NOP at 2-3, then the IRQ sequence pushes the PC at 6-7 and picks the
+    // vector after it. An NMI edge by the end of cycle 7 sends it to the NMI handler.
+    let mut cpu = cpu_with_program(&[CLI, NOP, NOP, NOP]);
+    cpu.memory().irq_cycles = 0..1000;
+    cpu.memory().nmi_cycles = 7..1000;
+    cpu.step();
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.last_interrupt(), Some(Interrupt::Nmi));
+    assert_eq!(cpu.get_registers().pc, NMI_HANDLER);
+    let (flags, return_address) = pushed_by_interrupt(&mut cpu);
+    assert_eq!(flags & BREAK_FLAG, 0);
+    assert_eq!(return_address, PROGRAM_START + 2);
+}
+
+#[test]
+fn nmi_after_the_vector_is_picked_runs_after_the_irq() {
+    let mut cpu = cpu_with_program(&[CLI, NOP, NOP, NOP]);
+    cpu.memory().irq_cycles = 0..1000;
+    cpu.memory().nmi_cycles = 8..1000;
+    cpu.step();
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.last_interrupt(), Some(Interrupt::Irq));
+    assert_eq!(cpu.get_registers().pc, IRQ_HANDLER);
+    // The first instruction of the handler runs before the NMI is taken.
+    cpu.step();
+    assert_eq!(cpu.last_interrupt(), None);
+    cpu.step();
+    assert_eq!(cpu.last_interrupt(), Some(Interrupt::Nmi));
+}
+
+#[test]
+fn nmi_during_brk_hijacks_the_vector() {
+    // BRK reads its opcode and padding byte at cycles 0-1 and pushes the PC at 2-3.
+    let mut cpu = cpu_with_program(&[BRK, NOP, NOP]);
+    cpu.memory().nmi_cycles = 3..1000;
+    cpu.step();
+    assert_eq!(cpu.get_registers().pc, NMI_HANDLER);
+    // The pushed flags still show it was a BRK.
+    let (flags, return_address) = pushed_by_interrupt(&mut cpu);
+    assert_eq!(flags & BREAK_FLAG, BREAK_FLAG);
+    assert_eq!(return_address, PROGRAM_START + 2);
+}
@@ -0,0 +1,103 @@
+#![allow(clippy::needless_return)]
+
+extern crate nese;
+
+use std::fs;
+
+const NESTEST_ROM: &str = "test-roms/nestest/nestest.nes";
+// The reference trace, from http://www.qmtpro.com/~nes/misc/nestest.log. It isn't in the
+// repository, so the test that diffs against it only runs with `cargo test -- --ignored`.
+const NESTEST_LOG: &str = "test-roms/nestest/nestest.log";
+
+// Automation mode starts at 0xC000 and finishes with the RTS at 0xC66E.
+const AUTOMATION_START: u16 = 0xC000;
+const AUTOMATION_END: u16 = 0xC66E;
+// The reference log has 8991 lines, this is only a safety net in case the CPU runs away.
+const MAX_INSTRUCTIONS: usize = 10000;
+// The reference log starts counting after the 7 cycles the CPU spends on reset.
+const RESET_CYCLES: u64 = 7;
+const DOTS_PER_SCANLINE: u32 = 341;
+const SCANLINES_PER_FRAME: u32 = 262;
+
+// One line of the trace in the nestest.log format, without the disassembly column.
+// e.g. "C000  4C F5 C5  A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+fn trace_line(console: &mut nese::Nes, cycles: u64, start_dot: u32) -> String {
+    let registers = console.cpu().get_registers();
+    let opcode = console.cpu().read(registers.pc);
+    let size = nese::cpu::instruction_size(opcode);
+    let mut bytes = Vec::new();
+    for i in 0..size {
+        let byte = console.cpu().read(registers.pc.wrapping_add(i));
+        bytes.push(format!("{:02X}", byte));
+    }
+
+    // The reference log's PPU position is counted from power on, so measure it from where the run started.
+    let (scanline, dot) = console.ppu_position();
+    let frame_dots = DOTS_PER_SCANLINE * SCANLINES_PER_FRAME;
+    let dots = (scanline as u32 * DOTS_PER_SCANLINE + dot as u32 + frame_dots - start_dot) % frame_dots + RESET_CYCLES as u32 * 3;
+
+    return format!("{:04X}  {:<8}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        registers.pc, bytes.join(" "), registers.a, registers.x, registers.y, registers.p, registers.sp,
+        dots / DOTS_PER_SCANLINE, dots % DOTS_PER_SCANLINE, cycles);
+}
+
+// Drop the disassembly column from a reference log line so it lines up with trace_line.
+fn strip_disassembly(line: &str) -> String {
+    match line.find("A:") {
+        Some(registers) => format!("{}{}", &line[..16], &line[registers..]),
+        None => line.to_string(),
+    }
+}
+
+fn run_automation_mode() -> (nese::Nes, Vec<String>) {
+    let mut console = nese::Nes::new(NESTEST_ROM.to_string()).unwrap();
+    console.cpu().set_pc(AUTOMATION_START);
+    let (scanline, dot) = console.ppu_position();
+    let start_dot = scanline as u32 * DOTS_PER_SCANLINE + dot as u32;
+
+    let mut trace = Vec::new();
+    let mut cycles = RESET_CYCLES;
+    loop {
+        trace.push(trace_line(&mut console, cycles, start_dot));
+        if console.cpu().get_registers().pc == AUTOMATION_END {
+            break;
+        }
+        if trace.len() >= MAX_INSTRUCTIONS {
+            panic!("nestest did not reach {:#06X} within {} instructions", AUTOMATION_END, MAX_INSTRUCTIONS);
+        }
+        let (step_cycles, _) = console.step();
+        cycles += step_cycles;
+    }
+    return (console, trace);
+}
+
+#[test]
+#[ignore = "needs test-roms/nestest/nestest.log, see test-roms/README.md"]
+fn nestest_matches_reference_log() {
+    let (_, trace) = run_automation_mode();
+
+    let log = match fs::read_to_string(NESTEST_LOG) {
+        Ok(log) => log,
+        Err(e) => panic!("failed to read the reference trace {}: {}", NESTEST_LOG, e),
+    };
+    let expected: Vec<String> = log.lines().map(strip_disassembly).collect();
+    for (i, (actual, expected)) in trace.iter().zip(expected.iter()).enumerate() {
+        if actual != expected {
+            let previous = if i > 0 { trace[i - 1].as_str() } else { "" };
+            panic!("trace diverges from {} at line {}\nprevious: {}\nexpected: {}\nactual:   {}",
+                NESTEST_LOG, i + 1, previous, expected, actual);
+        }
+    }
+    assert_eq!(trace.len(), expected.len(), "trace length differs from {}", NESTEST_LOG);
+}
+
+#[test]
+fn nestest_result_codes() {
+    let (mut console, _) = run_automation_mode();
+
+    // nestest leaves the error code of the last failed official and unofficial opcode test in 0x10 and 0x11.
+    let official = console.cpu().read(0x10);
+    let unofficial = console.cpu().read(0x11);
+    assert_eq!(official, 0, "official opcode tests failed with error code {:#04X}", official);
+    assert_eq!(unofficial, 0, "unofficial opcode tests failed with error code {:#04X}", unofficial);
+}
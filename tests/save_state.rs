@@ -0,0 +1,104 @@
+#![allow(clippy::needless_return)]
+
+extern crate nese;
+
+mod common;
+
+use nese::state::StateError;
+
+// A ROM that keeps the PPU and CPU busy, the test only needs it to run deterministically.
+const GAME: &str = "test-roms/branch_timing_tests/1.Branch_Basics.nes";
+// The offset of the version byte, after the 4 byte magic number.
+const VERSION_OFFSET: usize = 4;
+
+fn run_frames(console: &mut nese::Nes, frames: u32) {
+    let mut frame = 0;
+    while frame < frames {
+        let (_, frame_change) = console.step();
+        if frame_change {
+            frame += 1;
+        }
+    }
+}
+
+// The frame buffer and registers after running some frames, and the state they finished in.
+fn run_and_snapshot(console: &mut nese::Nes, frames: u32) -> (Vec<u8>, nese::cpu::Registers, Vec<u8>) {
+    run_frames(console, frames);
+    let frame_buffer = console.get_frame_buffer();
+    let registers = console.cpu().get_registers();
+    return (frame_buffer, registers, console.save_state());
+}
+
+#[test]
+fn state_round_trips_into_a_fresh_console() {
+    let mut console = nese::Nes::new(GAME.to_string()).unwrap();
+    run_frames(&mut console, 10);
+    let state = console.save_state();
+    let (frame_buffer, registers, final_state) = run_and_snapshot(&mut console, 20);
+
+    // Anything the state leaves out keeps its power on value in the fresh console and sends it
+    // down a different path.
+    let mut restored = nese::Nes::new(GAME.to_string()).unwrap();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.save_state(), state);
+    let (restored_frame_buffer, restored_registers, restored_final_state) = run_and_snapshot(&mut restored, 20);
+    assert!(restored_frame_buffer == frame_buffer, "frame buffers differ after loading the state");
+    assert_eq!(restored_registers, registers);
+    assert_eq!(restored_final_state, final_state);
+}
+
+#[test]
+fn state_from_another_version_is_rejected() {
+    let mut console = nese::Nes::new(GAME.to_string()).unwrap();
+    let mut state = console.save_state();
+    let version = state[VERSION_OFFSET].wrapping_add(1);
+    state[VERSION_OFFSET] = version;
+    match console.load_state(&state) {
+        Err(StateError::UnsupportedVersion(v)) => assert_eq!(v, version),
+        result => panic!("expected an unsupported version error, got {:?}", result),
+    }
+}
+
+#[test]
+fn state_with_bad_magic_or_truncated_is_rejected() {
+    let mut console = nese::Nes::new(GAME.to_string()).unwrap();
+    let state = console.save_state();
+
+    let mut bad_magic = state.clone();
+    bad_magic[0] ^= 0xFF;
+    match console.load_state(&bad_magic) {
+        Err(StateError::BadMagic) => {},
+        result => panic!("expected a bad magic error, got {:?}", result),
+    }
+
+    match console.load_state(&state[..state.len() - 1]) {
+        Err(StateError::UnexpectedEnd) => {},
+        result => panic!("expected an unexpected end error, got {:?}", result),
+    }
+}
+
+// The bank a state selects is checked against the cartridge, a UxROM and a CNROM game
+// keep theirs in the last byte of the state.
+fn load_with_last_byte(name: &str, rom: &[u8], value: u8) -> Result<(), StateError> {
+    let mut console = nese::Nes::new(common::write_rom(name, rom)).unwrap();
+    let mut state = console.save_state();
+    *state.last_mut().unwrap() = value;
+    return console.load_state(&state);
+}
+
+#[test]
+fn state_selecting_a_missing_bank_is_rejected() {
+    // 4 PRG banks for UxROM and 2 CHR banks for CNROM.
+    let uxrom = common::ines_rom(2, 4, 0);
+    let cnrom = common::ines_rom(3, 2, 2);
+    assert!(load_with_last_byte("uxrom", &uxrom, 3).is_ok());
+    assert!(load_with_last_byte("cnrom", &cnrom, 1).is_ok());
+    match load_with_last_byte("uxrom", &uxrom, 4) {
+        Err(StateError::Mismatch(_)) => {},
+        result => panic!("expected a mismatch error, got {:?}", result),
+    }
+    match load_with_last_byte("cnrom", &cnrom, 0xFF) {
+        Err(StateError::Mismatch(_)) => {},
+        result => panic!("expected a mismatch error, got {:?}", result),
+    }
+}
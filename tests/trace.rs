@@ -0,0 +1,107 @@
+#![allow(clippy::needless_return)]
+
+// Trace lines in the nestest.log layout, the PC filter and the ring buffer.
+extern crate nese;
+
+use nese::cpu::{InstructionTrace, Registers};
+use nese::trace::{TraceEntry, Tracer};
+
+use std::cell::RefCell;
+use std::io;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+const NESTEST_ROM: &str = "test-roms/nestest/nestest.nes";
+
+// A writer the test can still read from once the tracer owns it.
+#[derive(Clone)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(data);
+        return Ok(data.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+impl SharedOutput {
+    fn lines(&self) -> Vec<String> {
+        return String::from_utf8(self.0.borrow().clone()).unwrap().lines().map(|line| line.to_string()).collect();
+    }
+}
+
+fn entry(pc: u16, bytes: [u8; 3], size: u16, cycles: u64, scanline: u16, dot: u16) -> TraceEntry {
+    return TraceEntry{
+        instruction: InstructionTrace{
+            registers: Registers{pc, a: 0x00, x: 0x00, y: 0x00, p: 0x24, sp: 0xFD},
+            bytes,
+            size,
+            cycles,
+        },
+        scanline,
+        dot,
+    };
+}
+
+#[test]
+fn entries_are_formatted_like_nestest_log() {
+    assert_eq!(entry(0xC000, [0x4C, 0xF5, 0xC5], 3, 7, 0, 21).to_string(),
+        "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7");
+    assert_eq!(entry(0xC5F5, [0xA2, 0x00, 0x00], 2, 10, 0, 30).to_string(),
+        "C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10");
+    assert_eq!(entry(0xC72D, [0xEA, 0x00, 0x00], 1, 14575, 240, 330).to_string(),
+        "C72D  EA        NOP                             A:00 X:00 Y:00 P:24 SP:FD PPU:240,330 CYC:14575");
+}
+
+#[test]
+fn ring_buffer_keeps_the_last_entries_oldest_first() {
+    let mut tracer = Tracer::new(None, 3);
+    for i in 0..5 {
+        tracer.record(entry(0x8000 + i, [0xEA, 0x00, 0x00], 1, i as u64, 0, 0));
+    }
+    let mut dump = Vec::new();
+    tracer.dump_ring_buffer(&mut dump).unwrap();
+    let dump = String::from_utf8(dump).unwrap();
+    let pcs: Vec<&str> = dump.lines().map(|line| &line[..4]).collect();
+    assert_eq!(pcs, vec!["8002", "8003", "8004"]);
+
+    // A size of 0 keeps nothing.
+    let mut tracer = Tracer::new(None, 0);
+    tracer.record(entry(0x8000, [0xEA, 0x00, 0x00], 1, 0, 0, 0));
+    let mut dump = Vec::new();
+    tracer.dump_ring_buffer(&mut dump).unwrap();
+    assert!(dump.is_empty());
+}
+
+#[test]
+fn only_instructions_in_the_pc_range_are_traced() {
+    let output = SharedOutput(Rc::new(RefCell::new(Vec::new())));
+    let mut tracer = Tracer::new(Some(Box::new(output.clone())), 0);
+    tracer.set_pc_range(0xC000, 0xC0FF);
+    for &pc in [0xBFFF, 0xC000, 0xC0FF, 0xC100].iter() {
+        tracer.record(entry(pc, [0xEA, 0x00, 0x00], 1, 0, 0, 0));
+    }
+    let pcs: Vec<String> = output.lines().iter().map(|line| line[..4].to_string()).collect();
+    assert_eq!(pcs, vec!["C000", "C0FF"]);
+}
+
+#[test]
+fn console_traces_the_instructions_it_runs() {
+    let output = SharedOutput(Rc::new(RefCell::new(Vec::new())));
+    let mut console = nese::Nes::new(NESTEST_ROM.to_string()).unwrap();
+    console.cpu().set_pc(0xC000);
+    console.set_tracer(Some(Tracer::new(Some(Box::new(output.clone())), 0)));
+    for _ in 0..3 {
+        console.step();
+    }
+    // The start of nestest.log, up to where the PPU position and cycle count are measured from.
+    let lines = output.lines();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:"));
+    assert!(lines[1].starts_with("C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:"));
+    assert!(lines[2].starts_with("C5F7  86 00     STX $00                         A:00 X:00 Y:00 P:26 SP:FD PPU:"));
+}
@@ -0,0 +1,127 @@
+#![allow(clippy::needless_return)]
+
+// The unstable opcodes, checked against the 2A03 behavior described at
+// https://wiki.nesdev.com/w/index.php/Programming_with_unofficial_opcodes
+extern crate nese;
+
+mod common;
+
+use common::{cpu_with_program, set_axy};
+
+const NEGATIVE_FLAG: u8 = 0x80;
+const ZERO_FLAG: u8 = 0x02;
+
+#[test]
+fn shx_stores_x_and_high_byte_plus_one() {
+    // SHX $0200,Y
+    let mut cpu = cpu_with_program(&[0x9E, 0x00, 0x02]);
+    set_axy(&mut cpu, 0x00, 0xFF, 0x01);
+    cpu.step();
+    assert_eq!(cpu.memory().writes, vec![(0x0201, 0x03)]);
+}
+
+#[test]
+fn shx_page_cross_replaces_high_byte_of_address() {
+    // SHX $02FF,Y crosses into $0300. The stored value also becomes the high byte of the address.
+    let mut cpu = cpu_with_program(&[0x9E, 0xFF, 0x02]);
+    set_axy(&mut cpu, 0x00, 0x05, 0x01);
+    cpu.step();
+    assert_eq!(cpu.memory().writes, vec![(0x0100, 0x01)]);
+}
+
+#[test]
+fn shy_stores_y_and_high_byte_plus_one() {
+    // SHY $0200,X
+    let mut cpu = cpu_with_program(&[0x9C, 0x00, 0x02]);
+    set_axy(&mut cpu, 0x00, 0x01, 0xFF);
+    cpu.step();
+    assert_eq!(cpu.memory().writes, vec![(0x0201, 0x03)]);
+}
+
+#[test]
+fn shy_page_cross_replaces_high_byte_of_address() {
+    // SHY $02FF,X
+    let mut cpu = cpu_with_program(&[0x9C, 0xFF, 0x02]);
+    set_axy(&mut cpu, 0x00, 0x01, 0x05);
+    cpu.step();
+    assert_eq!(cpu.memory().writes, vec![(0x0100, 0x01)]);
+}
+
+#[test]
+fn sha_absolute_y_stores_a_and_x_and_high_byte_plus_one() {
+    // SHA $0200,Y
+    let mut cpu = cpu_with_program(&[0x9F, 0x00, 0x02]);
+    set_axy(&mut cpu, 0xF7, 0x7F, 0x01);
+    cpu.step();
+    assert_eq!(cpu.memory().writes, vec![(0x0201, 0x03)]);
+}
+
+#[test]
+fn sha_indirect_y_stores_a_and_x_and_high_byte_plus_one() {
+    // SHA ($10),Y with $10 pointing at $0600
+    let mut cpu = cpu_with_program(&[0x93, 0x10]);
+    cpu.memory().ram[0x10] = 0x00;
+    cpu.memory().ram[0x11] = 0x06;
+    set_axy(&mut cpu, 0xFF, 0x0F, 0x02);
+    cpu.step();
+    assert_eq!(cpu.memory().writes, vec![(0x0602, 0x07)]);
+}
+
+#[test]
+fn tas_sets_stack_pointer_and_stores_it_and_high_byte_plus_one() {
+    // TAS $0200,Y
+    let mut cpu = cpu_with_program(&[0x9B, 0x00, 0x02]);
+    set_axy(&mut cpu, 0xF3, 0x3F, 0x01);
+    cpu.step();
+    assert_eq!(cpu.get_registers().sp, 0x33);
+    assert_eq!(cpu.memory().writes, vec![(0x0201, 0x03)]);
+}
+
+#[test]
+fn xaa_ands_x_with_the_immediate_value() {
+    // XAA #$3C. The 2A03's magic constant is 0xFF, so A's old value doesn't matter.
+    let mut cpu = cpu_with_program(&[0x8B, 0x3C, 0x8B, 0x0F]);
+    set_axy(&mut cpu, 0x00, 0xF0, 0x00);
+    cpu.step();
+    let registers = cpu.get_registers();
+    assert_eq!(registers.a, 0x30);
+    assert_eq!(registers.x, 0xF0);
+    assert_eq!(registers.p & (NEGATIVE_FLAG | ZERO_FLAG), 0);
+
+    // XAA #$0F
+    cpu.step();
+    let registers = cpu.get_registers();
+    assert_eq!(registers.a, 0x00);
+    assert_eq!(registers.p & (NEGATIVE_FLAG | ZERO_FLAG), ZERO_FLAG);
+}
+
+#[test]
+fn lax_immediate_loads_a_and_x() {
+    // LAX #$84. With the magic constant 0xFF it loads the immediate value into both.
+    let mut cpu = cpu_with_program(&[0xAB, 0x84]);
+    set_axy(&mut cpu, 0x12, 0x34, 0x00);
+    cpu.step();
+    let registers = cpu.get_registers();
+    assert_eq!(registers.a, 0x84);
+    assert_eq!(registers.x, 0x84);
+    assert_eq!(registers.p & (NEGATIVE_FLAG | ZERO_FLAG), NEGATIVE_FLAG);
+}
+
+#[test]
+fn stack_wraps_after_tas_empties_it() {
+    // TAS $0200,Y leaves SP at 0, then PHA pushes A to $0100 and wraps SP to $FF.
+    let mut cpu = cpu_with_program(&[0x9B, 0x00, 0x02, 0x48, 0x68]);
+    set_axy(&mut cpu, 0x0F, 0xF0, 0x00);
+    cpu.step();
+    assert_eq!(cpu.get_registers().sp, 0x00);
+    cpu.step();
+    assert_eq!(cpu.get_registers().sp, 0xFF);
+    assert_eq!(cpu.memory().writes.last(), Some(&(0x0100, 0x0F)));
+
+    // PLA wraps it back to 0 and reads what was pushed.
+    cpu.memory().ram[0x0100] = 0x5A;
+    cpu.step();
+    let registers = cpu.get_registers();
+    assert_eq!(registers.sp, 0x00);
+    assert_eq!(registers.a, 0x5A);
+}